@@ -0,0 +1,1043 @@
+//! Assembler for the mnemonic syntax the emulator logs and displays, e.g.
+//! `LD V0, 0x12`, `DRW V0, V1, 5` or `JP loop`.
+//!
+//! A line holds an optional `label:`, then an instruction or directive, then an
+//! optional `; comment`. Directives:
+//!
+//! - `db 1, 0x2F, "text"` - bytes and strings
+//! - `dw 0x1234, label` - big-endian words
+//! - `org 0x300` - continue at a later address, padding with zeroes
+//! - `include "file.asm"` - assemble another file in place, relative to this one
+//! - `NAME equ 42` - define a constant
+//!
+//! Operands are expressions over numbers (`42`, `0x2A`, `0b101010`, `'*'`), labels,
+//! constants and `$` (the address of the current line), with the operators
+//! `( ) ~ * / % + - << >> & ^ |` in C precedence.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::Instruction::*;
+use crate::expr::{Env, Expr, ExprError, Syntax};
+
+/// Address programs are loaded at.
+pub const ORIGIN: u16 = 0x200;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// Deepest chain of constants defined in terms of each other before giving up on a cycle.
+const MAX_CONSTANT_DEPTH: usize = 32;
+
+/// Words that name registers and operands, so cannot be used as symbols.
+const RESERVED: [&str; 11] = [
+    "I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "EQU", "$",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    /// 1-based column the problem starts at.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// One source line and the bytes it produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// The result of assembling a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// Image to load at `ORIGIN`.
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+    /// Every source line in order, including those of included files.
+    pub lines: Vec<ListingLine>,
+}
+
+impl Assembly {
+    /// The source line that produced the byte at `addr`.
+    pub fn line_at(&self, addr: u16) -> Option<&ListingLine> {
+        self.lines.iter().find(|line| {
+            (line.address..line.address.saturating_add(line.bytes.len() as u16)).contains(&addr)
+        })
+    }
+
+    /// Listing text: the address and bytes of every source line next to it,
+    /// with a comment line each time the source switches files.
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        let mut file = None;
+        for line in &self.lines {
+            if file != Some(&line.file) {
+                out += &format!("; {}\n", line.file);
+                file = Some(&line.file);
+            }
+            let mut rows = line.bytes.chunks(4);
+            let first = rows.next().unwrap_or(&[]);
+            out += &format!(
+                "{:04X}  {:<12}{:>5}  {}\n",
+                line.address,
+                hex(first),
+                line.line,
+                line.text
+            );
+            for (i, row) in rows.enumerate() {
+                out += &format!(
+                    "{:04X}  {}\n",
+                    line.address as usize + 4 * (i + 1),
+                    hex(row)
+                );
+            }
+        }
+        out
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    hex.join(" ")
+}
+
+/// Assemble source text. Includes are looked up relative to the working directory.
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AsmError>> {
+    let mut assembler = Assembler::new();
+    assembler.read("<source>", source, Path::new(""), 0);
+    assembler.finish()
+}
+
+/// Assemble a single line to go at `address`, for patching a loaded program.
+/// `symbols` gives the addresses of labels the line may refer to.
+pub fn assemble_at(
+    line: &str,
+    address: u16,
+    symbols: &BTreeMap<&str, u16>,
+) -> Result<Vec<u8>, AsmError> {
+    if address < ORIGIN {
+        return Err(AsmError {
+            file: "<line>".to_string(),
+            line: 1,
+            column: 1,
+            message: format!("cannot assemble below 0x{:X}", ORIGIN),
+        });
+    }
+    let mut source = String::new();
+    for (name, value) in symbols {
+        source += &format!("{} equ 0x{:X}\n", name, value);
+    }
+    source += &format!("org 0x{:X}\n", address);
+    let header = source.lines().count();
+    let mut assembler = Assembler::new();
+    assembler.read("<line>", &(source + line), Path::new(""), 0);
+    match assembler.finish() {
+        Ok(assembly) => Ok(assembly
+            .rom
+            .get((address - ORIGIN) as usize..)
+            .unwrap_or_default()
+            .to_vec()),
+        Err(mut errors) => {
+            let mut error = errors.remove(0);
+            error.line = error.line.saturating_sub(header);
+            Err(error)
+        }
+    }
+}
+
+/// Assemble a file. Includes are looked up relative to the file including them.
+pub fn assemble_file(path: &Path) -> Result<Assembly, Vec<AsmError>> {
+    let name = path.display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|e| {
+        vec![AsmError {
+            file: name.clone(),
+            line: 0,
+            column: 0,
+            message: e.to_string(),
+        }]
+    })?;
+    let mut assembler = Assembler::new();
+    assembler.read(
+        &name,
+        &source,
+        path.parent().unwrap_or_else(|| Path::new("")),
+        0,
+    );
+    assembler.finish()
+}
+
+/// A comma-separated piece of a statement and where it starts.
+#[derive(Debug, Clone)]
+struct Operand {
+    text: String,
+    column: usize,
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        column: usize,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<Operand>),
+    Words(Vec<Operand>),
+    /// Read in the first pass, right after the line itself.
+    Include(PathBuf),
+}
+
+#[derive(Debug)]
+struct Item {
+    file: usize,
+    line: usize,
+    text: String,
+    address: u16,
+    statement: Option<Statement>,
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum Symbol {
+    Label(u16),
+    Constant {
+        expr: Operand,
+        address: u16,
+        file: usize,
+        line: usize,
+    },
+}
+
+/// An error without its file and line yet: the column and the message.
+type Fail = (usize, String);
+
+struct Assembler {
+    files: Vec<String>,
+    symbols: BTreeMap<String, Symbol>,
+    items: Vec<Item>,
+    errors: Vec<AsmError>,
+    /// Address of the next byte. Wider than an address so overflow can be reported.
+    address: u32,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            symbols: BTreeMap::new(),
+            items: Vec::new(),
+            errors: Vec::new(),
+            address: ORIGIN as u32,
+        }
+    }
+
+    fn error(&mut self, file: usize, line: usize, (column, message): Fail) {
+        self.errors.push(AsmError {
+            file: self.files[file].clone(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    /// First pass: split lines into statements, lay out addresses and define symbols.
+    fn read(&mut self, name: &str, source: &str, dir: &Path, depth: usize) {
+        let file = self.files.len();
+        self.files.push(name.to_string());
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let address = self.address.min(0xFFFF) as u16;
+            let statement = match self.read_line(file, line, text, address, dir, depth) {
+                Ok(statement) => statement,
+                Err(fail) => {
+                    self.error(file, line, fail);
+                    None
+                }
+            };
+            let size = statement.as_ref().map_or(Ok(0), size);
+            match size {
+                Ok(size) => self.address += size,
+                Err(fail) => self.error(file, line, fail),
+            }
+            if self.address > 0x10000 {
+                self.error(
+                    file,
+                    line,
+                    (1, "program does not fit in memory".to_string()),
+                );
+                self.address = 0x10000;
+            }
+            let (statement, include) = match statement {
+                Some(Statement::Include(path)) => (None, Some(path)),
+                statement => (statement, None),
+            };
+            self.items.push(Item {
+                file,
+                line,
+                text: text.to_string(),
+                address,
+                statement,
+                bytes: Vec::new(),
+            });
+            if let Some(path) = include {
+                self.include(file, line, &path, depth + 1);
+            }
+        }
+    }
+
+    fn include(&mut self, file: usize, line: usize, path: &Path, depth: usize) {
+        match std::fs::read_to_string(path) {
+            Ok(source) => {
+                let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                self.read(&path.display().to_string(), &source, dir, depth);
+            }
+            Err(e) => {
+                let message = format!("cannot include {}: {}", path.display(), e);
+                self.error(file, line, (1, message));
+            }
+        }
+    }
+
+    fn read_line(
+        &mut self,
+        file: usize,
+        line: usize,
+        text: &str,
+        address: u16,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<Option<Statement>, Fail> {
+        let code = strip_comment(text);
+        let mut rest = code.trim_start();
+        let mut column = 1 + code.len() - rest.len();
+
+        let word = identifier(rest);
+        if !word.is_empty() && rest[word.len()..].starts_with(':') {
+            self.define(word, column, Symbol::Label(address))?;
+            let after = &rest[word.len() + 1..];
+            rest = after.trim_start();
+            column += word.len() + 1 + after.len() - rest.len();
+        }
+        let rest = rest.trim_end();
+        if rest.is_empty() {
+            return Ok(None);
+        }
+
+        let word_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (word, after) = rest.split_at(word_len);
+        let args = after.trim_start();
+        let args_column = column + word_len + after.len() - args.len();
+
+        let next = args.split_whitespace().next().unwrap_or("");
+        if next.eq_ignore_ascii_case("equ") {
+            let expr = args[3..].trim_start();
+            let expr = Operand {
+                text: expr.to_string(),
+                column: args_column + args.len() - expr.len(),
+            };
+            if expr.text.is_empty() {
+                return Err((expr.column, "equ needs a value".to_string()));
+            }
+            let constant = Symbol::Constant {
+                expr,
+                address,
+                file,
+                line,
+            };
+            self.define(word, column, constant)?;
+            return Ok(None);
+        }
+
+        let operands = split_operands(args, args_column)?;
+        match word.to_ascii_lowercase().as_str() {
+            "db" => Ok(Some(Statement::Bytes(operands))),
+            "dw" => Ok(Some(Statement::Words(operands))),
+            "org" => {
+                let target = match operands.as_slice() {
+                    [target] => target,
+                    _ => return Err((column, "org needs one address".to_string())),
+                };
+                let value = self.eval(&target.text, target.column, address, 0)?;
+                if value < self.address as i64 || value > 0xFFFF {
+                    let message = format!(
+                        "org 0x{:X} is before the current address 0x{:X} or outside memory",
+                        value, self.address
+                    );
+                    return Err((target.column, message));
+                }
+                self.address = value as u32;
+                Ok(None)
+            }
+            "include" => {
+                let path = match operands.as_slice() {
+                    [path] if path.text.starts_with('"') => path,
+                    _ => return Err((column, "include needs a quoted file name".to_string())),
+                };
+                let name = String::from_utf8(parse_string(&path.text, path.column)?)
+                    .map_err(|_| (path.column, "file name is not UTF-8".to_string()))?;
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err((path.column, "includes are nested too deeply".to_string()));
+                }
+                Ok(Some(Statement::Include(dir.join(&name))))
+            }
+            _ => Ok(Some(Statement::Instruction {
+                mnemonic: word.to_ascii_uppercase(),
+                column,
+                operands,
+            })),
+        }
+    }
+
+    fn define(&mut self, name: &str, column: usize, symbol: Symbol) -> Result<(), Fail> {
+        if is_reserved(name) {
+            return Err((
+                column,
+                format!("'{}' is reserved and cannot be a symbol", name),
+            ));
+        }
+        if let Some(existing) = self.symbols.get(name) {
+            let message = match existing {
+                Symbol::Constant { file, line, .. } => {
+                    format!(
+                        "'{}' is already defined at {}:{}",
+                        name, self.files[*file], line
+                    )
+                }
+                Symbol::Label(addr) => format!("'{}' is already a label at 0x{:03X}", name, addr),
+            };
+            return Err((column, message));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// Second pass: encode every statement now that all symbols are known.
+    fn finish(mut self) -> Result<Assembly, Vec<AsmError>> {
+        let mut items = std::mem::take(&mut self.items);
+        for item in items.iter_mut() {
+            if let Some(statement) = &item.statement {
+                match self.encode(statement, item.address) {
+                    Ok(bytes) => item.bytes = bytes,
+                    Err(fail) => self.error(item.file, item.line, fail),
+                }
+            }
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let end = items
+            .iter()
+            .map(|item| item.address as usize + item.bytes.len())
+            .max()
+            .unwrap_or(ORIGIN as usize);
+        let mut rom = vec![0; end.saturating_sub(ORIGIN as usize)];
+        for item in &items {
+            let start = item.address as usize - ORIGIN as usize;
+            rom[start..start + item.bytes.len()].copy_from_slice(&item.bytes);
+        }
+        let labels = self
+            .symbols
+            .iter()
+            .filter_map(|(name, symbol)| match symbol {
+                Symbol::Label(addr) => Some((name.clone(), *addr)),
+                Symbol::Constant { .. } => None,
+            })
+            .collect();
+        let files = self.files;
+        let lines = items
+            .into_iter()
+            .map(|item| ListingLine {
+                address: item.address,
+                bytes: item.bytes,
+                file: files[item.file].clone(),
+                line: item.line,
+                text: item.text,
+            })
+            .collect();
+        Ok(Assembly { rom, labels, lines })
+    }
+
+    fn encode(&self, statement: &Statement, here: u16) -> Result<Vec<u8>, Fail> {
+        match statement {
+            Statement::Bytes(operands) => {
+                let mut bytes = Vec::new();
+                for operand in operands {
+                    if operand.text.starts_with('"') {
+                        bytes.extend(parse_string(&operand.text, operand.column)?);
+                    } else {
+                        let value = self.eval(&operand.text, operand.column, here, 0)?;
+                        bytes.push(fit(value, 8, true, operand.column)? as u8);
+                    }
+                }
+                Ok(bytes)
+            }
+            Statement::Words(operands) => {
+                let mut bytes = Vec::new();
+                for operand in operands {
+                    let value = self.eval(&operand.text, operand.column, here, 0)?;
+                    bytes.extend_from_slice(&fit(value, 16, true, operand.column)?.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            Statement::Instruction {
+                mnemonic,
+                column,
+                operands,
+            } => self.encode_instruction(mnemonic, *column, operands, here),
+            Statement::Include(_) => Ok(Vec::new()),
+        }
+    }
+
+    fn encode_instruction(
+        &self,
+        mnemonic: &str,
+        column: usize,
+        operands: &[Operand],
+        here: u16,
+    ) -> Result<Vec<u8>, Fail> {
+        let mut args = Vec::new();
+        for operand in operands {
+            args.push(self.arg(operand, here)?);
+        }
+        let byte = |value: i64, column| fit(value, 8, true, column).map(|v| v as u8);
+        let addr = |value: i64, column| fit(value, 12, false, column);
+        let nibble = |value: i64, column| fit(value, 4, false, column).map(|v| v as u8);
+
+        use Arg::*;
+        let instruction = match (mnemonic, args.as_slice()) {
+            ("CLS", []) => Cls,
+            ("RET", []) => Ret,
+            ("SCR", []) => Scr,
+            ("SCL", []) => Scl,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Low,
+            ("HIGH", []) => High,
+            ("AUDIO", []) => Audio,
+            ("SCD", [Value(n, c)]) => Scd(nibble(*n, *c)?),
+            ("SCU", [Value(n, c)]) => Scu(nibble(*n, *c)?),
+            ("PLANE", [Value(n, c)]) => Plane(nibble(*n, *c)?),
+            ("SYS", [Value(a, c)]) => Sys(addr(*a, *c)?),
+            ("JP", [Value(a, c)]) => Jp(addr(*a, *c)?),
+            ("JP", [Reg(0), Value(a, c)]) => JpV0(addr(*a, *c)?),
+            ("CALL", [Value(a, c)]) => Call(addr(*a, *c)?),
+            ("SE", [Reg(x), Value(b, c)]) => SeByte(*x, byte(*b, *c)?),
+            ("SE", [Reg(x), Reg(y)]) => SeReg(*x, *y),
+            ("SNE", [Reg(x), Value(b, c)]) => SneByte(*x, byte(*b, *c)?),
+            ("SNE", [Reg(x), Reg(y)]) => SneReg(*x, *y),
+            ("LD", [Reg(x), Value(b, c)]) => LdByte(*x, byte(*b, *c)?),
+            ("LD", [Reg(x), Reg(y)]) => LdReg(*x, *y),
+            ("LD", [I, Value(a, c)]) => LdI(addr(*a, *c)?),
+            ("LD", [I, Long(None)]) => LdLongI,
+            ("LD", [I, Long(Some((a, c)))]) => {
+                let mut bytes = LdLongI.encode().to_vec();
+                bytes.extend_from_slice(&fit(*a, 16, false, *c)?.to_be_bytes());
+                return Ok(bytes);
+            }
+            ("LD", [Reg(x), Dt]) => LdVxDt(*x),
+            ("LD", [Reg(x), K]) => LdVxK(*x),
+            ("LD", [Dt, Reg(x)]) => LdDtVx(*x),
+            ("LD", [St, Reg(x)]) => LdStVx(*x),
+            ("LD", [F, Reg(x)]) => LdF(*x),
+            ("LD", [Hf, Reg(x)]) => LdHf(*x),
+            ("LD", [B, Reg(x)]) => LdB(*x),
+            ("LD", [IndirectI, Reg(x)]) => LdIVx(*x),
+            ("LD", [Reg(x), IndirectI]) => LdVxI(*x),
+            ("LD", [IndirectI, Range(x, y)]) => SaveRange(*x, *y),
+            ("LD", [Range(x, y), IndirectI]) => LoadRange(*x, *y),
+            ("LD", [R, Reg(x)]) => LdRVx(*x),
+            ("LD", [Reg(x), R]) => LdVxR(*x),
+            ("ADD", [Reg(x), Value(b, c)]) => AddByte(*x, byte(*b, *c)?),
+            ("ADD", [Reg(x), Reg(y)]) => AddReg(*x, *y),
+            ("ADD", [I, Reg(x)]) => AddI(*x),
+            ("OR", [Reg(x), Reg(y)]) => Or(*x, *y),
+            ("AND", [Reg(x), Reg(y)]) => And(*x, *y),
+            ("XOR", [Reg(x), Reg(y)]) => Xor(*x, *y),
+            ("SUB", [Reg(x), Reg(y)]) => Sub(*x, *y),
+            ("SUBN", [Reg(x), Reg(y)]) => Subn(*x, *y),
+            ("SHR", [Reg(x)]) => Shr(*x, *x),
+            ("SHR", [Reg(x), Reg(y)]) => Shr(*x, *y),
+            ("SHL", [Reg(x)]) => Shl(*x, *x),
+            ("SHL", [Reg(x), Reg(y)]) => Shl(*x, *y),
+            ("RND", [Reg(x), Value(b, c)]) => Rnd(*x, byte(*b, *c)?),
+            ("DRW", [Reg(x), Reg(y), Value(n, c)]) => Drw(*x, *y, nibble(*n, *c)?),
+            ("SKP", [Reg(x)]) => Skp(*x),
+            ("SKNP", [Reg(x)]) => Sknp(*x),
+            ("PITCH", [Reg(x)]) => Pitch(*x),
+            _ if MNEMONICS.contains(&mnemonic) => {
+                return Err((column, format!("invalid operands for {}", mnemonic)));
+            }
+            _ => return Err((column, format!("unknown instruction '{}'", mnemonic))),
+        };
+        Ok(instruction.encode().to_vec())
+    }
+
+    fn arg(&self, operand: &Operand, here: u16) -> Result<Arg, Fail> {
+        let text = operand.text.as_str();
+        let upper = text.to_ascii_uppercase();
+        if let Some(x) = register(&upper) {
+            return Ok(Arg::Reg(x));
+        }
+        if let Some(dash) = upper.find('-') {
+            let (x, y) = (upper[..dash].trim(), upper[dash + 1..].trim());
+            if let (Some(x), Some(y)) = (register(x), register(y)) {
+                return Ok(Arg::Range(x, y));
+            }
+        }
+        let arg = match upper.as_str() {
+            "I" => Arg::I,
+            "[I]" => Arg::IndirectI,
+            "DT" => Arg::Dt,
+            "ST" => Arg::St,
+            "K" => Arg::K,
+            "F" => Arg::F,
+            "HF" => Arg::Hf,
+            "B" => Arg::B,
+            "R" => Arg::R,
+            "LONG" => Arg::Long(None),
+            _ if is_long(&upper) => {
+                let expr = text[4..].trim_start();
+                let column = operand.column + text.len() - expr.len();
+                Arg::Long(Some((self.eval(expr, column, here, 0)?, column)))
+            }
+            _ => Arg::Value(self.eval(text, operand.column, here, 0)?, operand.column),
+        };
+        Ok(arg)
+    }
+
+    fn eval(&self, text: &str, column: usize, here: u16, depth: usize) -> Result<i64, Fail> {
+        let syntax = Syntax {
+            comparisons: false,
+            memory: false,
+        };
+        let scope = Scope {
+            assembler: self,
+            here,
+            depth,
+        };
+        Expr::parse(text, column, syntax, |word| {
+            Ok(match word {
+                "$" => Name::Here,
+                _ => Name::Symbol(word.to_string()),
+            })
+        })
+        .and_then(|expr| expr.eval(&scope))
+        .map_err(|e| (e.column, e.message))
+    }
+
+    fn lookup(&self, name: &str, column: usize, depth: usize) -> Result<i64, Fail> {
+        match self.symbols.get(name) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Constant {
+                expr,
+                address,
+                file,
+                line,
+            }) => {
+                if depth >= MAX_CONSTANT_DEPTH {
+                    return Err((column, format!("'{}' is defined in terms of itself", name)));
+                }
+                self.eval(&expr.text, expr.column, *address, depth + 1)
+                    .map_err(|(inner_column, message)| {
+                        if depth > 0 {
+                            return (inner_column, message);
+                        }
+                        let location = format!("{}:{}", self.files[*file], line);
+                        (column, format!("in '{}' ({}): {}", name, location, message))
+                    })
+            }
+            None => Err((column, format!("unknown symbol '{}'", name))),
+        }
+    }
+}
+
+/// Size of a statement in bytes, known before any symbol is.
+fn size(statement: &Statement) -> Result<u32, Fail> {
+    match statement {
+        Statement::Bytes(operands) => {
+            let mut size = 0;
+            for operand in operands {
+                size += if operand.text.starts_with('"') {
+                    parse_string(&operand.text, operand.column)?.len() as u32
+                } else {
+                    1
+                };
+            }
+            Ok(size)
+        }
+        Statement::Words(operands) => Ok(2 * operands.len() as u32),
+        Statement::Include(_) => Ok(0),
+        Statement::Instruction {
+            mnemonic, operands, ..
+        } => {
+            let long = mnemonic == "LD"
+                && operands.len() == 2
+                && is_long(&operands[1].text.to_ascii_uppercase());
+            Ok(if long { 4 } else { 2 })
+        }
+    }
+}
+
+const MNEMONICS: [&str; 30] = [
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SCD", "SCU", "PLANE", "SYS", "JP",
+    "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PITCH",
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    Reg(u8),
+    Range(u8, u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    /// `LONG`, with the address and its column when one follows.
+    Long(Option<(i64, usize)>),
+    /// A number and the column it came from.
+    Value(i64, usize),
+}
+
+fn register(text: &str) -> Option<u8> {
+    match text.as_bytes() {
+        [b'V', digit] => (*digit as char).to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+/// `LONG` followed by an address.
+fn is_long(upper: &str) -> bool {
+    upper.starts_with("LONG") && upper[4..].starts_with(char::is_whitespace)
+}
+
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    register(&upper).is_some() || RESERVED.contains(&upper.as_str())
+}
+
+/// Check that `value` fits in `bits`, as unsigned or, if `signed` is allowed, two's complement.
+fn fit(value: i64, bits: u32, signed: bool, column: usize) -> Result<u16, Fail> {
+    let max = (1i64 << bits) - 1;
+    let min = if signed { -(1i64 << (bits - 1)) } else { 0 };
+    if value < min || value > max {
+        return Err((column, format!("{} does not fit in {} bits", value, bits)));
+    }
+    Ok((value & max) as u16)
+}
+
+fn identifier(text: &str) -> &str {
+    let len = text
+        .char_indices()
+        .find(|&(i, c)| {
+            !(c.is_ascii_alphabetic() || c == '_' || c == '.' || (i > 0 && c.is_ascii_digit()))
+        })
+        .map_or(text.len(), |(i, _)| i);
+    &text[..len]
+}
+
+/// Everything before a `;` that is not inside quotes.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' => return &text[..i],
+            None => {}
+        }
+    }
+    text
+}
+
+/// Split at commas outside quotes and brackets, keeping the column each operand starts at.
+fn split_operands(text: &str, column: usize) -> Result<Vec<Operand>, Fail> {
+    let mut operands = Vec::new();
+    if text.trim().is_empty() {
+        return Ok(operands);
+    }
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut push = |start: usize, end: usize| {
+        let raw = &text[start..end];
+        let trimmed = raw.trim_start();
+        let column = column + start + raw.len() - trimmed.len();
+        if trimmed.trim_end().is_empty() {
+            return Err((column, "missing operand".to_string()));
+        }
+        operands.push(Operand {
+            text: trimmed.trim_end().to_string(),
+            column,
+        });
+        Ok(())
+    };
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth -= 1,
+                ',' if depth == 0 => {
+                    push(start, i)?;
+                    start = i + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+    push(start, text.len())?;
+    Ok(operands)
+}
+
+/// The bytes of a `"quoted"` string, with `\"`, `\\`, `\n` and `\0` escapes.
+fn parse_string(text: &str, column: usize) -> Result<Vec<u8>, Fail> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| (column, "unterminated string".to_string()))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('0') => '\0',
+                Some(c @ '"') | Some(c @ '\\') | Some(c @ '\'') => c,
+                _ => return Err((column, "unknown escape in string".to_string())),
+            }
+        } else {
+            c
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Ok(bytes)
+}
+
+/// A name in an operand expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Name {
+    /// `$`, the address of the current line.
+    Here,
+    Symbol(String),
+}
+
+/// The assembler's view of names, while evaluating an expression on the line at `here`.
+struct Scope<'a> {
+    assembler: &'a Assembler,
+    here: u16,
+    depth: usize,
+}
+
+impl Env<Name> for Scope<'_> {
+    fn value(&self, name: &Name, column: usize) -> Result<i64, ExprError> {
+        match name {
+            Name::Here => Ok(self.here as i64),
+            Name::Symbol(name) => self
+                .assembler
+                .lookup(name, column, self.depth)
+                .map_err(|(column, message)| ExprError { column, message }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{assemble, assemble_at, assemble_file, AsmError};
+    use crate::cpu::Instruction;
+
+    fn errors(source: &str) -> Vec<AsmError> {
+        assemble(source).expect_err("source should not assemble")
+    }
+
+    #[test]
+    fn test_display_syntax() {
+        for word in 0..=0xFFFFu16 {
+            let bytes = word.to_be_bytes();
+            if let Ok(instruction) = Instruction::decode(bytes) {
+                let text = instruction.to_string();
+                let rom = assemble(&text)
+                    .unwrap_or_else(|e| panic!("'{}' should assemble: {}", text, e[0]))
+                    .rom;
+                assert_eq!(rom, bytes, "'{}' should assemble to {:04X}", text, word);
+            }
+        }
+    }
+
+    #[test]
+    fn test_labels() {
+        let source = "
+            start:  LD V0, 0
+            loop:   ADD V0, 1
+                    SE V0, count
+                    JP loop     ; forward and backward
+                    CALL sub
+                    JP start
+            sub:    RET
+            count   equ 2 * 5
+        ";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(
+            assembly.rom,
+            [0x60, 0x00, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x02, 0x22, 0x0C, 0x12, 0x00, 0x00, 0xEE]
+        );
+        assert_eq!(assembly.labels["sub"], 0x20C);
+        assert!(
+            !assembly.labels.contains_key("count"),
+            "Constants are not labels"
+        );
+    }
+
+    #[test]
+    fn test_data() {
+        let source = "
+                    LD I, sprite
+                    DRW V0, V1, sprite_end - sprite
+                    JP $
+                    org 0x210
+            sprite: db 0b11110000, 0x90, -1, \"A;\"  ; strings may hold ';'
+            sprite_end:
+                    dw sprite, 0x1234
+                    LD I, LONG 0xABCD
+        ";
+        let rom = assemble(source).unwrap().rom;
+        assert_eq!(&rom[..6], &[0xA2, 0x10, 0xD0, 0x15, 0x12, 0x04]);
+        assert!(
+            rom[6..0x10].iter().all(|&b| b == 0),
+            "org should pad with zeroes"
+        );
+        assert_eq!(&rom[0x10..0x15], &[0xF0, 0x90, 0xFF, b'A', b';']);
+        assert_eq!(&rom[0x15..0x19], &[0x02, 0x10, 0x12, 0x34]);
+        assert_eq!(&rom[0x19..], &[0xF0, 0x00, 0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn test_expressions() {
+        let source = "
+            nine    equ (1 + 2) * 3         ; 9
+            high    equ nine << 4 | 0x0F    ; 0x9F
+            masked  equ ~0 & 0xFF - 'A'     ; 0xFF - 0x41
+            rem     equ 17 % 5 - -1         ; 3
+                    db nine, high, masked, rem, $ - 0x200 + 1
+        ";
+        assert_eq!(assemble(source).unwrap().rom, [9, 0x9F, 0xBE, 3, 1]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let e = errors("LD V0, 0x12\n  JP nowhere\n");
+        assert_eq!(e.len(), 1);
+        assert_eq!((e[0].line, e[0].column), (2, 6));
+        assert_eq!(e[0].to_string(), "<source>:2:6: unknown symbol 'nowhere'");
+
+        let e = errors("LD V0, 256\nFOO V1\nADD DT, V0\nx: CLS\nx: CLS\nDRW V0, V1, (1 + \n");
+        let found: Vec<_> = e.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(found, [(5, 1), (1, 8), (2, 1), (3, 1), (6, 17)]);
+        assert!(e[1].message.contains("does not fit"), "got {}", e[1]);
+        assert!(e[2].message.contains("unknown instruction"), "got {}", e[2]);
+        assert!(e[3].message.contains("invalid operands"), "got {}", e[3]);
+
+        let e = errors("x equ y\ny equ x\ndb x");
+        assert!(e[0].message.contains("in terms of itself"), "got {}", e[0]);
+        let e = errors("org 0x100");
+        assert!(
+            e[0].message.contains("before the current address"),
+            "got {}",
+            e[0]
+        );
+        let e = errors("V3: CLS");
+        assert!(e[0].message.contains("reserved"), "got {}", e[0]);
+        let e = errors("db 1 / 0\ndb (1 << 63) / -1\ndb (1 << 63) % -1");
+        let found: Vec<_> = e.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(found, [(1, 6), (2, 14), (3, 14)]);
+        assert!(e[1].message.contains("overflows"), "got {}", e[1]);
+    }
+
+    #[test]
+    fn test_assemble_at() {
+        let mut symbols = BTreeMap::new();
+        symbols.insert("sub_300", 0x300);
+        assert_eq!(
+            assemble_at("CALL sub_300", 0x240, &symbols),
+            Ok(vec![0x23, 0x00])
+        );
+        assert_eq!(
+            assemble_at("JP $", 0x246, &symbols),
+            Ok(vec![0x12, 0x46]),
+            "$ should be the patched address"
+        );
+        assert_eq!(assemble_at("", 0x240, &symbols), Ok(vec![]));
+
+        let e = assemble_at("JP nowhere", 0x240, &symbols).unwrap_err();
+        assert_eq!(
+            (e.line, e.column),
+            (1, 4),
+            "Errors should point into the line, not the generated header"
+        );
+        assert!(assemble_at("CLS", 0x100, &symbols).is_err());
+    }
+
+    #[test]
+    fn test_include_and_listing() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("main.asm"),
+            "CALL digit\nEXIT\ninclude \"lib/digit.asm\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/digit.asm"), "digit: LD F, V0\n  RET\n").unwrap();
+        let assembly = assemble_file(&dir.join("main.asm"));
+        let missing = assemble_file(&dir.join("missing.asm"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let assembly = assembly.unwrap();
+        assert_eq!(
+            assembly.rom,
+            [0x22, 0x04, 0x00, 0xFD, 0xF0, 0x29, 0x00, 0xEE]
+        );
+        let line = assembly.line_at(0x206).unwrap();
+        assert!(line.file.ends_with("digit.asm"));
+        assert_eq!(line.line, 2);
+
+        let listing = assembly.listing();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert!(lines[0].starts_with("; ") && lines[0].ends_with("main.asm"));
+        assert_eq!(lines[1], "0200  22 04           1  CALL digit");
+        assert!(lines[4].ends_with("digit.asm"));
+        assert_eq!(lines[5], "0204  F0 29           1  digit: LD F, V0");
+        assert!(missing.is_err());
+    }
+}
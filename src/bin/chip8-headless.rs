@@ -0,0 +1,318 @@
+//! Runs ROMs without a window or GPU, for CI and scripting.
+
+#![deny(clippy::all)]
+#![forbid(unsafe_code)]
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::exit;
+
+use chip8::asm;
+use chip8::conformance;
+use chip8::cpu::{self, Cpu, FaultPolicy, QuirkProfile, Random, RngAlgorithm, Trace};
+use chip8::disasm;
+use chip8::headless::{self, Options, StopCondition};
+use chip8::movie::Movie;
+use chip8::screenshot;
+use simple_logger::SimpleLogger;
+
+const USAGE: &str = "\
+Usage: chip8-headless run ROM [options]
+       chip8-headless asm SOURCE [-o ROM] [--listing FILE]
+       chip8-headless disasm ROM [--json] [--entry ADDR]... [-o FILE]
+       chip8-headless tracediff TRACE TRACE
+       chip8-headless selftest
+
+`asm` assembles SOURCE into ROM (default: SOURCE with a .ch8 extension), and
+writes a listing of addresses, bytes and source lines to FILE if asked to.
+Errors are reported as FILE:LINE:COLUMN and exit with 1.
+
+`disasm` traces the ROM's code from 0x200 and any extra hex --entry addresses,
+such as targets of computed jumps, and prints it as assembly that `asm` turns
+back into the same ROM, or as JSON with --json. Bytes that are not reached are
+shown as data, and those drawn as sprites as bit patterns.
+
+`tracediff` compares two traces written by `run --trace` and shows the first
+line where they differ. It exits with 0 if they match and 2 if they do not.
+
+`selftest` runs the built-in conformance suite under every quirk profile and
+exits with 1 if any case fails.
+
+Options of `run`:
+  --frames N          run at most N frames (default: the input's length, or 600)
+  --until-pc ADDR     stop before executing the instruction at hex address ADDR
+  --until-idle        stop once the program spins on a jump to itself,
+                      or waits for a key with no input left
+  --input FILE        feed the key events of a movie or input script
+  --quirks PROFILE    cosmac-vip, schip-legacy, schip-modern or xo-chip
+  --ips N             instructions per second (default 700)
+  --seed N            random number seed (default 0)
+  --fault-policy P    halt, warn or wrap (default halt)
+  --screen FILE       write the final screen to FILE, as PNG or PBM by extension
+  --dump FILE         write the final machine state to FILE as JSON
+  --trace FILE        write a line per executed instruction to FILE
+  --trace-range A-B   only trace instructions at hex addresses A to B
+  --verbose           log warnings from the emulator
+
+Exit codes of `run`:
+  0  finished, stopped on a condition, or the program exited
+  1  bad arguments or unreadable files
+  2  the CPU faulted
+  3  the input was recorded for another ROM, or its replay diverged
+  4  none of the --until conditions was met within the frame limit";
+
+/// Print an error and exit with the usage error code.
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("chip8-headless: {}", message);
+    exit(1)
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> String {
+    args.next()
+        .unwrap_or_else(|| fail(format!("{} needs a value", flag)))
+}
+
+fn number<T: std::str::FromStr>(text: &str, flag: &str) -> T {
+    text.parse()
+        .unwrap_or_else(|_| fail(format!("bad value for {}: {}", flag, text)))
+}
+
+/// A hex address, with or without `0x`.
+fn address(text: &str) -> u16 {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16)
+        .unwrap_or_else(|_| fail(format!("bad address: {}", text)))
+}
+
+fn run(mut args: impl Iterator<Item = String>) {
+    let mut rom_path = None;
+    let mut options = Options::default();
+    let mut cpu = Cpu::new();
+    cpu.rng = Random::new(RngAlgorithm::Xorshift, 0);
+    let mut screen: Option<PathBuf> = None;
+    let mut dump: Option<PathBuf> = None;
+    let mut verbose = false;
+    let mut trace: Option<String> = None;
+    let mut trace_range = (0, 0xFFFF);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => options.frames = Some(number(&value(&mut args, &arg), &arg)),
+            "--until-pc" => {
+                let addr = address(&value(&mut args, &arg));
+                options.until.push(StopCondition::Pc(addr));
+            }
+            "--until-idle" => options.until.push(StopCondition::Idle),
+            "--input" => {
+                let path = value(&mut args, &arg);
+                let movie = Movie::load(&path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+                options.movie = Some(movie);
+            }
+            "--quirks" => {
+                let profile = match value(&mut args, &arg).as_str() {
+                    "cosmac-vip" => QuirkProfile::CosmacVip,
+                    "schip-legacy" => QuirkProfile::SchipLegacy,
+                    "schip-modern" => QuirkProfile::SchipModern,
+                    "xo-chip" => QuirkProfile::XoChip,
+                    other => fail(format!("unknown quirk profile: {}", other)),
+                };
+                cpu.quirks = profile.quirks();
+            }
+            "--ips" => cpu.instructions_per_second = number(&value(&mut args, &arg), &arg),
+            "--seed" => cpu.rng.reseed(number(&value(&mut args, &arg), &arg)),
+            "--fault-policy" => {
+                cpu.fault_policy = match value(&mut args, &arg).as_str() {
+                    "halt" => FaultPolicy::Halt,
+                    "warn" => FaultPolicy::Warn,
+                    "wrap" => FaultPolicy::Wrap,
+                    other => fail(format!("unknown fault policy: {}", other)),
+                }
+            }
+            "--screen" => screen = Some(value(&mut args, &arg).into()),
+            "--dump" => dump = Some(value(&mut args, &arg).into()),
+            "--trace" => trace = Some(value(&mut args, &arg)),
+            "--trace-range" => {
+                let range = value(&mut args, &arg);
+                let mut bounds = range.splitn(2, '-');
+                let start = address(bounds.next().unwrap());
+                let end = address(
+                    bounds
+                        .next()
+                        .unwrap_or_else(|| fail("--trace-range needs A-B")),
+                );
+                trace_range = (start, end);
+            }
+            "--verbose" => verbose = true,
+            flag if flag.starts_with("--") => {
+                fail(format!("unknown option: {}\n\n{}", flag, USAGE))
+            }
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => fail(USAGE),
+        }
+    }
+    if cpu.instructions_per_second == 0 {
+        fail("--ips must be at least 1");
+    }
+
+    let level = if verbose {
+        log::LevelFilter::Warn
+    } else {
+        log::LevelFilter::Off
+    };
+    SimpleLogger::new().with_level(level).init().unwrap();
+
+    let rom_path = rom_path.unwrap_or_else(|| fail(USAGE));
+    let rom = std::fs::read(&rom_path).unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+
+    if let Some(path) = trace {
+        let trace = Trace::create(&path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+        cpu.trace = Some(trace.range(trace_range.0, trace_range.1));
+    }
+
+    let outcome = headless::run(&mut cpu, &rom, options);
+    println!("{} after {} cycles", outcome, cpu.cycle());
+    // Finish the trace file, as `exit` skips destructors.
+    cpu.trace = None;
+
+    if let Some(path) = screen {
+        let image = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pbm") => screenshot::pbm(&cpu),
+            Some("png") => screenshot::png(&cpu),
+            _ => fail("--screen needs a .png or .pbm file name"),
+        };
+        std::fs::write(&path, image).unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)));
+    }
+    if let Some(path) = dump {
+        std::fs::write(&path, cpu.save_state().to_json())
+            .unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)));
+    }
+    exit(outcome.exit_code());
+}
+
+fn assemble(mut args: impl Iterator<Item = String>) {
+    let mut source: Option<PathBuf> = None;
+    let mut output: Option<PathBuf> = None;
+    let mut listing: Option<PathBuf> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(value(&mut args, &arg).into()),
+            "--listing" => listing = Some(value(&mut args, &arg).into()),
+            flag if flag.starts_with('-') => fail(format!("unknown option: {}\n\n{}", flag, USAGE)),
+            _ if source.is_none() => source = Some(arg.into()),
+            _ => fail(USAGE),
+        }
+    }
+    let source = source.unwrap_or_else(|| fail(USAGE));
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+    if output == source {
+        fail("the ROM would overwrite the source, pick another name with -o");
+    }
+
+    let assembly = asm::assemble_file(&source).unwrap_or_else(|errors| {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        exit(1)
+    });
+    std::fs::write(&output, &assembly.rom)
+        .unwrap_or_else(|e| fail(format!("{}: {}", output.display(), e)));
+    if let Some(path) = listing {
+        std::fs::write(&path, assembly.listing())
+            .unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)));
+    }
+    println!("{}: {} bytes", output.display(), assembly.rom.len());
+}
+
+fn disassemble(mut args: impl Iterator<Item = String>) {
+    let mut rom_path = None;
+    let mut output: Option<PathBuf> = None;
+    let mut json = false;
+    let mut entries = vec![asm::ORIGIN];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(value(&mut args, &arg).into()),
+            "--json" => json = true,
+            "--entry" => entries.push(address(&value(&mut args, &arg))),
+            flag if flag.starts_with('-') => fail(format!("unknown option: {}\n\n{}", flag, USAGE)),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => fail(USAGE),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| fail(USAGE));
+    let rom = std::fs::read(&rom_path).unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+
+    let disassembly = disasm::disassemble(&rom, asm::ORIGIN, &entries);
+    let text = if json {
+        disassembly.to_json()
+    } else {
+        disassembly.text()
+    };
+    match output {
+        Some(path) => std::fs::write(&path, text)
+            .unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e))),
+        None => print!("{}", text),
+    }
+}
+
+fn tracediff(mut args: impl Iterator<Item = String>) {
+    let (left, right) = match (args.next(), args.next(), args.next()) {
+        (Some(left), Some(right), None) => (left, right),
+        _ => fail(USAGE),
+    };
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .unwrap_or_else(|e| fail(format!("{}: {}", path, e)))
+    };
+    match cpu::first_divergence(open(&left), open(&right)) {
+        Ok(None) => println!("traces match"),
+        Ok(Some(divergence)) => {
+            println!("{}", divergence);
+            exit(2)
+        }
+        Err(e) => fail(e),
+    }
+}
+
+fn selftest() {
+    let mut failed = 0;
+    for case in conformance::CASES {
+        let failures: Vec<String> = QuirkProfile::ALL
+            .iter()
+            .filter_map(|&profile| {
+                case.run(profile)
+                    .err()
+                    .map(|e| format!("{}: {}", profile.name(), e))
+            })
+            .collect();
+        if failures.is_empty() {
+            println!("ok      {}", case.name);
+        } else {
+            failed += 1;
+            println!("FAILED  {}", case.name);
+            for failure in failures {
+                println!("          {}", failure);
+            }
+        }
+    }
+    let total = conformance::CASES.len();
+    println!(
+        "\n{} of {} cases passed under every quirk profile",
+        total - failed,
+        total
+    );
+    exit((failed > 0) as i32)
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("run") => run(args),
+        Some("asm") => assemble(args),
+        Some("disasm") => disassemble(args),
+        Some("tracediff") => tracediff(args),
+        Some("selftest") => selftest(),
+        Some("--help") | Some("-h") => println!("{}", USAGE),
+        _ => fail(USAGE),
+    }
+}
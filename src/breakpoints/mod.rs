@@ -0,0 +1,115 @@
+use chip8::cpu::{Breakpoint, Condition, Register, Trigger};
+
+mod ui;
+
+/// Text of a breakpoint being entered in the debugger window.
+#[derive(Debug, Clone, Default)]
+pub struct Draft {
+    /// Index into `Draft::KINDS`.
+    pub kind: usize,
+    /// Hex address, start of the range, or register name.
+    pub start: String,
+    /// Hex end of the range; the start alone if empty.
+    pub end: String,
+    pub condition: String,
+    pub pause_after: u16,
+    /// Why the last attempt to add the breakpoint failed.
+    pub error: Option<String>,
+}
+
+impl Draft {
+    pub const KINDS: [&'static str; 4] = ["PC", "Read", "Write", "Register"];
+
+    pub fn breakpoint(&self) -> Result<Breakpoint, String> {
+        let address = |text: &str| {
+            let text = text.trim();
+            u16::from_str_radix(text.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("bad address `{}`", text))
+        };
+        let range = || -> Result<(u16, u16), String> {
+            let start = address(&self.start)?;
+            let end = if self.end.trim().is_empty() {
+                start
+            } else {
+                address(&self.end)?
+            };
+            if end < start {
+                return Err("the range ends before it starts".to_string());
+            }
+            Ok((start, end))
+        };
+        let trigger = match self.kind {
+            0 => Trigger::Pc(address(&self.start)?),
+            1 => {
+                let (start, end) = range()?;
+                Trigger::Read { start, end }
+            }
+            2 => {
+                let (start, end) = range()?;
+                Trigger::Write { start, end }
+            }
+            _ => {
+                let name = self.start.trim().to_ascii_uppercase();
+                let register = match name.as_str() {
+                    "I" => Register::I,
+                    _ if name.len() == 2 && name.starts_with('V') => {
+                        let x = u8::from_str_radix(&name[1..], 16)
+                            .map_err(|_| format!("no such register `{}`", self.start.trim()))?;
+                        Register::V(x)
+                    }
+                    _ => return Err(format!("no such register `{}`", self.start.trim())),
+                };
+                Trigger::Register(register)
+            }
+        };
+        let mut breakpoint = Breakpoint::new(trigger);
+        if !self.condition.trim().is_empty() {
+            let condition =
+                Condition::parse(&self.condition).map_err(|e| format!("condition, {}", e))?;
+            breakpoint = breakpoint.with_condition(condition);
+        }
+        breakpoint.pause_after = self.pause_after as u64;
+        Ok(breakpoint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Draft;
+    use chip8::cpu::{Register, Trigger};
+
+    #[test]
+    fn test_draft() {
+        let draft = |kind, start: &str, end: &str, condition: &str| Draft {
+            kind,
+            start: start.to_string(),
+            end: end.to_string(),
+            condition: condition.to_string(),
+            ..Draft::default()
+        };
+        assert_eq!(
+            draft(0, "0x2A0", "", "").breakpoint().unwrap().trigger,
+            Trigger::Pc(0x2A0)
+        );
+        assert_eq!(
+            draft(2, "300", "30f", "").breakpoint().unwrap().trigger,
+            Trigger::Write {
+                start: 0x300,
+                end: 0x30F
+            }
+        );
+        assert_eq!(
+            draft(3, "vc", "", "").breakpoint().unwrap().trigger,
+            Trigger::Register(Register::V(0xC))
+        );
+        let conditional = draft(0, "200", "", "V0 == 1").breakpoint().unwrap();
+        assert_eq!(conditional.condition.unwrap().source(), "V0 == 1");
+
+        assert!(draft(1, "310", "300", "").breakpoint().is_err());
+        assert!(draft(3, "VG", "", "").breakpoint().is_err());
+        assert_eq!(
+            draft(0, "200", "", "V0 ==").breakpoint().unwrap_err(),
+            "condition, column 6: expected a value"
+        );
+    }
+}
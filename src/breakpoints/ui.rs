@@ -0,0 +1,69 @@
+use super::Draft;
+use chip8::cpu::Debugger;
+use egui::{DragValue, Ui};
+
+impl Draft {
+    /// List the breakpoints, with the form for adding more below them.
+    pub fn draw_ui(&mut self, ui: &mut Ui, debugger: &mut Debugger) {
+        let mut remove = None;
+        for (index, breakpoint) in debugger.breakpoints.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut breakpoint.enabled, breakpoint.trigger.to_string());
+                if let Some(condition) = &breakpoint.condition {
+                    ui.label(format!("if {}", condition));
+                }
+                if breakpoint.pause_after > 1 {
+                    ui.label(format!("from hit {}", breakpoint.pause_after));
+                }
+                ui.label(format!("{} hits", breakpoint.hits));
+                if ui.button("Remove").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = remove {
+            debugger.remove(index);
+        }
+
+        ui.separator();
+        let mut add = false;
+        ui.horizontal(|ui| {
+            for (kind, &name) in Draft::KINDS.iter().enumerate() {
+                ui.radio_value(&mut self.kind, kind, name);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label(match self.kind {
+                0 => "Address:",
+                1 | 2 => "From:",
+                _ => "Register:",
+            });
+            ui.text_edit_singleline(&mut self.start);
+            if self.kind == 1 || self.kind == 2 {
+                ui.label("to:");
+                ui.text_edit_singleline(&mut self.end);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Condition:");
+            ui.text_edit_singleline(&mut self.condition);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Pause from hit:");
+            ui.add(DragValue::u16(&mut self.pause_after));
+            add = ui.button("Add").clicked();
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error.as_str());
+        }
+        if add {
+            match self.breakpoint() {
+                Ok(breakpoint) => {
+                    self.error = None;
+                    debugger.breakpoints.push(breakpoint);
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! The debugger's disassembly window: which lines it shows, and editing them in place.
+
+use std::ops::Range;
+
+use chip8::disasm::{Disassembly, Line};
+use chip8::world::World;
+
+mod ui;
+
+/// Lines shown above and below the one at PC.
+pub const CONTEXT_LINES: usize = 12;
+/// Lines shown at once.
+pub const VISIBLE_LINES: usize = 2 * CONTEXT_LINES + 1;
+
+#[derive(Debug, Clone)]
+pub struct CodeView {
+    /// Keep the line at PC in the middle of the view as the program runs.
+    pub follow_pc: bool,
+    /// First line shown.
+    pub top: u32,
+    /// Address of the line being edited.
+    pub editing: Option<u16>,
+    /// Source of the line being edited.
+    pub edit: String,
+    /// Why the last edit could not be assembled.
+    pub error: Option<String>,
+}
+
+impl Default for CodeView {
+    fn default() -> Self {
+        Self {
+            follow_pc: true,
+            top: 0,
+            editing: None,
+            edit: String::new(),
+            error: None,
+        }
+    }
+}
+
+impl CodeView {
+    /// Last line that can be scrolled to the top.
+    pub fn max_top(disassembly: &Disassembly) -> u32 {
+        disassembly.lines.len().saturating_sub(VISIBLE_LINES) as u32
+    }
+
+    /// Indices of the lines to show, scrolling to PC first if following it.
+    pub fn visible(&mut self, disassembly: &Disassembly, pc: u16) -> Range<usize> {
+        if self.follow_pc {
+            if let Some(line) = disassembly.line_at(pc) {
+                self.top = line.saturating_sub(CONTEXT_LINES) as u32;
+            }
+        }
+        self.top = self.top.min(Self::max_top(disassembly));
+        let start = self.top as usize;
+        start..(start + VISIBLE_LINES).min(disassembly.lines.len())
+    }
+
+    pub fn start_edit(&mut self, line: &Line) {
+        self.editing = Some(line.address);
+        self.edit = line.text.clone();
+        self.error = None;
+    }
+
+    pub fn cancel_edit(&mut self) {
+        self.editing = None;
+        self.error = None;
+    }
+
+    /// Assemble the line being edited into RAM, keeping it open if that fails.
+    pub fn finish_edit(&mut self, world: &mut World) {
+        if let Some(address) = self.editing {
+            match world.patch(address, &self.edit) {
+                Ok(_) => self.cancel_edit(),
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CodeView, CONTEXT_LINES, VISIBLE_LINES};
+    use chip8::disasm;
+    use chip8::world::World;
+
+    #[test]
+    fn test_visible() {
+        // 40 instructions of `ADD V0, 1`, then a jump back to the start.
+        let mut rom: Vec<u8> = [0x70, 0x01].repeat(40);
+        rom.extend_from_slice(&[0x12, 0x00]);
+        let disassembly = disasm::disassemble_rom(&rom);
+        let mut view = CodeView::default();
+
+        assert_eq!(view.visible(&disassembly, 0x200), 0..VISIBLE_LINES);
+        let lines = view.visible(&disassembly, 0x200 + 2 * 20);
+        assert_eq!(
+            lines.start + CONTEXT_LINES,
+            disassembly.line_at(0x228).unwrap(),
+            "The line at PC should be in the middle"
+        );
+        let lines = view.visible(&disassembly, 0x250);
+        assert_eq!(
+            lines.end,
+            disassembly.lines.len(),
+            "The view should stop at the last line"
+        );
+
+        view.follow_pc = false;
+        view.top = 3;
+        assert_eq!(view.visible(&disassembly, 0x228), 3..3 + VISIBLE_LINES);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut world = World::new();
+        world.load_rom(&[0x00, 0xE0, 0x12, 0x00]);
+        let mut view = CodeView::default();
+        let line = world.disassembly().lines[0].clone();
+        view.start_edit(&line);
+        assert_eq!(view.edit, "CLS");
+
+        view.edit = "LD V3, 0x".to_string();
+        view.finish_edit(&mut world);
+        assert!(
+            view.error.is_some(),
+            "A bad line should stay open with the error"
+        );
+        assert_eq!(view.editing, Some(0x200));
+
+        view.edit = "JP start".to_string();
+        view.finish_edit(&mut world);
+        assert_eq!(view.error, None);
+        assert_eq!(view.editing, None);
+        assert_eq!(&world.cpu.memory()[0x200..0x202], &[0x12, 0x00]);
+        assert_eq!(
+            world.disassembly().lines[0].text,
+            "JP start",
+            "The disassembly should show the patched code"
+        );
+
+        world.start_recording();
+        let before = world.cpu.memory()[0x200..0x202].to_vec();
+        assert!(
+            world.patch(0x200, "JP 0x202").is_err(),
+            "Code should not change under a movie"
+        );
+        assert_eq!(&world.cpu.memory()[0x200..0x202], &before[..]);
+    }
+}
@@ -0,0 +1,151 @@
+use super::CodeView;
+use chip8::cpu::ExecutionState;
+use chip8::disasm::Kind;
+use chip8::world::World;
+use egui::{Color32, TextStyle, Ui};
+
+/// Something clicked on a line, done once the lines are no longer borrowed.
+enum Action {
+    ToggleBreakpoint(u16),
+    RunTo(u16),
+    Edit(usize),
+    Assemble,
+    Cancel,
+}
+
+impl CodeView {
+    pub fn draw_ui(&mut self, ui: &mut Ui, world: &mut World) {
+        let paused = world.cpu.execution_state == ExecutionState::Paused;
+        let disassembly = world.disassembly();
+        ui.horizontal(|ui| {
+            ui.label(format!("{} lines", disassembly.lines.len()));
+            if ui.button("Save as...").clicked() {
+                let file = rfd::FileDialog::new()
+                    .set_directory(".")
+                    .add_filter("Assembly", &["asm"])
+                    .add_filter("JSON", &["json"])
+                    .save_file();
+                if let Some(file) = file {
+                    let text = match file.extension().and_then(|ext| ext.to_str()) {
+                        Some("json") => disassembly.to_json(),
+                        _ => disassembly.text(),
+                    };
+                    if let Err(e) = std::fs::write(&file, text) {
+                        log::error!("Failed to save disassembly: {}", e);
+                    }
+                }
+            }
+            ui.checkbox(&mut self.follow_pc, "Follow PC");
+        });
+        if !disassembly.computed_jumps.is_empty() {
+            let jumps: Vec<String> = disassembly
+                .computed_jumps
+                .iter()
+                .map(|addr| format!("{:03X}", addr))
+                .collect();
+            ui.colored_label(
+                Color32::YELLOW,
+                format!("Computed jumps not followed at {}", jumps.join(", ")),
+            );
+        }
+        if !self.follow_pc {
+            ui.add(
+                egui::Slider::u32(&mut self.top, 0..=CodeView::max_top(disassembly)).text("line"),
+            );
+        }
+        ui.label("Click a line to toggle a breakpoint on it.");
+        ui.separator();
+
+        let pc = world.cpu.pc();
+        let mut action = None;
+        for index in self.visible(disassembly, pc) {
+            let line = &disassembly.lines[index];
+            if let Some(label) = &line.label {
+                ui.add(egui::Label::new(format!("{}:", label)).text_style(TextStyle::Monospace));
+            }
+            ui.horizontal(|ui| {
+                let breakpoint = world.cpu.debugger.has_pc(line.address);
+                let marker = match (line.address == pc, breakpoint) {
+                    (true, _) => ">",
+                    (false, true) => "*",
+                    (false, false) => " ",
+                };
+                let mut bytes: Vec<String> = line
+                    .bytes
+                    .iter()
+                    .take(4)
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                if line.bytes.len() > 4 {
+                    bytes.push("..".to_string());
+                }
+                if self.editing == Some(line.address) {
+                    ui.add(
+                        egui::Label::new(format!("{} {:03X}", marker, line.address))
+                            .text_style(TextStyle::Monospace),
+                    );
+                    ui.text_edit_singleline(&mut self.edit);
+                    if ui.button("Assemble").clicked() {
+                        action = Some(Action::Assemble);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        action = Some(Action::Cancel);
+                    }
+                    return;
+                }
+                let mut text = format!(
+                    "{} {:03X}  {:<14} {:<28}",
+                    marker,
+                    line.address,
+                    bytes.join(" "),
+                    line.text
+                );
+                if let Some(comment) = &line.comment {
+                    text.push_str("; ");
+                    text.push_str(comment);
+                }
+                let colour = match line.kind {
+                    _ if line.address == pc => Color32::YELLOW,
+                    _ if breakpoint => Color32::RED,
+                    Kind::Code => Color32::WHITE,
+                    Kind::Sprite => Color32::from_rgb(140, 180, 255),
+                    Kind::Data => Color32::GRAY,
+                };
+                let button = egui::Button::new(text)
+                    .text_style(TextStyle::Monospace)
+                    .text_color(colour)
+                    .frame(false);
+                if ui.add(button).clicked() {
+                    action = Some(Action::ToggleBreakpoint(line.address));
+                }
+                if ui
+                    .add(egui::Button::new("Run to").small().enabled(paused))
+                    .clicked()
+                {
+                    action = Some(Action::RunTo(line.address));
+                }
+                if ui
+                    .add(egui::Button::new("Edit").small().enabled(paused))
+                    .clicked()
+                {
+                    action = Some(Action::Edit(index));
+                }
+            });
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error.as_str());
+        }
+
+        match action {
+            Some(Action::ToggleBreakpoint(address)) => world.cpu.debugger.toggle_pc(address),
+            Some(Action::RunTo(address)) => world.run_to(address),
+            Some(Action::Edit(index)) => {
+                let line = world.disassembly().lines[index].clone();
+                self.start_edit(&line);
+            }
+            Some(Action::Assemble) => self.finish_edit(world),
+            Some(Action::Cancel) => self.cancel_edit(),
+            None => {}
+        }
+    }
+}
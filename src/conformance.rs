@@ -0,0 +1,892 @@
+//! Built-in conformance suite: small programs assembled in code that exercise every
+//! opcode, flag and quirk, with the registers, memory and screen each one should
+//! leave behind under every quirk profile.
+
+use crate::cpu::Instruction::{self, *};
+use crate::cpu::{Cpu, CpuEvent, ExecutionState, QuirkProfile, Quirks, Random, RngAlgorithm};
+use crate::cpu::{MemoryIncrement, RAM_SIZE};
+
+/// A case fails if it has not reached `EXIT` after this many frames.
+const MAX_FRAMES: u64 = 120;
+
+/// One test program and the expectations it is checked against.
+pub struct Case {
+    pub name: &'static str,
+    build: fn(&Quirks) -> Test,
+}
+
+struct Test {
+    rom: Vec<u8>,
+    /// Key events and the frame at the start of which they happen.
+    keys: Vec<(u64, CpuEvent)>,
+    expect: Expect,
+}
+
+impl Test {
+    fn new(program: &Program, expect: Expect) -> Self {
+        Self {
+            rom: program.0.clone(),
+            keys: Vec::new(),
+            expect,
+        }
+    }
+}
+
+/// Machine state a case should end in. Anything left unset is not checked,
+/// except that the screen must be blank unless told otherwise.
+#[derive(Default)]
+struct Expect {
+    reg: Vec<(usize, u8)>,
+    i: Option<u16>,
+    sp: Option<u8>,
+    memory: Vec<(u16, Vec<u8>)>,
+    /// Every pixel that is lit, with its colour.
+    screen: Vec<(usize, usize, u8)>,
+    hires: Option<bool>,
+    sound_timer: Option<u8>,
+    audio: Option<([u8; 16], u8)>,
+}
+
+impl Expect {
+    fn reg(mut self, x: usize, value: u8) -> Self {
+        self.reg.push((x, value));
+        self
+    }
+
+    fn i(mut self, addr: u16) -> Self {
+        self.i = Some(addr);
+        self
+    }
+
+    fn sp(mut self, sp: u8) -> Self {
+        self.sp = Some(sp);
+        self
+    }
+
+    fn memory(mut self, addr: u16, bytes: &[u8]) -> Self {
+        self.memory.push((addr, bytes.to_vec()));
+        self
+    }
+
+    /// Pixels lit in the first plane only.
+    fn lit(mut self, pixels: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        self.screen
+            .extend(pixels.into_iter().map(|(x, y)| (x, y, 1)));
+        self
+    }
+
+    fn colour(mut self, x: usize, y: usize, colour: u8) -> Self {
+        self.screen.push((x, y, colour));
+        self
+    }
+
+    fn hires(mut self, hires: bool) -> Self {
+        self.hires = Some(hires);
+        self
+    }
+
+    fn sound_timer(mut self, value: u8) -> Self {
+        self.sound_timer = Some(value);
+        self
+    }
+
+    fn audio(mut self, pattern: [u8; 16], pitch: u8) -> Self {
+        self.audio = Some((pattern, pitch));
+        self
+    }
+}
+
+/// A program under construction, loaded at 0x200.
+struct Program(Vec<u8>);
+
+impl Program {
+    fn new() -> Self {
+        Program(Vec::new())
+    }
+
+    /// Address of the next byte.
+    fn here(&self) -> u16 {
+        0x200 + self.0.len() as u16
+    }
+
+    fn op(&mut self, instructions: &[Instruction]) -> &mut Self {
+        for instruction in instructions {
+            self.0.extend_from_slice(&instruction.encode());
+        }
+        self
+    }
+
+    fn data(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    /// Pad with zeroes up to `addr`.
+    fn org(&mut self, addr: u16) -> &mut Self {
+        assert!(
+            addr >= self.here(),
+            "org 0x{:03X} is behind the program",
+            addr
+        );
+        self.0.resize(addr as usize - 0x200, 0);
+        self
+    }
+}
+
+/// Pixels set in a picture drawn with `#`, placed with its top-left corner at `x`, `y`.
+fn art(x: usize, y: usize, rows: &[&str]) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for (dy, row) in rows.iter().enumerate() {
+        for (dx, c) in row.chars().enumerate() {
+            if c == '#' {
+                pixels.push((x + dx, y + dy));
+            }
+        }
+    }
+    pixels
+}
+
+/// The hex digit font as it should look, independently of the table in `cpu`.
+#[rustfmt::skip]
+const FONT: [[&str; 5]; 16] = [
+    ["####", "#..#", "#..#", "#..#", "####"],
+    ["..#.", ".##.", "..#.", "..#.", ".###"],
+    ["####", "...#", "####", "#...", "####"],
+    ["####", "...#", "####", "...#", "####"],
+    ["#..#", "#..#", "####", "...#", "...#"],
+    ["####", "#...", "####", "...#", "####"],
+    ["####", "#...", "####", "#..#", "####"],
+    ["####", "...#", "..#.", ".#..", ".#.."],
+    ["####", "#..#", "####", "#..#", "####"],
+    ["####", "#..#", "####", "...#", "####"],
+    ["####", "#..#", "####", "#..#", "#..#"],
+    ["###.", "#..#", "###.", "#..#", "###."],
+    ["####", "#...", "#...", "#...", "####"],
+    ["###.", "#..#", "#..#", "#..#", "###."],
+    ["####", "#...", "####", "#...", "####"],
+    ["####", "#...", "####", "#...", "#..."],
+];
+
+fn add_byte(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(0, 0xFF),
+        LdByte(0xF, 0x12),
+        AddByte(0, 0x02),
+        LdByte(1, 0x10),
+        AddByte(1, 0x20),
+        Exit,
+    ]);
+    // 7XNN wraps around without touching VF.
+    let expect = Expect::default().reg(0, 0x01).reg(1, 0x30).reg(0xF, 0x12);
+    Test::new(&p, expect)
+}
+
+fn load_register(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdByte(1, 0x42), LdReg(2, 1), Exit]);
+    Test::new(&p, Expect::default().reg(1, 0x42).reg(2, 0x42))
+}
+
+fn logic(quirks: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdByte(1, 0b1100), LdByte(2, 0b1010)]);
+    for (dest, flag, op) in [(3, 6, Or(3, 2)), (4, 7, And(4, 2)), (5, 8, Xor(5, 2))].iter() {
+        p.op(&[LdReg(*dest, 1), LdByte(0xF, 0x55), *op, LdReg(*flag, 0xF)]);
+    }
+    p.op(&[Exit]);
+    let vf = if quirks.vf_reset { 0 } else { 0x55 };
+    let expect = Expect::default()
+        .reg(3, 0b1110)
+        .reg(4, 0b1000)
+        .reg(5, 0b0110)
+        .reg(6, vf)
+        .reg(7, vf)
+        .reg(8, vf);
+    Test::new(&p, expect)
+}
+
+fn add_register(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(1, 0xF0),
+        LdByte(2, 0x20),
+        AddReg(1, 2),
+        LdReg(6, 0xF),
+        LdByte(3, 1),
+        LdByte(4, 2),
+        AddReg(3, 4),
+        LdReg(7, 0xF),
+        // With VF as the destination, the flag wins over the sum.
+        LdByte(0xF, 0xFF),
+        LdByte(5, 1),
+        AddReg(0xF, 5),
+        Exit,
+    ]);
+    let expect = Expect::default()
+        .reg(1, 0x10)
+        .reg(6, 1)
+        .reg(3, 3)
+        .reg(7, 0)
+        .reg(0xF, 1);
+    Test::new(&p, expect)
+}
+
+fn sub(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(1, 10),
+        LdByte(2, 3),
+        Sub(1, 2),
+        LdReg(6, 0xF),
+        LdByte(3, 3),
+        LdByte(4, 10),
+        Sub(3, 4),
+        LdReg(7, 0xF),
+        LdByte(5, 5),
+        LdByte(8, 5),
+        Sub(5, 8),
+        LdReg(9, 0xF),
+        LdByte(0xF, 1),
+        LdByte(0xA, 2),
+        Sub(0xF, 0xA),
+        Exit,
+    ]);
+    let expect = Expect::default()
+        .reg(1, 7)
+        .reg(6, 1)
+        .reg(3, 0xF9)
+        .reg(7, 0)
+        .reg(5, 0)
+        .reg(9, 1)
+        .reg(0xF, 0);
+    Test::new(&p, expect)
+}
+
+fn subn(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(1, 10),
+        LdByte(2, 3),
+        Subn(1, 2),
+        LdReg(6, 0xF),
+        LdByte(3, 3),
+        LdByte(4, 10),
+        Subn(3, 4),
+        LdReg(7, 0xF),
+        LdByte(0xF, 3),
+        LdByte(0xA, 1),
+        Subn(0xF, 0xA),
+        Exit,
+    ]);
+    let expect = Expect::default()
+        .reg(1, 0xF9)
+        .reg(6, 0)
+        .reg(3, 7)
+        .reg(7, 1)
+        .reg(0xF, 0);
+    Test::new(&p, expect)
+}
+
+fn shift_right(quirks: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(1, 0x80),
+        LdByte(2, 0x03),
+        Shr(1, 2),
+        LdReg(6, 0xF),
+        LdByte(3, 0x03),
+        LdByte(0xF, 0x02),
+        Shr(0xF, 3),
+        Exit,
+    ]);
+    let expect = if quirks.shift_uses_vy {
+        Expect::default().reg(1, 0x01).reg(6, 1).reg(0xF, 1)
+    } else {
+        Expect::default().reg(1, 0x40).reg(6, 0).reg(0xF, 0)
+    };
+    Test::new(&p, expect.reg(2, 0x03))
+}
+
+fn shift_left(quirks: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(1, 0x01),
+        LdByte(2, 0xC0),
+        Shl(1, 2),
+        LdReg(6, 0xF),
+        LdByte(3, 0x40),
+        LdByte(0xF, 0x81),
+        Shl(0xF, 3),
+        Exit,
+    ]);
+    let expect = if quirks.shift_uses_vy {
+        Expect::default().reg(1, 0x80).reg(6, 1).reg(0xF, 0)
+    } else {
+        Expect::default().reg(1, 0x02).reg(6, 0).reg(0xF, 1)
+    };
+    Test::new(&p, expect.reg(2, 0xC0))
+}
+
+fn skips(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdByte(1, 5), LdByte(2, 5), LdByte(3, 6)]);
+    // Each add that is not skipped sets a different bit of V0.
+    p.op(&[SeByte(1, 5), AddByte(0, 0x01)]);
+    p.op(&[SeByte(1, 6), AddByte(0, 0x02)]);
+    p.op(&[SneByte(1, 5), AddByte(0, 0x04)]);
+    p.op(&[SneByte(1, 6), AddByte(0, 0x08)]);
+    p.op(&[SeReg(1, 2), AddByte(0, 0x10)]);
+    p.op(&[SeReg(1, 3), AddByte(0, 0x20)]);
+    p.op(&[SneReg(1, 3), AddByte(0, 0x40)]);
+    p.op(&[SneReg(1, 2), AddByte(0, 0x80)]);
+    // XO-CHIP's 4-byte `F000 NNNN` is skipped as a whole.
+    p.op(&[SeByte(1, 5), LdLongI]).data(&[0x03, 0x00]);
+    p.op(&[LdByte(4, 1), Exit]);
+    let expect = Expect::default().reg(0, 0xA6).reg(4, 1).i(0);
+    Test::new(&p, expect)
+}
+
+fn jump(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[Jp(0x204), LdByte(0, 0xEE)]);
+    p.op(&[LdByte(1, 1), Exit]);
+    Test::new(&p, Expect::default().reg(0, 0).reg(1, 1))
+}
+
+fn call(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[Call(0x20A), AddByte(1, 1), Exit]);
+    p.org(0x20A).op(&[AddByte(0, 0x01), Call(0x210), Ret]);
+    p.org(0x210).op(&[AddByte(0, 0x10), Ret]);
+    Test::new(&p, Expect::default().reg(0, 0x11).reg(1, 1).sp(0))
+}
+
+fn jump_offset(quirks: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdByte(0, 4), LdByte(2, 8), JpV0(0x210)]);
+    p.org(0x214).op(&[LdByte(5, 1), Exit]);
+    p.org(0x218).op(&[LdByte(5, 2), Exit]);
+    // BXNN is B2NN here, so the quirk jumps by V2 instead of V0.
+    let target = if quirks.jump_with_vx { 2 } else { 1 };
+    Test::new(&p, Expect::default().reg(5, target))
+}
+
+fn load_store(quirks: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(0, 1),
+        LdByte(1, 2),
+        LdByte(2, 3),
+        LdI(0x300),
+        LdIVx(2),
+        // Lands wherever the first store left I.
+        LdByte(0, 0xAA),
+        LdIVx(0),
+        LdByte(0, 0),
+        LdByte(1, 0),
+        LdByte(2, 0),
+        LdI(0x300),
+        LdVxI(2),
+        Exit,
+    ]);
+    let advance = match quirks.memory_increment {
+        MemoryIncrement::None => 0,
+        MemoryIncrement::X => 2,
+        MemoryIncrement::XPlusOne => 3,
+    };
+    let mut memory = [1, 2, 3, 0];
+    memory[advance] = 0xAA;
+    let expect = Expect::default()
+        .memory(0x300, &memory)
+        .reg(0, memory[0])
+        .reg(1, memory[1])
+        .reg(2, memory[2])
+        .i(0x300 + advance as u16);
+    Test::new(&p, expect)
+}
+
+fn bcd(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(0, 234),
+        LdI(0x300),
+        LdB(0),
+        LdByte(1, 7),
+        LdI(0x310),
+        LdB(1),
+        Exit,
+    ]);
+    let expect = Expect::default()
+        .memory(0x300, &[2, 3, 4])
+        .memory(0x310, &[0, 0, 7])
+        .i(0x310);
+    Test::new(&p, expect)
+}
+
+fn add_i(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdI(0x2F0),
+        LdByte(1, 0x20),
+        LdByte(0xF, 0x33),
+        AddI(1),
+        Exit,
+    ]);
+    Test::new(&p, Expect::default().i(0x310).reg(0xF, 0x33))
+}
+
+fn timers(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdByte(0, 5), LdDtVx(0), LdStVx(0), LdVxDt(2)]);
+    let wait = p.here();
+    p.op(&[LdVxDt(1), SeByte(1, 0), Jp(wait), Exit]);
+    let expect = Expect::default().reg(2, 5).reg(1, 0).sound_timer(0);
+    Test::new(&p, expect)
+}
+
+fn display_wait(quirks: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        // Sprites of blank memory, so only the timing matters.
+        LdI(0x400),
+        LdByte(1, 10),
+        LdDtVx(1),
+        Drw(0, 0, 1),
+        Drw(0, 0, 1),
+        Drw(0, 0, 1),
+        LdVxDt(2),
+        Exit,
+    ]);
+    // Each sprite waits for the next timer tick, a dozen cycles apart at 700 Hz.
+    let delay = if quirks.display_wait { 7 } else { 10 };
+    Test::new(&p, Expect::default().reg(2, delay))
+}
+
+fn random(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[Rnd(0, 0x00), Rnd(1, 0x0F), LdByte(2, 0xF0), And(2, 1), Exit]);
+    Test::new(&p, Expect::default().reg(0, 0).reg(2, 0))
+}
+
+fn font(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    let mut expect = Expect::default();
+    for digit in 0..16u8 {
+        let (x, y) = ((digit % 8) * 5, (digit / 8) * 6);
+        p.op(&[
+            LdByte(0, digit),
+            LdF(0),
+            LdByte(1, x),
+            LdByte(2, y),
+            Drw(1, 2, 5),
+        ]);
+        expect = expect.lit(art(x as usize, y as usize, &FONT[digit as usize]));
+    }
+    p.op(&[Exit]);
+    Test::new(&p, expect)
+}
+
+fn big_font(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdByte(0, 7), LdHf(0), LdByte(1, 0), Drw(1, 1, 10), Exit]);
+    let seven = [
+        "########", "########", "......##", "......##", ".....##.", "....##..", "...##...",
+        "...##...", "...##...", "...##...",
+    ];
+    Test::new(&p, Expect::default().lit(art(0, 0, &seven)).i(0x96))
+}
+
+fn collision(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdI(0x300),
+        Drw(0, 1, 1),
+        LdReg(5, 0xF),
+        Drw(0, 1, 1),
+        LdReg(6, 0xF),
+        LdByte(2, 4),
+        LdByte(3, 2),
+        Drw(2, 3, 1),
+        LdReg(7, 0xF),
+        Exit,
+    ]);
+    p.org(0x300).data(&[0xFF]);
+    let expect = Expect::default()
+        .reg(5, 0)
+        .reg(6, 1)
+        .reg(7, 0)
+        .lit((4..12).map(|x| (x, 2)));
+    Test::new(&p, expect)
+}
+
+fn clear(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdI(0x300), Drw(0, 0, 2), Cls, Exit]);
+    p.org(0x300).data(&[0xFF, 0xFF]);
+    Test::new(&p, Expect::default())
+}
+
+fn clipping(quirks: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        // The starting position always wraps: (66, 33) is (2, 1).
+        LdByte(0, 66),
+        LdByte(1, 33),
+        LdI(0x300),
+        Drw(0, 1, 1),
+        LdByte(2, 60),
+        LdByte(3, 4),
+        LdI(0x301),
+        Drw(2, 3, 1),
+        LdByte(4, 8),
+        LdByte(5, 30),
+        LdI(0x302),
+        Drw(4, 5, 4),
+        Exit,
+    ]);
+    p.org(0x300).data(&[0x80, 0xFF, 0x80, 0x80, 0x80, 0x80]);
+    let mut expect = Expect::default()
+        .lit(vec![(2, 1), (8, 30), (8, 31)])
+        .lit((60..64).map(|x| (x, 4)));
+    if quirks.wrap_x {
+        expect = expect.lit((0..4).map(|x| (x, 4)));
+    }
+    if quirks.wrap_y {
+        expect = expect.lit(vec![(8, 0), (8, 1)]);
+    }
+    Test::new(&p, expect)
+}
+
+fn hires(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        High,
+        LdByte(0, 100),
+        LdByte(1, 40),
+        LdI(0x300),
+        Drw(0, 1, 1),
+        Exit,
+    ]);
+    p.org(0x300).data(&[0x80]);
+    Test::new(&p, Expect::default().hires(true).lit(vec![(100, 40)]))
+}
+
+fn lores(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[High, LdI(0x300), Drw(0, 0, 1), Low, Exit]);
+    p.org(0x300).data(&[0x80]);
+    Test::new(&p, Expect::default().hires(false))
+}
+
+fn scroll(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(0, 8),
+        LdI(0x300),
+        Drw(0, 0, 1),
+        Scd(3),
+        Scr,
+        Scr,
+        Scl,
+        Scu(1),
+        Exit,
+    ]);
+    p.org(0x300).data(&[0x80]);
+    Test::new(&p, Expect::default().lit(vec![(12, 10)]))
+}
+
+fn big_sprite(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[High, LdI(0x300), Drw(0, 0, 0), LdReg(5, 0xF), Exit]);
+    p.org(0x300);
+    for _ in 0..8 {
+        p.data(&[0xFF, 0x00, 0x00, 0xFF]);
+    }
+    let pixels = (0..16).flat_map(|y| {
+        let xs = if y % 2 == 0 { 0..8 } else { 8..16 };
+        xs.map(move |x| (x, y))
+    });
+    Test::new(&p, Expect::default().hires(true).lit(pixels).reg(5, 0))
+}
+
+fn rpl_flags(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(0, 1),
+        LdByte(1, 2),
+        LdByte(2, 3),
+        LdRVx(2),
+        LdByte(0, 0),
+        LdByte(1, 0),
+        LdByte(2, 0),
+        LdByte(3, 0x44),
+        LdVxR(2),
+        Exit,
+    ]);
+    let expect = Expect::default().reg(0, 1).reg(1, 2).reg(2, 3).reg(3, 0x44);
+    Test::new(&p, expect)
+}
+
+fn register_range(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[
+        LdByte(1, 1),
+        LdByte(2, 2),
+        LdByte(3, 3),
+        LdI(0x300),
+        SaveRange(1, 3),
+        LdI(0x310),
+        SaveRange(3, 1),
+        LdI(0x300),
+        LoadRange(4, 6),
+        LoadRange(9, 7),
+        Exit,
+    ]);
+    let expect = Expect::default()
+        .memory(0x300, &[1, 2, 3])
+        .memory(0x310, &[3, 2, 1])
+        .reg(4, 1)
+        .reg(5, 2)
+        .reg(6, 3)
+        .reg(9, 1)
+        .reg(8, 2)
+        .reg(7, 3)
+        .i(0x300);
+    Test::new(&p, expect)
+}
+
+fn long_load(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdLongI]).data(&[0xAB, 0xCD]);
+    p.op(&[LdByte(0, 0x77), LdIVx(0), Exit]);
+    Test::new(&p, Expect::default().memory(0xABCD, &[0x77]))
+}
+
+fn planes(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    // Both planes take a row each; then clearing the first plane leaves only the second.
+    p.op(&[Plane(3), LdI(0x300), Drw(0, 0, 1), Plane(1), Cls, Exit]);
+    p.org(0x300).data(&[0x80, 0xC0]);
+    Test::new(&p, Expect::default().colour(0, 0, 2).colour(1, 0, 2))
+}
+
+fn audio(_: &Quirks) -> Test {
+    let mut pattern = [0; 16];
+    for (i, byte) in pattern.iter_mut().enumerate() {
+        *byte = i as u8 * 0x11;
+    }
+    let mut p = Program::new();
+    p.op(&[LdI(0x300), Audio, LdByte(0, 0x70), Pitch(0), Exit]);
+    p.org(0x300).data(&pattern);
+    Test::new(&p, Expect::default().audio(pattern, 0x70))
+}
+
+fn keys(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[LdVxK(1), Skp(1), LdByte(2, 1), Sknp(1), LdByte(3, 1)]);
+    let held = p.here();
+    p.op(&[Sknp(1), Jp(held)]);
+    p.op(&[Skp(1), LdByte(4, 1), Sknp(1), LdByte(5, 1), Exit]);
+    let expect = Expect::default()
+        .reg(1, 5)
+        .reg(2, 0)
+        .reg(3, 1)
+        .reg(4, 1)
+        .reg(5, 0);
+    let mut test = Test::new(&p, expect);
+    test.keys = vec![(2, CpuEvent::KeyPressed(5)), (4, CpuEvent::KeyReleased(5))];
+    test
+}
+
+fn sys(_: &Quirks) -> Test {
+    let mut p = Program::new();
+    p.op(&[Sys(0x123), LdByte(0, 1), Exit]);
+    Test::new(&p, Expect::default().reg(0, 1))
+}
+
+#[rustfmt::skip]
+pub static CASES: &[Case] = &[
+    Case { name: "6XNN 7XNN load and add byte", build: add_byte },
+    Case { name: "8XY0 load register", build: load_register },
+    Case { name: "8XY1 8XY2 8XY3 logic", build: logic },
+    Case { name: "8XY4 add with carry", build: add_register },
+    Case { name: "8XY5 subtract", build: sub },
+    Case { name: "8XY7 subtract reversed", build: subn },
+    Case { name: "8XY6 shift right", build: shift_right },
+    Case { name: "8XYE shift left", build: shift_left },
+    Case { name: "3XNN 4XNN 5XY0 9XY0 skips", build: skips },
+    Case { name: "1NNN jump", build: jump },
+    Case { name: "2NNN 00EE call and return", build: call },
+    Case { name: "BNNN jump with offset", build: jump_offset },
+    Case { name: "FX55 FX65 store and load", build: load_store },
+    Case { name: "FX33 BCD", build: bcd },
+    Case { name: "FX1E add to I", build: add_i },
+    Case { name: "FX07 FX15 FX18 timers", build: timers },
+    Case { name: "DXYN display wait", build: display_wait },
+    Case { name: "CXNN random", build: random },
+    Case { name: "FX29 font", build: font },
+    Case { name: "FX30 big font", build: big_font },
+    Case { name: "DXYN collision", build: collision },
+    Case { name: "00E0 clear screen", build: clear },
+    Case { name: "DXYN clipping and wrapping", build: clipping },
+    Case { name: "00FF high resolution", build: hires },
+    Case { name: "00FE low resolution", build: lores },
+    Case { name: "00CN 00DN 00FB 00FC scrolling", build: scroll },
+    Case { name: "DXY0 16x16 sprite", build: big_sprite },
+    Case { name: "FX75 FX85 RPL flags", build: rpl_flags },
+    Case { name: "5XY2 5XY3 register ranges", build: register_range },
+    Case { name: "F000 NNNN long load", build: long_load },
+    Case { name: "FN01 bitplanes", build: planes },
+    Case { name: "F002 FX3A audio", build: audio },
+    Case { name: "EX9E EXA1 FX0A keys", build: keys },
+    Case { name: "0NNN ignored", build: sys },
+];
+
+impl Case {
+    /// Run the case under `profile` and describe every way the result differs from the expectation.
+    pub fn run(&self, profile: QuirkProfile) -> Result<(), String> {
+        let quirks = profile.quirks();
+        let test = (self.build)(&quirks);
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.rng = Random::new(RngAlgorithm::Xorshift, 0);
+        cpu.load_rom(&test.rom);
+        cpu.resume();
+        for frame in 0..MAX_FRAMES {
+            for &(_, event) in test.keys.iter().filter(|&&(at, _)| at == frame) {
+                cpu.handle_event(event);
+            }
+            cpu.run_frame();
+            if !cpu.is_active() {
+                break;
+            }
+        }
+        match cpu.execution_state {
+            ExecutionState::Halted => {}
+            ExecutionState::Faulted { fault, pc } => {
+                return Err(format!("{} at 0x{:03X}", fault, pc));
+            }
+            _ => return Err(format!("did not exit within {} frames", MAX_FRAMES)),
+        }
+        let errors = test.expect.check(&cpu);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+}
+
+impl Expect {
+    fn check(&self, cpu: &Cpu) -> Vec<String> {
+        let state = cpu.save_state();
+        let mut errors = Vec::new();
+        for &(x, value) in &self.reg {
+            if state.reg[x] != value {
+                errors.push(format!(
+                    "V{:X} is 0x{:02X}, expected 0x{:02X}",
+                    x, state.reg[x], value
+                ));
+            }
+        }
+        if let Some(i) = self.i.filter(|&i| i != state.reg_i) {
+            errors.push(format!("I is 0x{:03X}, expected 0x{:03X}", state.reg_i, i));
+        }
+        if let Some(sp) = self.sp.filter(|&sp| sp != state.sp) {
+            errors.push(format!("SP is {}, expected {}", state.sp, sp));
+        }
+        for (addr, bytes) in &self.memory {
+            let start = *addr as usize;
+            let found = &cpu.memory()[start..(start + bytes.len()).min(RAM_SIZE)];
+            if found != bytes.as_slice() {
+                errors.push(format!(
+                    "memory at 0x{:03X} is {:02X?}, expected {:02X?}",
+                    addr, found, bytes
+                ));
+            }
+        }
+        if let Some(hires) = self.hires.filter(|&hires| hires != state.hires) {
+            errors.push(format!(
+                "high resolution is {}, expected {}",
+                state.hires, hires
+            ));
+        }
+        if let Some(value) = self.sound_timer.filter(|&value| value != state.tim_sound) {
+            errors.push(format!(
+                "sound timer is {}, expected {}",
+                state.tim_sound, value
+            ));
+        }
+        if let Some((pattern, pitch)) = self.audio {
+            if state.audio_pattern != pattern {
+                errors.push(format!("audio pattern is {:02X?}", state.audio_pattern));
+            }
+            if state.pitch != pitch {
+                errors.push(format!("pitch is {}, expected {}", state.pitch, pitch));
+            }
+        }
+
+        let (width, height) = cpu.screen_size();
+        let mut expected = self.screen.clone();
+        expected.sort_unstable();
+        let mut found = Vec::new();
+        for x in 0..width {
+            for y in 0..height {
+                if cpu.vram[x][y] != 0 {
+                    found.push((x, y, cpu.vram[x][y]));
+                }
+            }
+        }
+        if let Some(&(x, y, colour)) = expected.iter().find(|px| !found.contains(px)) {
+            errors.push(format!("pixel ({}, {}) is not colour {}", x, y, colour));
+        } else if let Some(&(x, y, colour)) = found.iter().find(|px| !expected.contains(px)) {
+            errors.push(format!(
+                "pixel ({}, {}) is colour {}, expected it dark",
+                x, y, colour
+            ));
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::mem::discriminant;
+
+    use super::CASES;
+    use crate::cpu::{Instruction, QuirkProfile};
+
+    #[test]
+    fn test_conformance() {
+        let mut failures = Vec::new();
+        for case in CASES {
+            for &profile in QuirkProfile::ALL.iter() {
+                if let Err(e) = case.run(profile) {
+                    failures.push(format!("{} ({}): {}", case.name, profile.name(), e));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_every_opcode_covered() {
+        let mut all = HashSet::new();
+        for word in 0..=0xFFFFu16 {
+            if let Ok(instruction) = Instruction::decode(word.to_be_bytes()) {
+                all.insert(discriminant(&instruction));
+            }
+        }
+        let quirks = QuirkProfile::CosmacVip.quirks();
+        for case in CASES {
+            let rom = (case.build)(&quirks).rom;
+            for word in rom.chunks_exact(2) {
+                if let Ok(instruction) = Instruction::decode([word[0], word[1]]) {
+                    all.remove(&discriminant(&instruction));
+                }
+            }
+        }
+        assert!(all.is_empty(), "{} instructions are not covered", all.len());
+    }
+}
@@ -0,0 +1,177 @@
+//! Conditions on the machine state, like `V3 == 0x10 && I > 0x300`, for breakpoints.
+//!
+//! Values are the registers `V0`-`VF`, `I`, `DT`, `ST`, `SP` and `PC`, numbers in
+//! decimal, hex (`0x`) or binary (`0b`), and memory bytes as `[address]`. Operators
+//! are those of C with the same precedence: `|| && | ^ & == != < <= > >= << >> + - * / %`
+//! and the unary `! - ~`. Comparisons give 1 or 0, and anything but 0 is true.
+
+use std::fmt;
+
+use super::Cpu;
+use crate::expr::{Env, Expr, ExprError, Syntax};
+
+pub type ConditionError = ExprError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    V(usize),
+    I,
+    Dt,
+    St,
+    Sp,
+    Pc,
+}
+
+impl Value {
+    fn parse(word: &str) -> Option<Self> {
+        let upper = word.to_ascii_uppercase();
+        match upper.as_str() {
+            "I" => Some(Value::I),
+            "DT" => Some(Value::Dt),
+            "ST" => Some(Value::St),
+            "SP" => Some(Value::Sp),
+            "PC" => Some(Value::Pc),
+            _ if upper.len() == 2 && upper.starts_with('V') => {
+                usize::from_str_radix(&upper[1..], 16).ok().map(Value::V)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Env<Value> for Cpu {
+    fn value(&self, value: &Value, _column: usize) -> Result<i64, ExprError> {
+        Ok(match *value {
+            Value::V(x) => self.reg[x] as i64,
+            Value::I => self.reg_i as i64,
+            Value::Dt => self.tim_delay as i64,
+            Value::St => self.tim_sound as i64,
+            Value::Sp => self.sp as i64,
+            Value::Pc => self.pc as i64,
+        })
+    }
+
+    fn memory(&self, address: i64) -> i64 {
+        if address >= 0 && (address as usize) < self.ram.len() {
+            self.ram[address as usize] as i64
+        } else {
+            0
+        }
+    }
+}
+
+/// A parsed condition, which remembers the text it was written as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr<Value>,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let syntax = Syntax {
+            comparisons: true,
+            memory: true,
+        };
+        let expr = Expr::parse(source, 1, syntax, |word| {
+            Value::parse(word).ok_or_else(|| format!("unknown value `{}`", word))
+        })?;
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The value of the condition. Dividing by zero makes the whole condition 0,
+    /// rather than stopping the emulator.
+    pub fn eval(&self, cpu: &Cpu) -> i64 {
+        self.expr.eval(cpu).unwrap_or(0)
+    }
+
+    pub fn is_true(&self, cpu: &Cpu) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Condition;
+    use crate::cpu::Cpu;
+
+    fn eval(source: &str, cpu: &Cpu) -> i64 {
+        Condition::parse(source)
+            .unwrap_or_else(|e| panic!("{}: {}", source, e))
+            .eval(cpu)
+    }
+
+    #[test]
+    fn test_values() {
+        let mut cpu = Cpu::new();
+        cpu.reg[3] = 0x10;
+        cpu.reg[0xF] = 1;
+        cpu.reg_i = 0x300;
+        cpu.ram[0x300] = 0xAB;
+        cpu.tim_delay = 5;
+        assert_eq!(eval("V3", &cpu), 0x10);
+        assert_eq!(eval("vf", &cpu), 1, "Names should not be case sensitive");
+        assert_eq!(eval("I", &cpu), 0x300);
+        assert_eq!(eval("DT + PC", &cpu), 5 + 0x200);
+        assert_eq!(eval("[I]", &cpu), 0xAB);
+        assert_eq!(eval("[0x2FF + 1]", &cpu), 0xAB);
+        assert_eq!(
+            eval("[0x10000]", &cpu),
+            0,
+            "Addresses past RAM should read as 0"
+        );
+        assert_eq!(eval("0b101 + 10", &cpu), 15);
+    }
+
+    #[test]
+    fn test_operators() {
+        let cpu = Cpu::new();
+        assert_eq!(eval("1 + 2 * 3", &cpu), 7);
+        assert_eq!(eval("(1 + 2) * 3", &cpu), 9);
+        assert_eq!(eval("1 << 4 | 1", &cpu), 17);
+        assert_eq!(eval("6 & 3 == 2", &cpu), 0, "== should bind tighter than &");
+        assert_eq!(eval("1 < 2 && 2 <= 2 && 3 >= 4 || 5 != 5", &cpu), 0);
+        assert_eq!(eval("!0 + -1", &cpu), 0);
+        assert_eq!(eval("~0", &cpu), -1);
+        assert_eq!(eval("7 / 0", &cpu), 0);
+        assert_eq!(
+            eval("10 - 3 - 2", &cpu),
+            5,
+            "Operators should be left associative"
+        );
+    }
+
+    #[test]
+    fn test_is_true() {
+        let mut cpu = Cpu::new();
+        let condition = Condition::parse("V3 == 0x10 && I > 0x300").unwrap();
+        assert!(!condition.is_true(&cpu));
+        cpu.reg[3] = 0x10;
+        cpu.reg_i = 0x301;
+        assert!(condition.is_true(&cpu));
+        assert_eq!(condition.to_string(), "V3 == 0x10 && I > 0x300");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| Condition::parse(source).unwrap_err();
+        assert_eq!(error("V3 ==").column, 6);
+        assert_eq!(error("VG > 1").message, "unknown value `VG`");
+        assert_eq!(error("(1 + 2").message, "expected `)`");
+        assert_eq!(error("1 2").column, 3);
+        assert_eq!(error("").column, 1);
+    }
+}
@@ -0,0 +1,673 @@
+//! Breakpoints on the program counter, memory accesses and register changes.
+
+use std::fmt;
+
+use super::{Condition, Cpu, ExecutionState, Instruction, RAM_SIZE};
+
+/// A register a watchpoint can look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+        }
+    }
+}
+
+/// What makes a breakpoint fire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// The CPU is about to execute the instruction at this address.
+    Pc(u16),
+    /// An instruction read a byte from `start` to `end` inclusive, as `FX65`, `5XY3`,
+    /// `DXYN` and `F002` do.
+    Read { start: u16, end: u16 },
+    /// An instruction wrote a byte from `start` to `end` inclusive, as `FX55`, `5XY2`
+    /// and `FX33` do.
+    Write { start: u16, end: u16 },
+    /// An instruction changed the value of the register.
+    Register(Register),
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = |f: &mut fmt::Formatter<'_>, start: u16, end: u16| {
+            if start == end {
+                write!(f, "0x{:03X}", start)
+            } else {
+                write!(f, "0x{:03X}-0x{:03X}", start, end)
+            }
+        };
+        match *self {
+            Trigger::Pc(pc) => write!(f, "PC = 0x{:03X}", pc),
+            Trigger::Read { start, end } => {
+                write!(f, "read ")?;
+                range(f, start, end)
+            }
+            Trigger::Write { start, end } => {
+                write!(f, "write ")?;
+                range(f, start, end)
+            }
+            Trigger::Register(register) => write!(f, "{} changes", register),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub trigger: Trigger,
+    /// Only counts as a hit while this holds.
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+    /// Times the trigger fired with the condition holding.
+    pub hits: u64,
+    /// Pause on this hit and every one after it. 0 and 1 both pause on the first.
+    pub pause_after: u64,
+}
+
+impl Breakpoint {
+    pub fn new(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            condition: None,
+            enabled: true,
+            hits: 0,
+            pause_after: 0,
+        }
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+}
+
+/// Why a breakpoint paused the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    /// About to execute the instruction at the address.
+    Pc(u16),
+    /// The instruction at `pc` read memory at `addr`.
+    Read { pc: u16, addr: u16 },
+    /// The instruction at `pc` wrote `value` to memory at `addr`.
+    Write { pc: u16, addr: u16, value: u8 },
+    /// The instruction at `pc` changed a register.
+    Register {
+        pc: u16,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BreakReason::Pc(pc) => write!(f, "reached 0x{:03X}", pc),
+            BreakReason::Read { pc, addr } => {
+                write!(f, "0x{:03X} read memory at 0x{:03X}", pc, addr)
+            }
+            BreakReason::Write { pc, addr, value } => write!(
+                f,
+                "0x{:03X} wrote 0x{:02X} to memory at 0x{:03X}",
+                pc, value, addr
+            ),
+            BreakReason::Register {
+                pc,
+                register,
+                old,
+                new,
+            } => write!(
+                f,
+                "0x{:03X} changed {} from 0x{:X} to 0x{:X}",
+                pc, register, old, new
+            ),
+        }
+    }
+}
+
+/// The last time a breakpoint paused the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Break {
+    /// Index into `Debugger::breakpoints`.
+    pub breakpoint: usize,
+    pub reason: BreakReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read(usize),
+    Write(usize, u8),
+}
+
+/// Where a step over, step out or run to address pauses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Pc(u16),
+    /// The return address of a call, back at the depth it was made from.
+    Return {
+        pc: u16,
+        sp: u8,
+    },
+    /// Anywhere with fewer than this many stack entries.
+    Shallower(u8),
+}
+
+impl Target {
+    fn reached(self, pc: u16, sp: u8) -> bool {
+        match self {
+            Target::Pc(target) => pc == target,
+            Target::Return {
+                pc: target,
+                sp: depth,
+            } => pc == target && sp == depth,
+            Target::Shallower(depth) => sp < depth,
+        }
+    }
+}
+
+/// Breakpoints, checked around every instruction the CPU executes.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    /// Why the CPU last paused on a breakpoint, until it is resumed.
+    pub last_break: Option<Break>,
+    /// Memory accessed by the instruction being executed.
+    accesses: Vec<Access>,
+    /// A PC breakpoint at this address just fired, so resuming should not fire it again.
+    resume_at: Option<u16>,
+    target: Option<Target>,
+}
+
+impl Debugger {
+    /// Add a PC breakpoint at `pc`, or remove the ones there.
+    pub fn toggle_pc(&mut self, pc: u16) {
+        let before = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.trigger != Trigger::Pc(pc));
+        if self.breakpoints.len() == before {
+            self.breakpoints.push(Breakpoint::new(Trigger::Pc(pc)));
+        }
+    }
+
+    /// Whether an enabled PC breakpoint is set at `pc`.
+    pub fn has_pc(&self, pc: u16) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.enabled && breakpoint.trigger == Trigger::Pc(pc))
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.breakpoints.len() {
+            self.breakpoints.remove(index);
+            self.last_break = None;
+        }
+    }
+
+    /// Whether there is anything to check around instructions.
+    pub(super) fn is_active(&self) -> bool {
+        !self.breakpoints.is_empty() || self.target.is_some()
+    }
+
+    /// Forget any pending step over, step out or run to address.
+    pub(super) fn cancel_step(&mut self) {
+        self.target = None;
+    }
+
+    fn watches_memory(&self) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.enabled
+                && matches!(
+                    breakpoint.trigger,
+                    Trigger::Read { .. } | Trigger::Write { .. }
+                )
+        })
+    }
+
+    fn watches_registers(&self) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.enabled && matches!(breakpoint.trigger, Trigger::Register(_))
+        })
+    }
+}
+
+impl Cpu {
+    /// Note a data read by the current instruction, for watchpoints.
+    pub(super) fn watch_read(&mut self, addr: usize, len: usize) {
+        if !self.debugger.breakpoints.is_empty() {
+            let accesses = &mut self.debugger.accesses;
+            accesses.extend((addr..addr + len).map(|addr| Access::Read(addr % RAM_SIZE)));
+        }
+    }
+
+    /// Note a write by the current instruction, for watchpoints.
+    pub(super) fn watch_write(&mut self, addr: usize, bytes: &[u8]) {
+        if !self.debugger.breakpoints.is_empty() {
+            let accesses = &mut self.debugger.accesses;
+            accesses.extend(
+                bytes
+                    .iter()
+                    .enumerate()
+                    .map(|(offset, &byte)| Access::Write((addr + offset) % RAM_SIZE, byte)),
+            );
+        }
+    }
+
+    /// Execute one instruction while paused. A breakpoint at the current address
+    /// does not fire, watchpoints do.
+    pub fn step(&mut self) {
+        if self.execution_state != ExecutionState::Paused {
+            return;
+        }
+        if self.debugger.is_active() {
+            self.debugger.resume_at = Some(self.pc);
+        }
+        self.execution_state = ExecutionState::Running;
+        // Under the display wait quirk nothing runs until the next timer tick.
+        while self.waiting_for_vblank && self.is_active() {
+            self.clock_cycle();
+        }
+        self.clock_cycle();
+        self.pause();
+    }
+
+    /// Like `step`, but run a whole subroutine if the next instruction calls one,
+    /// pausing once it returns.
+    pub fn step_over(&mut self) {
+        let pc = self.pc as usize;
+        let next = match (self.read(pc), self.read(pc + 1)) {
+            (Ok(hi), Ok(lo)) => Instruction::decode([hi, lo]).ok(),
+            _ => None,
+        };
+        match next {
+            Some(Instruction::Call(_)) if self.sp < 16 => {
+                let target = Target::Return {
+                    pc: self.pc.wrapping_add(2),
+                    sp: self.sp,
+                };
+                self.run_to_target(target);
+            }
+            _ => self.step(),
+        }
+    }
+
+    /// Run until the current subroutine returns. Does nothing outside one.
+    pub fn step_out(&mut self) {
+        if self.sp > 0 {
+            self.run_to_target(Target::Shallower(self.sp));
+        }
+    }
+
+    /// Run until the instruction at `addr` is about to execute.
+    pub fn run_to(&mut self, addr: u16) {
+        self.run_to_target(Target::Pc(addr));
+    }
+
+    /// Resume, to pause again at `target` or any breakpoint on the way.
+    fn run_to_target(&mut self, target: Target) {
+        if self.execution_state != ExecutionState::Paused {
+            return;
+        }
+        self.debugger.target = Some(target);
+        self.debugger.resume_at = Some(self.pc);
+        self.resume();
+    }
+
+    /// Execute the next instruction with breakpoints and any step target in effect.
+    /// PC breakpoints and targets pause before it runs, watchpoints right after.
+    /// Returns `false` if it did not run.
+    pub(super) fn execute_with_debugger(&mut self) -> bool {
+        let pc = self.pc;
+        let resuming = self.debugger.resume_at.take() == Some(pc);
+        if !resuming {
+            if let Some(target) = self.debugger.target {
+                if target.reached(pc, self.sp) {
+                    self.debugger.target = None;
+                    self.pause();
+                    return false;
+                }
+            }
+            if let Some(index) = self.hit(|trigger| trigger == Trigger::Pc(pc)) {
+                self.debugger.resume_at = Some(pc);
+                self.stop(index, BreakReason::Pc(pc));
+                return false;
+            }
+        }
+
+        let watch_memory = self.debugger.watches_memory();
+        let before = if self.debugger.watches_registers() {
+            Some((self.reg, self.reg_i))
+        } else {
+            None
+        };
+        self.debugger.accesses.clear();
+        self.execute_next_instruction();
+
+        if watch_memory {
+            let accesses = std::mem::take(&mut self.debugger.accesses);
+            for access in accesses {
+                let (addr, reason) = match access {
+                    Access::Read(addr) => (
+                        addr,
+                        BreakReason::Read {
+                            pc,
+                            addr: addr as u16,
+                        },
+                    ),
+                    Access::Write(addr, value) => (
+                        addr,
+                        BreakReason::Write {
+                            pc,
+                            addr: addr as u16,
+                            value,
+                        },
+                    ),
+                };
+                let in_range =
+                    |start: u16, end: u16| (start as usize..=end as usize).contains(&addr);
+                let index = self.hit(|trigger| match (trigger, access) {
+                    (Trigger::Read { start, end }, Access::Read(_))
+                    | (Trigger::Write { start, end }, Access::Write(..)) => in_range(start, end),
+                    _ => false,
+                });
+                if let Some(index) = index {
+                    self.stop(index, reason);
+                    return true;
+                }
+            }
+        }
+
+        if let Some((reg, reg_i)) = before {
+            let changes = (0..16)
+                .filter(|&x| reg[x] != self.reg[x])
+                .map(|x| (Register::V(x as u8), reg[x] as u16, self.reg[x] as u16))
+                .chain(Some((Register::I, reg_i, self.reg_i)).filter(|(_, old, new)| old != new));
+            for (register, old, new) in changes.collect::<Vec<_>>() {
+                if let Some(index) = self.hit(|trigger| trigger == Trigger::Register(register)) {
+                    let reason = BreakReason::Register {
+                        pc,
+                        register,
+                        old,
+                        new,
+                    };
+                    self.stop(index, reason);
+                    return true;
+                }
+            }
+        }
+        true
+    }
+
+    /// Count a hit on every enabled breakpoint `fires` picks whose condition holds,
+    /// and return the first that should pause.
+    fn hit(&mut self, fires: impl Fn(Trigger) -> bool) -> Option<usize> {
+        let mut pause = None;
+        for index in 0..self.debugger.breakpoints.len() {
+            let breakpoint = &self.debugger.breakpoints[index];
+            if !breakpoint.enabled || !fires(breakpoint.trigger) {
+                continue;
+            }
+            if let Some(condition) = &breakpoint.condition {
+                if !condition.is_true(self) {
+                    continue;
+                }
+            }
+            let breakpoint = &mut self.debugger.breakpoints[index];
+            breakpoint.hits += 1;
+            if breakpoint.hits >= breakpoint.pause_after && pause.is_none() {
+                pause = Some(index);
+            }
+        }
+        pause
+    }
+
+    fn stop(&mut self, breakpoint: usize, reason: BreakReason) {
+        log::info!("Breakpoint {}: {}", breakpoint, reason);
+        self.debugger.last_break = Some(Break { breakpoint, reason });
+        self.debugger.target = None;
+        if self.execution_state == ExecutionState::Running {
+            self.execution_state = ExecutionState::Paused;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BreakReason, Breakpoint, Register, Trigger};
+    use crate::cpu::{Condition, Cpu, ExecutionState};
+
+    fn run(cpu: &mut Cpu, cycles: usize) {
+        cpu.resume();
+        for _ in 0..cycles {
+            cpu.clock_cycle();
+        }
+    }
+
+    #[test]
+    fn test_pc_breakpoint() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]); // LD V0, 1; ADD V0, 1; JP 0x202
+        cpu.debugger
+            .breakpoints
+            .push(Breakpoint::new(Trigger::Pc(0x204)));
+        run(&mut cpu, 10);
+        assert_eq!(cpu.execution_state, ExecutionState::Paused);
+        assert_eq!(
+            cpu.pc(),
+            0x204,
+            "The instruction at the breakpoint should not run"
+        );
+        assert_eq!(cpu.reg[0], 2);
+        let last = cpu.debugger.last_break.unwrap();
+        assert_eq!(last.reason, BreakReason::Pc(0x204));
+        assert_eq!(last.reason.to_string(), "reached 0x204");
+
+        run(&mut cpu, 10);
+        assert_eq!(
+            cpu.pc(),
+            0x204,
+            "Resuming should run past the breakpoint once"
+        );
+        assert_eq!(cpu.reg[0], 3);
+        assert_eq!(cpu.debugger.breakpoints[0].hits, 2);
+    }
+
+    #[test]
+    fn test_condition_and_hit_count() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x70, 0x01, 0x12, 0x00]); // ADD V0, 1; JP 0x200
+        let mut breakpoint = Breakpoint::new(Trigger::Pc(0x200))
+            .with_condition(Condition::parse("V0 >= 3").unwrap());
+        breakpoint.pause_after = 2;
+        cpu.debugger.breakpoints.push(breakpoint);
+        run(&mut cpu, 100);
+        assert_eq!(
+            cpu.reg[0], 4,
+            "Should pause on the second time the condition held"
+        );
+        assert_eq!(cpu.debugger.breakpoints[0].hits, 2);
+    }
+
+    #[test]
+    fn test_memory_watchpoints() {
+        let rom = [
+            0xA3, 0x00, // LD I, 0x300
+            0x60, 0x2A, // LD V0, 42
+            0xF0, 0x33, // LD B, V0
+            0xF0, 0x65, // LD V0, [I]
+            0xA3, 0x00, // LD I, 0x300
+            0xD0, 0x05, // DRW V0, V0, 5
+            0x12, 0x0C, // JP 0x20C
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom);
+        cpu.debugger
+            .breakpoints
+            .push(Breakpoint::new(Trigger::Write {
+                start: 0x302,
+                end: 0x302,
+            }));
+        run(&mut cpu, 10);
+        assert_eq!(
+            cpu.debugger.last_break.unwrap().reason,
+            BreakReason::Write {
+                pc: 0x204,
+                addr: 0x302,
+                value: 2
+            },
+            "FX33 should trigger a write watchpoint on its last digit"
+        );
+        assert_eq!(cpu.pc(), 0x206, "Watchpoints should pause after the access");
+
+        cpu.debugger.breakpoints[0].trigger = Trigger::Read {
+            start: 0x300,
+            end: 0x30F,
+        };
+        run(&mut cpu, 10);
+        assert_eq!(
+            cpu.debugger.last_break.unwrap().reason,
+            BreakReason::Read {
+                pc: 0x206,
+                addr: 0x300
+            }
+        );
+        run(&mut cpu, 10);
+        assert_eq!(
+            cpu.debugger.last_break.unwrap().reason,
+            BreakReason::Read {
+                pc: 0x20A,
+                addr: 0x300
+            },
+            "Sprite data read by DXYN should trigger a read watchpoint"
+        );
+        run(&mut cpu, 10);
+        assert_eq!(
+            cpu.execution_state,
+            ExecutionState::Running,
+            "Fetching instructions is not a data read"
+        );
+    }
+
+    #[test]
+    fn test_register_watchpoint() {
+        let rom = [
+            0x61, 0x05, // LD V1, 5
+            0x61, 0x05, // LD V1, 5
+            0x71, 0x01, // ADD V1, 1
+            0x12, 0x06, // JP 0x206
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom);
+        cpu.debugger
+            .breakpoints
+            .push(Breakpoint::new(Trigger::Register(Register::V(1))));
+        run(&mut cpu, 10);
+        assert_eq!(cpu.pc(), 0x202);
+        run(&mut cpu, 10);
+        assert_eq!(
+            cpu.pc(),
+            0x206,
+            "Writing the value a register already holds is not a change"
+        );
+        assert_eq!(
+            cpu.debugger.last_break.unwrap().reason.to_string(),
+            "0x204 changed V1 from 0x5 to 0x6"
+        );
+    }
+
+    #[test]
+    fn test_toggle_pc() {
+        let mut cpu = Cpu::new();
+        cpu.debugger.toggle_pc(0x210);
+        assert!(cpu.debugger.has_pc(0x210));
+        cpu.debugger.toggle_pc(0x210);
+        assert!(!cpu.debugger.has_pc(0x210));
+        assert!(cpu.debugger.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn test_step() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]); // LD V0, 1; ADD V0, 1; JP 0x202
+        cpu.debugger.toggle_pc(0x200);
+        cpu.step();
+        assert_eq!(
+            (cpu.pc(), cpu.reg[0]),
+            (0x202, 1),
+            "Stepping should not stop on a breakpoint at the current address"
+        );
+        assert_eq!(cpu.execution_state, ExecutionState::Paused);
+        assert_eq!(cpu.cycle(), 1);
+
+        cpu.execution_state = ExecutionState::Running;
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x202, "Stepping should only work while paused");
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let rom = [
+            0x22, 0x08, // CALL 0x208
+            0x60, 0x07, // LD V0, 7
+            0x12, 0x04, // JP 0x204
+            0x00, 0x00, 0x61, 0x01, // LD V1, 1
+            0x22, 0x0E, // CALL 0x20E
+            0x00, 0xEE, // RET
+            0x62, 0x02, // LD V2, 2
+            0x00, 0xEE, // RET
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom);
+        cpu.step_over();
+        cpu.run_frame();
+        assert_eq!(cpu.pc(), 0x202, "Step over should run the whole call");
+        assert_eq!((cpu.reg[1], cpu.reg[2]), (1, 2));
+        assert_eq!(cpu.execution_state, ExecutionState::Paused);
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom);
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x20E, "Should be two calls deep");
+        cpu.step_out();
+        cpu.run_frame();
+        assert_eq!(cpu.pc(), 0x20C, "Step out should stop after the return");
+        cpu.step_out();
+        cpu.run_frame();
+        assert_eq!(cpu.pc(), 0x202);
+        cpu.step_out();
+        assert_eq!(
+            cpu.execution_state,
+            ExecutionState::Paused,
+            "There is nothing to step out of at the top level"
+        );
+    }
+
+    #[test]
+    fn test_run_to() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x70, 0x01, 0x12, 0x00]); // ADD V0, 1; JP 0x200
+        cpu.run_to(0x200);
+        cpu.run_frame();
+        assert_eq!(
+            (cpu.pc(), cpu.reg[0]),
+            (0x200, 1),
+            "Running to the current address should go round the loop once"
+        );
+        cpu.debugger.toggle_pc(0x202);
+        cpu.run_to(0x200);
+        cpu.run_frame();
+        assert_eq!(cpu.pc(), 0x202, "Breakpoints on the way should still fire");
+        assert_eq!(
+            cpu.debugger.target, None,
+            "Hitting a breakpoint should cancel the run to address"
+        );
+    }
+}
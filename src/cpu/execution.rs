@@ -1,249 +1,288 @@
-use log::{debug, error, trace, warn};
-use rand::Rng;
+use log::{debug, trace, warn};
+
+use super::instruction::Instruction::{self, *};
+use super::{CpuFault, FaultPolicy, MemoryIncrement, SpriteDraw};
 
 impl super::Cpu {
-    pub fn fetch_next_instruction(&mut self) -> [u8; 2] {
-        let instruction = &self.ram[self.pc as usize..=self.pc as usize + 2];
+    pub fn fetch_next_instruction(&mut self) -> Result<[u8; 2], CpuFault> {
+        let pc = self.pc as usize;
+        let instruction = [self.read(pc)?, self.read(pc + 1)?];
         trace!(
             "Instruction 0x{:02X}{:02X} at {}",
             instruction[0],
             instruction[1],
             self.pc
         );
-        self.pc += 2;
-        [instruction[0], instruction[1]]
+        self.pc = self.pc.wrapping_add(2);
+        Ok(instruction)
+    }
+
+    /// Skip over the next instruction, which is 4 bytes long if it is XO-CHIP's `F000 NNNN`.
+    fn skip_next_instruction(&mut self) -> Result<(), CpuFault> {
+        let next = self.pc as usize;
+        let long = [self.read(next)?, self.read(next + 1)?] == [0xF0, 0x00];
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+        Ok(())
+    }
+
+    /// Value `8XY6`/`8XYE` shift, depending on the shift quirk.
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.reg[y as usize]
+        } else {
+            self.reg[x as usize]
+        }
     }
 
-    fn parse_instruction(instruction: [u8; 2]) -> (u8, u8, u8, u16) {
-        let x = instruction[0] & 0x0F;
-        let y = instruction[1] >> 4;
-        let kk = instruction[1];
-        let nnn = kk as u16 + ((x as u16) << 8);
-        (x, y, kk, nnn)
+    /// Registers `Vx` to `Vy` as used by `5XY2`/`5XY3`, in descending order if `x > y`.
+    fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    fn increment_i_after_memory_access(&mut self, x: u8) -> Result<(), CpuFault> {
+        self.reg_i = self.offset_i(match self.quirks.memory_increment {
+            MemoryIncrement::None => 0,
+            MemoryIncrement::X => x as u16,
+            MemoryIncrement::XPlusOne => x as u16 + 1,
+        })?;
+        Ok(())
     }
 
-    /// Execute a given instruction.
+    /// Decode and execute a given instruction.
     pub fn execute_instruction(&mut self, instruction: [u8; 2]) {
-        let (x, y, byte, addr) = Self::parse_instruction(instruction);
-        let last_digit = byte & 0x0F;
-        match instruction[0] >> 4 {
-            0 => match instruction {
-                [0x00, 0xE0] => {
-                    debug!("CLS");
-                    for i in self.vram.iter_mut() {
-                        for j in i.iter_mut() {
-                            *j = false;
-                        }
-                    }
-                }
-                [0x00, 0xEE] => {
-                    debug!("RET");
-                    if self.sp == 0 {
-                        error!("Trying to return with empty stack! Execution halted.");
-                        self.pause();
-                        // panic!("{:?}", self.execution_state)
-                    } else {
-                        self.sp -= 1;
-                        self.pc = self.stack[self.sp as usize];
-                        trace!("Returning to {:X}", self.pc);
-                    }
-                }
-                _ => {
-                    debug!("SYS {:X}", addr);
-                    warn!("SYS is an unsupported instruction! Continuing execution.")
+        match Instruction::decode(instruction) {
+            Ok(instruction) => self.execute(instruction),
+            Err(e) => self.fault(CpuFault::InvalidOpcode(e.0), self.pc.wrapping_sub(2)),
+        }
+    }
+
+    /// Execute a decoded instruction, handling any fault according to the fault policy.
+    pub fn execute(&mut self, instruction: Instruction) {
+        debug!("{}", instruction);
+        // The instruction has already been fetched, so it started 2 bytes back.
+        let pc = self.pc.wrapping_sub(2);
+        if let Err(fault) = self.try_execute(instruction) {
+            self.fault(fault, pc);
+        }
+    }
+
+    fn try_execute(&mut self, instruction: Instruction) -> Result<(), CpuFault> {
+        let wrap = self.fault_policy == FaultPolicy::Wrap;
+        match instruction {
+            Scd(n) => self.scroll_down(n as usize),
+            Scu(n) => self.scroll_up(n as usize),
+            Cls => self.clear_screen(),
+            Ret => {
+                if self.sp == 0 && !wrap {
+                    return Err(CpuFault::StackUnderflow);
                 }
-            },
-            1 => {
-                debug!("JP {:X}", addr);
+                self.sp = (self.sp + 15) % 16;
+                self.pc = self.stack[self.sp as usize];
+                trace!("Returning to {:X}", self.pc);
+            }
+            Scr => self.scroll_right(4),
+            Scl => self.scroll_left(4),
+            Exit => self.execution_state = super::ExecutionState::Halted,
+            Low => self.set_hires(false),
+            High => self.set_hires(true),
+            Sys(_) => warn!("SYS is an unsupported instruction! Continuing execution."),
+            Jp(addr) => {
                 self.pc = addr;
                 trace!("Jumping to {:X}", self.pc);
             }
-            2 => {
-                debug!("CALL {:X}", addr);
-                if self.sp == 16 {
-                    error!("Stack overflow at 0x{:X}", self.pc-2);
-                    self.execution_state = super::ExecutionState::Paused;
-                    return
+            Call(addr) => {
+                if self.sp == 16 && !wrap {
+                    return Err(CpuFault::StackOverflow);
                 }
-                self.stack[self.sp as usize] = self.pc;
-                self.sp += 1;
+                self.stack[self.sp as usize % 16] = self.pc;
+                self.sp = if wrap { (self.sp + 1) % 16 } else { self.sp + 1 };
                 self.pc = addr;
             }
-            3 => {
-                debug!("SE V{:X}, {:X}", x, byte);
+            SeByte(x, byte) => {
                 if self.reg[x as usize] == byte {
-                    self.pc += 2;
+                    self.skip_next_instruction()?;
                 }
             }
-            4 => {
-                debug!("SNE V{:X}, {:X}", x, byte);
+            SneByte(x, byte) => {
                 if self.reg[x as usize] != byte {
-                    self.pc += 2;
+                    self.skip_next_instruction()?;
                 }
             }
-            5 => {
-                debug!("SE V{:X}, V{:X}", x, y);
+            SeReg(x, y) => {
                 if self.reg[x as usize] == self.reg[y as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction()?;
                 }
             }
-            6 => {
-                debug!("LD V{:X}, {:X}", x, byte);
-                self.reg[x as usize] = byte;
-            }
-            7 => {
-                debug!("ADD V{:X}, {:X}", x, byte);
-                self.reg[x as usize] = self.reg[x as usize].wrapping_add(byte);
+            SaveRange(x, y) => {
+                let values: Vec<u8> = Self::register_range(x, y).map(|r| self.reg[r]).collect();
+                self.write_range(self.reg_i as usize, &values)?;
             }
-            8 => match last_digit {
-                0 => {
-                    debug!("LD V{:X}, V{:X}", x, y);
-                    self.reg[x as usize] = self.reg[y as usize];
-                }
-                1 => {
-                    debug!("OR V{:X}, V{:X}", x, y);
-                    self.reg[x as usize] |= self.reg[y as usize];
-                }
-                2 => {
-                    debug!("AND V{:X}, V{:X}", x, y);
-                    self.reg[x as usize] &= self.reg[y as usize];
-                }
-                3 => {
-                    debug!("XOR V{:X}, V{:X}", x, y);
-                    self.reg[x as usize] ^= self.reg[y as usize];
-                }
-                4 => {
-                    debug!("ADD V{:X}, V{:X}", x, y);
-                    let results = self.reg[x as usize].overflowing_add(self.reg[y as usize]);
-                    self.reg[x as usize] = results.0;
-                    self.reg[15] = results.1 as u8;
+            LoadRange(x, y) => {
+                let registers: Vec<usize> = Self::register_range(x, y).collect();
+                let values = self.read_range(self.reg_i as usize, registers.len())?;
+                for (r, value) in registers.into_iter().zip(values) {
+                    self.reg[r] = value;
                 }
-                5 => {
-                    debug!("SUB V{:X}, V{:X}", x, y);
-                    let (result, rev_carry) =
-                        self.reg[x as usize].overflowing_sub(self.reg[y as usize]);
-                    self.reg[x as usize] = result;
-                    self.reg[15] = !rev_carry as u8;
-                }
-                6 => {
-                    debug!("SHR V{:X}", y);
-                    let carry = self.reg[x as usize] & 0b00000001;
-                    self.reg[x as usize] >>= 1;
-                    self.reg[15] = carry;
+            }
+            LdByte(x, byte) => self.reg[x as usize] = byte,
+            AddByte(x, byte) => self.reg[x as usize] = self.reg[x as usize].wrapping_add(byte),
+            LdReg(x, y) => self.reg[x as usize] = self.reg[y as usize],
+            Or(x, y) => {
+                self.reg[x as usize] |= self.reg[y as usize];
+                if self.quirks.vf_reset {
+                    self.reg[15] = 0;
                 }
-                7 => {
-                    debug!("SUBN V{:X}, V{:X}", x, y);
-                    let (result, rev_carry) =
-                        self.reg[y as usize].overflowing_sub(self.reg[y as usize]);
-                    self.reg[x as usize] = result;
-                    self.reg[15] = !rev_carry as u8;
+            }
+            And(x, y) => {
+                self.reg[x as usize] &= self.reg[y as usize];
+                if self.quirks.vf_reset {
+                    self.reg[15] = 0;
                 }
-                0xE => {
-                    debug!("SHL V{:X}", x);
-                    let (result, carry) =
-                        self.reg[x as usize].overflowing_shl(self.reg[y as usize] as u32);
-                    self.reg[x as usize] = result;
-                    self.reg[15] = carry as u8;
+            }
+            Xor(x, y) => {
+                self.reg[x as usize] ^= self.reg[y as usize];
+                if self.quirks.vf_reset {
+                    self.reg[15] = 0;
                 }
-                _ => debug!("0x8__{:X}", last_digit),
-            },
-            9 => {
-                debug!("SNE V{:X}, V{:X}", x, y);
+            }
+            AddReg(x, y) => {
+                let results = self.reg[x as usize].overflowing_add(self.reg[y as usize]);
+                self.reg[x as usize] = results.0;
+                self.reg[15] = results.1 as u8;
+            }
+            Sub(x, y) => {
+                let (result, rev_carry) =
+                    self.reg[x as usize].overflowing_sub(self.reg[y as usize]);
+                self.reg[x as usize] = result;
+                self.reg[15] = !rev_carry as u8;
+            }
+            Shr(x, y) => {
+                let source = self.shift_source(x, y);
+                self.reg[x as usize] = source >> 1;
+                self.reg[15] = source & 0b00000001;
+            }
+            Subn(x, y) => {
+                let (result, rev_carry) =
+                    self.reg[y as usize].overflowing_sub(self.reg[x as usize]);
+                self.reg[x as usize] = result;
+                self.reg[15] = !rev_carry as u8;
+            }
+            Shl(x, y) => {
+                let source = self.shift_source(x, y);
+                self.reg[x as usize] = source << 1;
+                self.reg[15] = source >> 7;
+            }
+            SneReg(x, y) => {
                 if self.reg[x as usize] != self.reg[y as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction()?;
                 }
             }
-            0xA => {
-                debug!("LD I, {:X}", addr);
-                self.reg_i = addr;
-            }
-            0xB => {
-                debug!("JP V0, {:X}", addr);
-                self.pc = self.reg[0] as u16 + addr;
+            LdI(addr) => self.reg_i = addr,
+            JpV0(addr) => {
+                let offset = if self.quirks.jump_with_vx {
+                    self.reg[(addr >> 8) as usize]
+                } else {
+                    self.reg[0]
+                };
+                self.pc = offset as u16 + addr;
             }
-            0xC => {
-                debug!("RND V{:X}, {:X}", x, byte);
-                self.reg[x as usize] = rand::thread_rng().gen::<u8>()  & byte;
-                // warn!("RND unimplemented!");
+            Rnd(x, byte) => {
+                self.reg[x as usize] = self.rng.next_byte() & byte;
             }
-            0xD => {
-                debug!("DRW V{:X}, V{:X}, {:X}", x, y, last_digit);
+            Drw(x, y, n) => {
                 let (vx, vy) = (self.reg[x as usize], self.reg[y as usize]);
-                let mut sprite = Vec::new();
-                for i in 0..last_digit as usize {
-                    sprite.push(self.ram[self.reg_i as usize + i]);
-                }
-                let result = self.draw_sprite(
-                    vx as usize,
-                    vy as usize,
-                    &sprite,
-                );
+                let start = self.reg_i as usize;
+                let planes = self.plane_count();
+                let result = if n == 0 {
+                    // SUPER-CHIP 16x16 sprite
+                    let sprite = self.read_range(start, 32 * planes)?;
+                    self.draw_wide_sprite(vx as usize, vy as usize, &sprite)
+                } else {
+                    let sprite = self.read_range(start, n as usize * planes)?;
+                    self.draw_sprite(vx as usize, vy as usize, &sprite)
+                };
                 self.reg[15] = result as u8;
-            }
-            0xE => match byte {
-                0x9E => {
-                    debug!("SKP V{:X}", x);
-                    if self.keyboard[self.reg[x as usize] as usize] {
-                        self.pc += 2;
-                    }
-                }
-                0xA1 => {
-                    debug!("SKNP V{:X}", x);
-                    if !self.keyboard[self.reg[x as usize] as usize] {
-                        self.pc += 2;
-                    }
-                }
-                _ => debug!("0xE_{:X}", byte),
-            },
-            0xF => match byte {
-                0x07 => {
-                    debug!("LD V{:X}, DT", x);
-                    self.reg[x as usize] = self.tim_delay;
-                }
-                0x0A => {
-                    debug!("LD V{:X}, K", x);
-                    self.execution_state =
-                        super::ExecutionState::WaitingForKey(self.reg[x as usize] as usize);
-                }
-                0x15 => {
-                    debug!("LD DT, V{:X}", x);
-                    self.tim_delay = self.reg[x as usize];
-                }
-                0x18 => {
-                    debug!("LD ST, V{:X}", x);
-                    self.tim_sound = self.reg[x as usize];
-                }
-                0x1E => {
-                    debug!("ADD I, V{:X}", x);
-                    self.reg_i += self.reg[x as usize] as u16;
-                }
-                0x29 => {
-                    debug!("LD F, V{:X}", x);
-                    self.reg_i = (self.reg[x as usize] * 5) as u16;
-                }
-                0x33 => {
-                    debug!("LD B, V{:X}", x);
-                    self.ram[self.reg_i as usize] = self.reg[x as usize] / 100;
-                    self.ram[self.reg_i as usize + 1] = (self.reg[x as usize] / 10) % 10;
-                    self.ram[self.reg_i as usize + 2] = self.reg[x as usize] % 10;
-                    // warn!("BCD isn't implemented!");
+                self.record_sprite(SpriteDraw {
+                    pc: self.pc.wrapping_sub(2),
+                    address: start as u16,
+                    x: vx,
+                    y: vy,
+                    height: if n == 0 { 16 } else { n },
+                    wide: n == 0,
+                    collided: result,
+                });
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
                 }
-                0x55 => {
-                    debug!("LD [I], V{:X}", x);
-                    for i in 0..=x as usize {
-                        self.ram[self.reg_i as usize + i] = self.reg[i];
-                    }
+            }
+            Skp(x) => {
+                if self.keyboard[self.key_index(x)?] {
+                    self.skip_next_instruction()?;
                 }
-                0x65 => {
-                    debug!("LD V{:X}, [I]", x);
-                    for i in 0..=x as usize {
-                        self.reg[i] = self.ram[self.reg_i as usize + i];
-                    }
+            }
+            Sknp(x) => {
+                if !self.keyboard[self.key_index(x)?] {
+                    self.skip_next_instruction()?;
                 }
-                _ => debug!("0xF_{:X}", byte),
-            },
-            _ => debug!("0x{:X}{:X}", instruction[0], instruction[1]),
+            }
+            LdLongI => {
+                let pc = self.pc as usize;
+                let long_addr = u16::from_be_bytes([self.read(pc)?, self.read(pc + 1)?]);
+                trace!("Loading I with {:X}", long_addr);
+                self.reg_i = long_addr;
+                self.pc = self.pc.wrapping_add(2);
+            }
+            Plane(n) => self.planes = n & 0b11,
+            Audio => {
+                let pattern = self.read_range(self.reg_i as usize, 16)?;
+                self.audio_pattern.copy_from_slice(&pattern);
+            }
+            LdVxDt(x) => self.reg[x as usize] = self.tim_delay,
+            LdVxK(x) => {
+                self.execution_state = super::ExecutionState::WaitingForKey(x as usize);
+            }
+            LdDtVx(x) => self.tim_delay = self.reg[x as usize],
+            LdStVx(x) => self.tim_sound = self.reg[x as usize],
+            AddI(x) => self.reg_i = self.offset_i(self.reg[x as usize] as u16)?,
+            LdF(x) => {
+                self.reg_i = (super::SPRITES_ADDR + self.reg[x as usize] as usize * 5) as u16;
+            }
+            LdHf(x) => {
+                self.reg_i =
+                    (super::BIG_SPRITES_ADDR + (self.reg[x as usize] & 0xF) as usize * 10) as u16;
+            }
+            LdB(x) => {
+                let value = self.reg[x as usize];
+                self.write_range(
+                    self.reg_i as usize,
+                    &[value / 100, (value / 10) % 10, value % 10],
+                )?;
+            }
+            Pitch(x) => self.pitch = self.reg[x as usize],
+            LdIVx(x) => {
+                let values = self.reg;
+                self.write_range(self.reg_i as usize, &values[..=x as usize])?;
+                self.increment_i_after_memory_access(x)?;
+            }
+            LdVxI(x) => {
+                let values = self.read_range(self.reg_i as usize, x as usize + 1)?;
+                self.reg[..=x as usize].copy_from_slice(&values);
+                self.increment_i_after_memory_access(x)?;
+            }
+            LdRVx(x) => {
+                self.rpl[..=x as usize].copy_from_slice(&self.reg[..=x as usize]);
+                self.save_rpl();
+            }
+            LdVxR(x) => {
+                self.reg[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+            }
         }
+        Ok(())
     }
 }
 
@@ -251,7 +290,7 @@ impl super::Cpu {
 mod test {
     use simple_logger::SimpleLogger;
 
-    use crate::cpu::{Cpu, ExecutionState};
+    use crate::cpu::{Cpu, CpuEvent, CpuFault, ExecutionState, FaultPolicy, QuirkProfile};
 
     #[test]
     fn test_cls() {
@@ -259,16 +298,16 @@ mod test {
         let mut cpu = Cpu::new();
         for i in cpu.vram.iter_mut() {
             for j in i.iter_mut() {
-                *j = true;
+                *j = 1;
             }
         }
         cpu.execute_instruction([0x00, 0xE0]);
         let or_result = cpu
             .vram
             .iter()
-            .map(|x| x.iter().fold(false, |x, &y| x | y))
-            .fold(false, |x, y| x | y);
-        assert_eq!(or_result, false, "Screen should be black");
+            .map(|x| x.iter().fold(0, |x, &y| x | y))
+            .fold(0, |x, y| x | y);
+        assert_eq!(or_result, 0, "Screen should be black");
     }
 
     #[test]
@@ -292,7 +331,10 @@ mod test {
         cpu.execute_instruction([0x00, 0xEE]);
         assert_eq!(
             cpu.execution_state,
-            ExecutionState::Paused,
+            ExecutionState::Faulted {
+                fault: CpuFault::StackUnderflow,
+                pc: 0x1FE
+            },
             "This incorrect command should stop the execution."
         )
     }
@@ -399,21 +441,317 @@ mod test {
     }
 
     #[test]
-    fn test_parse() {
-        let result = Cpu::parse_instruction([0x12, 0x34]);
-        assert_eq!(result, (0x2, 0x3, 0x34, 0x234))
+    fn test_skp() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.reg[3] = 0xA;
+        cpu.execute_instruction([0xE3, 0x9E]);
+        assert_eq!(cpu.pc, 0x200, "Should not skip while key A is up");
+        cpu.handle_event(CpuEvent::KeyPressed(0xA));
+        cpu.execute_instruction([0xE3, 0x9E]);
+        assert_eq!(cpu.pc, 0x202, "Should skip while key A is down");
+        cpu.handle_event(CpuEvent::KeyReleased(0xA));
+        cpu.execute_instruction([0xE3, 0xA1]);
+        assert_eq!(cpu.pc, 0x204, "SKNP should skip once key A is released");
     }
 
     #[test]
-    #[ignore = "not yet needed"]
-    fn test_nopanic() {
+    fn test_ld_key() {
         let _ = SimpleLogger::new().init();
         let mut cpu = Cpu::new();
-        for i in 0..=255u8 {
-            for j in 0..=255u8 {
-               cpu.execute_instruction([i, j]); 
+        cpu.execution_state = ExecutionState::Running;
+        cpu.reg[5] = 0xF;
+        cpu.execute_instruction([0xF5, 0x0A]);
+        assert_eq!(
+            cpu.execution_state,
+            ExecutionState::WaitingForKey(5),
+            "The CPU should wait for a key to be stored in V5"
+        );
+        cpu.handle_event(CpuEvent::KeyPressed(7));
+        assert_eq!(cpu.reg[5], 7, "The pressed key should be stored in V5");
+        assert_eq!(cpu.execution_state, ExecutionState::Running);
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::with_quirks(QuirkProfile::CosmacVip.quirks());
+        cpu.reg[1] = 0b1000_0001;
+        cpu.execute_instruction([0x80, 0x16]);
+        assert_eq!(cpu.reg[0], 0b0100_0000, "VIP should shift V1 into V0");
+        assert_eq!(cpu.reg[15], 1, "VF should hold the bit shifted out");
+        cpu.execute_instruction([0x80, 0x1E]);
+        assert_eq!(cpu.reg[0], 0b0000_0010, "VIP should shift V1 into V0");
+        assert_eq!(cpu.reg[15], 1, "VF should hold the bit shifted out");
+
+        let mut cpu = Cpu::with_quirks(QuirkProfile::SchipModern.quirks());
+        cpu.reg[0] = 0b0100_0000;
+        cpu.reg[1] = 0b1000_0001;
+        cpu.execute_instruction([0x80, 0x1E]);
+        assert_eq!(cpu.reg[0], 0b1000_0000, "SCHIP should shift V0 in place");
+        assert_eq!(cpu.reg[15], 0, "VF should hold the bit shifted out");
+    }
+
+    #[test]
+    fn test_memory_increment_quirk() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::with_quirks(QuirkProfile::CosmacVip.quirks());
+        cpu.reg_i = 0x300;
+        cpu.execute_instruction([0xF2, 0x55]);
+        assert_eq!(cpu.reg_i, 0x303, "VIP should leave I past the stored registers");
+
+        let mut cpu = Cpu::with_quirks(QuirkProfile::SchipModern.quirks());
+        cpu.reg_i = 0x300;
+        cpu.execute_instruction([0xF2, 0x65]);
+        assert_eq!(cpu.reg_i, 0x300, "SCHIP should leave I unchanged");
+    }
+
+    #[test]
+    fn test_jump_quirk() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::with_quirks(QuirkProfile::CosmacVip.quirks());
+        cpu.reg[0] = 1;
+        cpu.reg[3] = 2;
+        cpu.execute_instruction([0xB3, 0x00]);
+        assert_eq!(cpu.pc, 0x301, "VIP should add V0");
+
+        let mut cpu = Cpu::with_quirks(QuirkProfile::SchipModern.quirks());
+        cpu.reg[0] = 1;
+        cpu.reg[3] = 2;
+        cpu.execute_instruction([0xB3, 0x00]);
+        assert_eq!(cpu.pc, 0x302, "SCHIP should add V3");
+    }
+
+    #[test]
+    fn test_vf_reset_quirk() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::with_quirks(QuirkProfile::CosmacVip.quirks());
+        cpu.reg[15] = 1;
+        cpu.execute_instruction([0x80, 0x11]);
+        assert_eq!(cpu.reg[15], 0, "VIP should reset VF on OR");
+
+        let mut cpu = Cpu::with_quirks(QuirkProfile::SchipModern.quirks());
+        cpu.reg[15] = 1;
+        cpu.execute_instruction([0x80, 0x11]);
+        assert_eq!(cpu.reg[15], 1, "SCHIP should leave VF alone");
+    }
+
+    #[test]
+    fn test_schip_resolution() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.screen_size(), (64, 32));
+        cpu.execute_instruction([0x00, 0xFF]);
+        assert_eq!(cpu.screen_size(), (128, 64), "HIGH should switch to 128x64");
+        cpu.execute_instruction([0x00, 0xFE]);
+        assert_eq!(cpu.screen_size(), (64, 32), "LOW should switch back to 64x32");
+    }
+
+    #[test]
+    fn test_schip_big_font() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.reg[2] = 3;
+        cpu.execute_instruction([0xF2, 0x30]);
+        assert_eq!(cpu.reg_i, 0x50 + 30, "I should point at the big 3");
+        assert_eq!(cpu.ram[cpu.reg_i as usize..cpu.reg_i as usize + 2], [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_schip_rpl() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.reg[..3].copy_from_slice(&[1, 2, 3]);
+        cpu.execute_instruction([0xF2, 0x75]);
+        cpu.reg = [0; 16];
+        cpu.execute_instruction([0xF1, 0x85]);
+        assert_eq!(cpu.reg[..3], [1, 2, 0], "V0 and V1 should be restored");
+    }
+
+    #[test]
+    fn test_exit() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.execution_state = ExecutionState::Running;
+        cpu.execute_instruction([0x00, 0xFD]);
+        assert_eq!(cpu.execution_state, ExecutionState::Halted);
+    }
+
+    #[test]
+    fn test_xo_long_load() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0xF0, 0x00, 0xAB, 0xCD]);
+        cpu.execute_next_instruction();
+        assert_eq!(cpu.reg_i, 0xABCD, "I should be loaded with the 16-bit address");
+        assert_eq!(cpu.pc, 0x204, "PC should move past the whole instruction");
+    }
+
+    #[test]
+    fn test_xo_skip_long_instruction() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD]);
+        cpu.execute_next_instruction();
+        assert_eq!(cpu.pc, 0x206, "Skips should jump over all 4 bytes of F000");
+    }
+
+    #[test]
+    fn test_xo_register_range() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.reg_i = 0x400;
+        cpu.reg[2..5].copy_from_slice(&[1, 2, 3]);
+        cpu.execute_instruction([0x54, 0x22]);
+        assert_eq!(cpu.ram[0x400..0x403], [3, 2, 1], "V4 down to V2 should be saved");
+        assert_eq!(cpu.reg_i, 0x400, "I should not change");
+        cpu.execute_instruction([0x57, 0x93]);
+        assert_eq!(cpu.reg[7..10], [3, 2, 1], "V7 to V9 should be loaded");
+    }
+
+    #[test]
+    fn test_xo_audio() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.reg_i = 0x400;
+        cpu.ram[0x400..0x410].copy_from_slice(&[0xAA; 16]);
+        cpu.execute_instruction([0xF0, 0x02]);
+        assert_eq!(cpu.audio_pattern(), &[0xAA; 16]);
+        cpu.reg[1] = 112;
+        cpu.execute_instruction([0xF1, 0x3A]);
+        assert!((cpu.playback_rate() - 8000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_rnd_seeded() {
+        let _ = SimpleLogger::new().init();
+        let mut a = Cpu::new();
+        let mut b = Cpu::new();
+        a.rng.reseed(7);
+        b.rng.reseed(7);
+        for _ in 0..16 {
+            a.execute_instruction([0xC0, 0xFF]);
+            b.execute_instruction([0xC0, 0xFF]);
+            assert_eq!(a.reg[0], b.reg[0], "The same seed should give the same bytes");
+        }
+        a.execute_instruction([0xC1, 0x0F]);
+        assert_eq!(a.reg[1] & 0xF0, 0, "The result should be masked with NN");
+    }
+
+    #[test]
+    fn test_fault_fetch_past_ram() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xFFFF;
+        cpu.resume();
+        cpu.clock_cycle();
+        assert_eq!(
+            cpu.execution_state,
+            ExecutionState::Faulted {
+                fault: CpuFault::OutOfBounds(0x10000),
+                pc: 0xFFFF
+            },
+            "Fetching past the end of RAM should fault"
+        );
+        let cycle = cpu.cycle();
+        cpu.clock_cycle();
+        assert_eq!(cpu.cycle(), cycle, "Nothing should run while faulted");
+        cpu.clear_fault();
+        assert_eq!(cpu.execution_state, ExecutionState::Paused);
+    }
+
+    #[test]
+    fn test_fault_bad_key() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.reg[0] = 0x10;
+        cpu.execute_instruction([0xE0, 0x9E]);
+        assert_eq!(
+            cpu.execution_state,
+            ExecutionState::Faulted {
+                fault: CpuFault::BadKey(0x10),
+                pc: 0x1FE
+            }
+        );
+        let mut cpu = Cpu::new();
+        cpu.fault_policy = FaultPolicy::Wrap;
+        cpu.reg[0] = 0x10;
+        cpu.handle_event(CpuEvent::KeyPressed(0));
+        cpu.execute_instruction([0xE0, 0x9E]);
+        assert_eq!(cpu.pc, 0x202, "Key 0x10 should wrap around to key 0");
+    }
+
+    #[test]
+    fn test_fault_invalid_opcode() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.execute_instruction([0xFF, 0xFF]);
+        assert_eq!(
+            cpu.execution_state,
+            ExecutionState::Faulted {
+                fault: CpuFault::InvalidOpcode([0xFF, 0xFF]),
+                pc: 0x1FE
+            }
+        );
+    }
+
+    #[test]
+    fn test_fault_policy() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        cpu.fault_policy = FaultPolicy::Warn;
+        cpu.reg_i = 0xFFFF;
+        cpu.reg[0] = 0xAB;
+        cpu.reg[1] = 0xCD;
+        cpu.execute_instruction([0xF1, 0x55]);
+        assert_eq!(cpu.execution_state, ExecutionState::Paused, "Warn should not stop");
+        assert_eq!(cpu.ram[0xFFFF], 0, "The faulting write should be abandoned");
+        assert_eq!(cpu.ram[0], 0xF0, "The faulting write should be abandoned");
+
+        let mut cpu = Cpu::new();
+        cpu.fault_policy = FaultPolicy::Wrap;
+        cpu.quirks.memory_increment = crate::cpu::MemoryIncrement::XPlusOne;
+        cpu.reg_i = 0xFFFF;
+        cpu.reg[0] = 0xAB;
+        cpu.reg[1] = 0xCD;
+        cpu.execute_instruction([0xF1, 0x55]);
+        assert_eq!(cpu.ram[0xFFFF], 0xAB);
+        assert_eq!(cpu.ram[0], 0xCD, "The write should wrap around to address 0");
+        assert_eq!(cpu.reg_i, 1, "I should wrap around too");
+        for _ in 0..17 {
+            cpu.execute_instruction([0x23, 0x00]);
+        }
+        assert_eq!(cpu.sp, 1, "The stack pointer should wrap around");
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let _ = SimpleLogger::new().init();
+        let mut cpu = Cpu::new();
+        for _ in 0..17 {
+            cpu.execute_instruction([0x23, 0x00]);
+        }
+        assert_eq!(
+            cpu.execution_state,
+            ExecutionState::Faulted {
+                fault: CpuFault::StackOverflow,
+                pc: 0x2FE
+            }
+        );
+        assert_eq!(cpu.sp, 16);
+    }
+
+    #[test]
+    fn test_nopanic() {
+        let _ = SimpleLogger::new().init();
+        for &policy in FaultPolicy::ALL.iter() {
+            let mut cpu = Cpu::new();
+            cpu.fault_policy = policy;
+            for i in 0..=255u8 {
+                for j in 0..=255u8 {
+                    cpu.execute_instruction([i, j]);
+                }
             }
         }
     }
-    
 }
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{ExecutionState, RAM_SIZE};
+
+/// Something a program did that real hardware would not survive in a well-defined way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CpuFault {
+    /// `CALL` with all 16 stack entries in use.
+    StackOverflow,
+    /// `RET` with an empty stack.
+    StackUnderflow,
+    /// A memory access past the end of RAM.
+    OutOfBounds(usize),
+    /// Two bytes that do not decode to any instruction.
+    InvalidOpcode([u8; 2]),
+    /// `SKP`/`SKNP` on a register holding something other than a key from 0 to F.
+    BadKey(u8),
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuFault::StackOverflow => write!(f, "stack overflow"),
+            CpuFault::StackUnderflow => write!(f, "return with an empty stack"),
+            CpuFault::OutOfBounds(addr) => write!(f, "out of bounds access at 0x{:X}", addr),
+            CpuFault::InvalidOpcode(op) => write!(f, "invalid opcode {:02X}{:02X}", op[0], op[1]),
+            CpuFault::BadKey(key) => write!(f, "no such key 0x{:X}", key),
+        }
+    }
+}
+
+impl std::error::Error for CpuFault {}
+
+/// What to do when the program faults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FaultPolicy {
+    /// Stop in `ExecutionState::Faulted`.
+    Halt,
+    /// Log a warning, skip the offending instruction and carry on.
+    Warn,
+    /// Do what hardware would: addresses, the stack pointer and key numbers wrap around.
+    /// Invalid opcodes are skipped silently.
+    Wrap,
+}
+
+impl FaultPolicy {
+    pub const ALL: [FaultPolicy; 3] = [FaultPolicy::Halt, FaultPolicy::Warn, FaultPolicy::Wrap];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FaultPolicy::Halt => "Halt",
+            FaultPolicy::Warn => "Warn and continue",
+            FaultPolicy::Wrap => "Wrap like hardware",
+        }
+    }
+}
+
+impl super::Cpu {
+    /// Apply the fault policy to a fault raised by the instruction at `pc`.
+    pub(super) fn fault(&mut self, fault: CpuFault, pc: u16) {
+        match self.fault_policy {
+            FaultPolicy::Halt => {
+                error!("{} at 0x{:X}. Execution halted.", fault, pc);
+                self.execution_state = ExecutionState::Faulted { fault, pc };
+            }
+            FaultPolicy::Warn => warn!("{} at 0x{:X}, skipping instruction", fault, pc),
+            FaultPolicy::Wrap => debug!("{} at 0x{:X}, skipping instruction", fault, pc),
+        }
+    }
+
+    /// Go back to `Paused` after a fault, e.g. once the state has been fixed by hand.
+    pub fn clear_fault(&mut self) {
+        if let ExecutionState::Faulted { .. } = self.execution_state {
+            self.execution_state = ExecutionState::Paused;
+        }
+    }
+
+    /// Resolve an address, wrapping it around under `FaultPolicy::Wrap`.
+    pub(super) fn address(&self, addr: usize) -> Result<usize, CpuFault> {
+        if addr < RAM_SIZE {
+            Ok(addr)
+        } else if self.fault_policy == FaultPolicy::Wrap {
+            Ok(addr % RAM_SIZE)
+        } else {
+            Err(CpuFault::OutOfBounds(addr))
+        }
+    }
+
+    pub(super) fn read(&self, addr: usize) -> Result<u8, CpuFault> {
+        Ok(self.ram[self.address(addr)?])
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), CpuFault> {
+        let addr = self.address(addr)?;
+        self.ram[addr] = value;
+        Ok(())
+    }
+
+    /// Read `len` bytes starting at `addr`.
+    pub(super) fn read_range(&mut self, addr: usize, len: usize) -> Result<Vec<u8>, CpuFault> {
+        let bytes = (addr..addr + len)
+            .map(|addr| self.read(addr))
+            .collect::<Result<_, _>>()?;
+        self.watch_read(addr, len);
+        Ok(bytes)
+    }
+
+    /// Write `bytes` starting at `addr`. Nothing is written if any of it would fault.
+    pub(super) fn write_range(&mut self, addr: usize, bytes: &[u8]) -> Result<(), CpuFault> {
+        if let Some(last) = bytes.len().checked_sub(1) {
+            self.address(addr + last)?;
+        }
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.write(addr + offset, byte)?;
+        }
+        self.watch_write(addr, bytes);
+        Ok(())
+    }
+
+    /// `I` plus `offset`, which may not leave the 16-bit address space except under `Wrap`.
+    pub(super) fn offset_i(&self, offset: u16) -> Result<u16, CpuFault> {
+        let addr = self.reg_i as usize + offset as usize;
+        Ok(self.address(addr)? as u16)
+    }
+
+    /// Key number in `Vx`, for `SKP`/`SKNP`.
+    pub(super) fn key_index(&self, x: u8) -> Result<usize, CpuFault> {
+        let key = self.reg[x as usize];
+        match key {
+            0..=0xF => Ok(key as usize),
+            _ if self.fault_policy == FaultPolicy::Wrap => Ok(key as usize & 0xF),
+            _ => Err(CpuFault::BadKey(key)),
+        }
+    }
+}
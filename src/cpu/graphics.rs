@@ -1,24 +1,137 @@
 impl super::Cpu {
-    fn draw_pixel(&mut self, mut x: usize, y: usize, px: bool) -> bool {
-        x %= 64;
-        let res = self.vram[x][y] && px;
-        self.vram[x][y] ^= px;
+    fn draw_pixel(&mut self, x: usize, y: usize, px: bool, plane: u8) -> bool {
+        if !px {
+            return false;
+        }
+        let res = self.vram[x][y] & plane != 0;
+        self.vram[x][y] ^= plane;
         res
     }
 
-    fn draw_line(&mut self, x: usize, y: usize, line: u8) -> bool {
+    fn draw_line(&mut self, x: usize, y: usize, line: u8, plane: u8) -> bool {
+        let (width, _) = self.screen_size();
         Decon::new(line)
             .enumerate()
-            .map(|(idx, px)| self.draw_pixel(x + idx, y % 32, px))
-            .any(|y| y)
+            .fold(false, |collided, (idx, px)| {
+                let mut x = x + idx;
+                if x >= width {
+                    if !self.quirks.wrap_x {
+                        return collided;
+                    }
+                    x %= width;
+                }
+                self.draw_pixel(x, y, px, plane) || collided
+            })
     }
 
+    /// Draw a sprite and report whether it erased any pixel. The starting
+    /// coordinates always wrap; the rest of the sprite wraps or gets clipped
+    /// at the edges depending on the quirks.
+    ///
+    /// When several XO-CHIP planes are selected, `sprite` holds the data for
+    /// each of them in turn.
     pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
-        sprite
-            .iter()
-            .enumerate()
-            .map(|(idx, &line)| self.draw_line(x, y + idx, line))
-            .any(|y| y)
+        self.draw_planes(x, y, sprite, 1)
+    }
+
+    /// Draw a 16 pixel wide SUPER-CHIP sprite, two bytes per row.
+    pub fn draw_wide_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.draw_planes(x, y, sprite, 2)
+    }
+
+    /// Number of XO-CHIP bitplanes currently selected.
+    pub fn plane_count(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
+    fn draw_planes(&mut self, x: usize, y: usize, sprite: &[u8], row_bytes: usize) -> bool {
+        let plane_len = sprite.len() / self.plane_count().max(1);
+        let mut data = sprite.chunks(plane_len.max(1));
+        let mut collided = false;
+        for plane in [1, 2].iter().copied() {
+            if self.planes & plane == 0 {
+                continue;
+            }
+            if let Some(data) = data.next() {
+                collided = self.draw_rows(x, y, data.chunks(row_bytes), plane) || collided;
+            }
+        }
+        collided
+    }
+
+    fn draw_rows<'a>(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: impl Iterator<Item = &'a [u8]>,
+        plane: u8,
+    ) -> bool {
+        let (width, height) = self.screen_size();
+        let (x, y) = (x % width, y % height);
+        rows.enumerate().fold(false, |collided, (idx, row)| {
+            let mut y = y + idx;
+            if y >= height {
+                if !self.quirks.wrap_y {
+                    return collided;
+                }
+                y %= height;
+            }
+            row.iter()
+                .enumerate()
+                .fold(collided, |collided, (byte, &line)| {
+                    self.draw_line(x + byte * 8, y, line, plane) || collided
+                })
+        })
+    }
+
+    /// Clear the selected planes.
+    pub fn clear_screen(&mut self) {
+        for column in self.vram.iter_mut() {
+            for px in column.iter_mut() {
+                *px &= !self.planes;
+            }
+        }
+    }
+
+    /// Switch between 64x32 and 128x64 mode, clearing every plane.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.vram = [[0; 64]; 128];
+    }
+
+    /// Move the selected planes by `dx`, `dy` pixels, filling the gap with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.screen_size();
+        let old = self.vram;
+        for x in 0..width {
+            for y in 0..height {
+                let (src_x, src_y) = (x as isize - dx, y as isize - dy);
+                let src = if (0..width as isize).contains(&src_x)
+                    && (0..height as isize).contains(&src_y)
+                {
+                    old[src_x as usize][src_y as usize]
+                } else {
+                    0
+                };
+                self.vram[x][y] = (old[x][y] & !self.planes) | (src & self.planes);
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
     }
 }
 
@@ -46,6 +159,71 @@ impl Iterator for Decon {
 
 #[cfg(test)]
 mod test {
+    use crate::cpu::{Cpu, QuirkProfile};
+
+    #[test]
+    fn test_collision_draws_whole_sprite() {
+        let mut cpu = Cpu::new();
+        cpu.draw_sprite(0, 0, &[0b1000_0000]);
+        assert!(cpu.draw_sprite(0, 0, &[0b1100_0000, 0b1000_0000]));
+        assert_eq!(cpu.vram[0][0], 0, "The colliding pixel should be erased");
+        assert_eq!(cpu.vram[1][0], 1, "Pixels after a collision should still be drawn");
+        assert_eq!(cpu.vram[0][1], 1, "Lines after a collision should still be drawn");
+    }
+
+    #[test]
+    fn test_clip_and_wrap() {
+        let mut cpu = Cpu::with_quirks(QuirkProfile::CosmacVip.quirks());
+        cpu.draw_sprite(60, 31, &[0xFF, 0xFF]);
+        assert_eq!(cpu.vram[63][31], 1);
+        assert_eq!(cpu.vram[0][31], 0, "VIP should clip at the right edge");
+        assert_eq!(cpu.vram[60][0], 0, "VIP should clip at the bottom edge");
+
+        let mut cpu = Cpu::with_quirks(QuirkProfile::XoChip.quirks());
+        cpu.draw_sprite(60 + 64, 31, &[0xFF, 0xFF]);
+        assert_eq!(cpu.vram[63][31], 1, "Starting coordinates should wrap");
+        assert_eq!(cpu.vram[0][31], 1, "XO-CHIP should wrap at the right edge");
+        assert_eq!(cpu.vram[60][0], 1, "XO-CHIP should wrap at the bottom edge");
+    }
+
+    #[test]
+    fn test_wide_sprite() {
+        let mut cpu = Cpu::new();
+        cpu.set_hires(true);
+        cpu.draw_wide_sprite(120, 0, &[0x80, 0x01]);
+        assert_eq!(cpu.vram[120][0], 1);
+        assert_eq!((cpu.vram[127][0], cpu.vram[7][0]), (0, 0));
+        cpu.draw_wide_sprite(0, 62, &[0x80, 0x01, 0x80, 0x01]);
+        assert_eq!((cpu.vram[0][62], cpu.vram[15][62], cpu.vram[0][63]), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut cpu = Cpu::new();
+        cpu.planes = 3;
+        cpu.draw_sprite(0, 0, &[0x80, 0xC0]);
+        assert_eq!((cpu.vram[0][0], cpu.vram[1][0]), (3, 2), "Each plane gets its own data");
+        cpu.planes = 2;
+        cpu.clear_screen();
+        assert_eq!((cpu.vram[0][0], cpu.vram[1][0]), (1, 0), "Only plane 2 is cleared");
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut cpu = Cpu::new();
+        cpu.vram[10][10] = 3;
+        cpu.scroll_down(3);
+        assert_eq!(cpu.vram[10][13], 1);
+        assert_eq!(cpu.vram[10][10], 2, "Unselected planes should not scroll");
+        cpu.scroll_right(4);
+        assert_eq!(cpu.vram[14][13], 1);
+        cpu.scroll_left(4);
+        cpu.scroll_left(4);
+        cpu.scroll_up(3);
+        assert_eq!(cpu.vram[6][10], 1);
+        assert_eq!((cpu.vram[10][13], cpu.vram[14][13], cpu.vram[6][13]), (0, 0, 0));
+    }
+
     #[test]
     fn test_decon() {
         let a: u8 = 0b10101010;
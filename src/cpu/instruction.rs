@@ -0,0 +1,403 @@
+use std::fmt;
+
+/// A decoded instruction. Register operands are register indices (0-F),
+/// `Vx`/`Vy` in the mnemonics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `00CN` - scroll the selected planes down by N pixels (SUPER-CHIP).
+    Scd(u8),
+    /// `00DN` - scroll the selected planes up by N pixels (XO-CHIP).
+    Scu(u8),
+    /// `00E0`
+    Cls,
+    /// `00EE`
+    Ret,
+    /// `00FB` - scroll right by 4 pixels (SUPER-CHIP).
+    Scr,
+    /// `00FC` - scroll left by 4 pixels (SUPER-CHIP).
+    Scl,
+    /// `00FD` - exit the interpreter (SUPER-CHIP).
+    Exit,
+    /// `00FE` - switch to 64x32 (SUPER-CHIP).
+    Low,
+    /// `00FF` - switch to 128x64 (SUPER-CHIP).
+    High,
+    /// `0NNN` - call a machine language routine, unsupported.
+    Sys(u16),
+    /// `1NNN`
+    Jp(u16),
+    /// `2NNN`
+    Call(u16),
+    /// `3XNN`
+    SeByte(u8, u8),
+    /// `4XNN`
+    SneByte(u8, u8),
+    /// `5XY0`
+    SeReg(u8, u8),
+    /// `5XY2` - save `Vx` to `Vy` at I (XO-CHIP).
+    SaveRange(u8, u8),
+    /// `5XY3` - load `Vx` to `Vy` from I (XO-CHIP).
+    LoadRange(u8, u8),
+    /// `6XNN`
+    LdByte(u8, u8),
+    /// `7XNN`
+    AddByte(u8, u8),
+    /// `8XY0`
+    LdReg(u8, u8),
+    /// `8XY1`
+    Or(u8, u8),
+    /// `8XY2`
+    And(u8, u8),
+    /// `8XY3`
+    Xor(u8, u8),
+    /// `8XY4`
+    AddReg(u8, u8),
+    /// `8XY5`
+    Sub(u8, u8),
+    /// `8XY6`
+    Shr(u8, u8),
+    /// `8XY7`
+    Subn(u8, u8),
+    /// `8XYE`
+    Shl(u8, u8),
+    /// `9XY0`
+    SneReg(u8, u8),
+    /// `ANNN`
+    LdI(u16),
+    /// `BNNN` - jumps to `NNN + V0`, or `XNN + VX` under the jump quirk.
+    JpV0(u16),
+    /// `CXNN`
+    Rnd(u8, u8),
+    /// `DXYN` - N = 0 draws a 16x16 sprite (SUPER-CHIP).
+    Drw(u8, u8, u8),
+    /// `EX9E`
+    Skp(u8),
+    /// `EXA1`
+    Sknp(u8),
+    /// `F000 NNNN` - load I with the 16-bit address in the next word (XO-CHIP).
+    LdLongI,
+    /// `FN01` - select the bitplanes in the mask N (XO-CHIP).
+    Plane(u8),
+    /// `F002` - load the audio pattern from I (XO-CHIP).
+    Audio,
+    /// `FX07`
+    LdVxDt(u8),
+    /// `FX0A`
+    LdVxK(u8),
+    /// `FX15`
+    LdDtVx(u8),
+    /// `FX18`
+    LdStVx(u8),
+    /// `FX1E`
+    AddI(u8),
+    /// `FX29`
+    LdF(u8),
+    /// `FX30` - point I at the big font digit (SUPER-CHIP).
+    LdHf(u8),
+    /// `FX33`
+    LdB(u8),
+    /// `FX3A` - set the audio pitch (XO-CHIP).
+    Pitch(u8),
+    /// `FX55`
+    LdIVx(u8),
+    /// `FX65`
+    LdVxI(u8),
+    /// `FX75` - save to the RPL flags (SUPER-CHIP).
+    LdRVx(u8),
+    /// `FX85` - load from the RPL flags (SUPER-CHIP).
+    LdVxR(u8),
+}
+
+/// Two bytes that do not form any known instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownOpcode(pub [u8; 2]);
+
+impl fmt::Display for UnknownOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode {:02X}{:02X}", self.0[0], self.0[1])
+    }
+}
+
+impl std::error::Error for UnknownOpcode {}
+
+/// Split an instruction into its `X`, `Y`, `NN` and `NNN` fields.
+fn parse(instruction: [u8; 2]) -> (u8, u8, u8, u16) {
+    let x = instruction[0] & 0x0F;
+    let y = instruction[1] >> 4;
+    let kk = instruction[1];
+    let nnn = kk as u16 + ((x as u16) << 8);
+    (x, y, kk, nnn)
+}
+
+impl Instruction {
+    pub fn decode(instruction: [u8; 2]) -> Result<Self, UnknownOpcode> {
+        use Instruction::*;
+        let (x, y, byte, addr) = parse(instruction);
+        let n = byte & 0x0F;
+        let decoded = match instruction[0] >> 4 {
+            0 => match instruction {
+                [0x00, 0xE0] => Cls,
+                [0x00, 0xEE] => Ret,
+                [0x00, 0xFB] => Scr,
+                [0x00, 0xFC] => Scl,
+                [0x00, 0xFD] => Exit,
+                [0x00, 0xFE] => Low,
+                [0x00, 0xFF] => High,
+                [0x00, b] if b & 0xF0 == 0xC0 => Scd(n),
+                [0x00, b] if b & 0xF0 == 0xD0 => Scu(n),
+                _ => Sys(addr),
+            },
+            1 => Jp(addr),
+            2 => Call(addr),
+            3 => SeByte(x, byte),
+            4 => SneByte(x, byte),
+            5 => match n {
+                0 => SeReg(x, y),
+                2 => SaveRange(x, y),
+                3 => LoadRange(x, y),
+                _ => return Err(UnknownOpcode(instruction)),
+            },
+            6 => LdByte(x, byte),
+            7 => AddByte(x, byte),
+            8 => match n {
+                0x0 => LdReg(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => AddReg(x, y),
+                0x5 => Sub(x, y),
+                0x6 => Shr(x, y),
+                0x7 => Subn(x, y),
+                0xE => Shl(x, y),
+                _ => return Err(UnknownOpcode(instruction)),
+            },
+            9 if n == 0 => SneReg(x, y),
+            0xA => LdI(addr),
+            0xB => JpV0(addr),
+            0xC => Rnd(x, byte),
+            0xD => Drw(x, y, n),
+            0xE => match byte {
+                0x9E => Skp(x),
+                0xA1 => Sknp(x),
+                _ => return Err(UnknownOpcode(instruction)),
+            },
+            0xF => match byte {
+                0x00 if x == 0 => LdLongI,
+                0x01 => Plane(x),
+                0x02 if x == 0 => Audio,
+                0x07 => LdVxDt(x),
+                0x0A => LdVxK(x),
+                0x15 => LdDtVx(x),
+                0x18 => LdStVx(x),
+                0x1E => AddI(x),
+                0x29 => LdF(x),
+                0x30 => LdHf(x),
+                0x33 => LdB(x),
+                0x3A => Pitch(x),
+                0x55 => LdIVx(x),
+                0x65 => LdVxI(x),
+                0x75 => LdRVx(x),
+                0x85 => LdVxR(x),
+                _ => return Err(UnknownOpcode(instruction)),
+            },
+            _ => return Err(UnknownOpcode(instruction)),
+        };
+        Ok(decoded)
+    }
+
+    pub fn encode(self) -> [u8; 2] {
+        use Instruction::*;
+        let word = |prefix: u16, rest: u16| ((prefix << 12) | (rest & 0x0FFF)).to_be_bytes();
+        let xy = |prefix: u16, x: u8, y: u8, n: u8| {
+            word(
+                prefix,
+                ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | (n as u16 & 0xF),
+            )
+        };
+        let xkk = |prefix: u16, x: u8, kk: u8| word(prefix, ((x as u16 & 0xF) << 8) | kk as u16);
+        match self {
+            Scd(n) => [0x00, 0xC0 | (n & 0xF)],
+            Scu(n) => [0x00, 0xD0 | (n & 0xF)],
+            Cls => [0x00, 0xE0],
+            Ret => [0x00, 0xEE],
+            Scr => [0x00, 0xFB],
+            Scl => [0x00, 0xFC],
+            Exit => [0x00, 0xFD],
+            Low => [0x00, 0xFE],
+            High => [0x00, 0xFF],
+            Sys(addr) => word(0x0, addr),
+            Jp(addr) => word(0x1, addr),
+            Call(addr) => word(0x2, addr),
+            SeByte(x, kk) => xkk(0x3, x, kk),
+            SneByte(x, kk) => xkk(0x4, x, kk),
+            SeReg(x, y) => xy(0x5, x, y, 0x0),
+            SaveRange(x, y) => xy(0x5, x, y, 0x2),
+            LoadRange(x, y) => xy(0x5, x, y, 0x3),
+            LdByte(x, kk) => xkk(0x6, x, kk),
+            AddByte(x, kk) => xkk(0x7, x, kk),
+            LdReg(x, y) => xy(0x8, x, y, 0x0),
+            Or(x, y) => xy(0x8, x, y, 0x1),
+            And(x, y) => xy(0x8, x, y, 0x2),
+            Xor(x, y) => xy(0x8, x, y, 0x3),
+            AddReg(x, y) => xy(0x8, x, y, 0x4),
+            Sub(x, y) => xy(0x8, x, y, 0x5),
+            Shr(x, y) => xy(0x8, x, y, 0x6),
+            Subn(x, y) => xy(0x8, x, y, 0x7),
+            Shl(x, y) => xy(0x8, x, y, 0xE),
+            SneReg(x, y) => xy(0x9, x, y, 0x0),
+            LdI(addr) => word(0xA, addr),
+            JpV0(addr) => word(0xB, addr),
+            Rnd(x, kk) => xkk(0xC, x, kk),
+            Drw(x, y, n) => xy(0xD, x, y, n),
+            Skp(x) => xkk(0xE, x, 0x9E),
+            Sknp(x) => xkk(0xE, x, 0xA1),
+            LdLongI => [0xF0, 0x00],
+            Plane(n) => xkk(0xF, n, 0x01),
+            Audio => [0xF0, 0x02],
+            LdVxDt(x) => xkk(0xF, x, 0x07),
+            LdVxK(x) => xkk(0xF, x, 0x0A),
+            LdDtVx(x) => xkk(0xF, x, 0x15),
+            LdStVx(x) => xkk(0xF, x, 0x18),
+            AddI(x) => xkk(0xF, x, 0x1E),
+            LdF(x) => xkk(0xF, x, 0x29),
+            LdHf(x) => xkk(0xF, x, 0x30),
+            LdB(x) => xkk(0xF, x, 0x33),
+            Pitch(x) => xkk(0xF, x, 0x3A),
+            LdIVx(x) => xkk(0xF, x, 0x55),
+            LdVxI(x) => xkk(0xF, x, 0x65),
+            LdRVx(x) => xkk(0xF, x, 0x75),
+            LdVxR(x) => xkk(0xF, x, 0x85),
+        }
+    }
+
+    /// Size of the instruction in bytes, including the address word of `F000 NNNN`.
+    pub fn size(self) -> u16 {
+        match self {
+            Instruction::LdLongI => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether execution can continue at the next instruction, i.e. this is not an
+    /// unconditional jump, a return or an exit.
+    pub fn falls_through(self) -> bool {
+        !matches!(
+            self,
+            Instruction::Jp(_) | Instruction::JpV0(_) | Instruction::Ret | Instruction::Exit
+        )
+    }
+
+    /// Whether this conditionally skips the next instruction.
+    pub fn is_skip(self) -> bool {
+        matches!(
+            self,
+            Instruction::SeByte(..)
+                | Instruction::SneByte(..)
+                | Instruction::SeReg(..)
+                | Instruction::SneReg(..)
+                | Instruction::Skp(_)
+                | Instruction::Sknp(_)
+        )
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            Scd(n) => write!(f, "SCD {}", n),
+            Scu(n) => write!(f, "SCU {}", n),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Sys(addr) => write!(f, "SYS 0x{:03X}", addr),
+            Jp(addr) => write!(f, "JP 0x{:03X}", addr),
+            Call(addr) => write!(f, "CALL 0x{:03X}", addr),
+            SeByte(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            SneByte(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            LdByte(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            AddByte(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            JpV0(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Rnd(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdLongI => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddI(x) => write!(f, "ADD I, V{:X}", x),
+            LdF(x) => write!(f, "LD F, V{:X}", x),
+            LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            LdB(x) => write!(f, "LD B, V{:X}", x),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            LdVxR(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Instruction, UnknownOpcode};
+
+    #[test]
+    fn test_parse() {
+        let result = parse([0x12, 0x34]);
+        assert_eq!(result, (0x2, 0x3, 0x34, 0x234))
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for i in 0..=0xFFFFu16 {
+            let bytes = i.to_be_bytes();
+            if let Ok(instruction) = Instruction::decode(bytes) {
+                assert_eq!(instruction.encode(), bytes, "{} should encode back", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let show = |bytes| Instruction::decode(bytes).unwrap().to_string();
+        assert_eq!(show([0x60, 0x12]), "LD V0, 0x12");
+        assert_eq!(show([0xD0, 0x15]), "DRW V0, V1, 5");
+        assert_eq!(show([0x22, 0x34]), "CALL 0x234");
+        assert_eq!(show([0xF3, 0x65]), "LD V3, [I]");
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(
+            Instruction::decode([0x81, 0x28]),
+            Err(UnknownOpcode([0x81, 0x28]))
+        );
+        assert_eq!(
+            Instruction::decode([0xE1, 0x00]),
+            Err(UnknownOpcode([0xE1, 0x00]))
+        );
+    }
+}
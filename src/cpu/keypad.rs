@@ -0,0 +1,36 @@
+//! Reading the state of the hex keypad, for the keypad window.
+
+use super::{Cpu, ExecutionState};
+
+impl Cpu {
+    /// Which keys are held down.
+    pub fn keyboard(&self) -> &[bool; 16] {
+        &self.keyboard
+    }
+
+    /// The register `LD Vx, K` is waiting to load a key into, if it is.
+    pub fn waiting_for_key(&self) -> Option<usize> {
+        match self.execution_state {
+            ExecutionState::WaitingForKey(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::{Cpu, CpuEvent};
+
+    #[test]
+    fn test_waiting_for_key() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0xF5, 0x0A]); // LD V5, K
+        assert_eq!(cpu.waiting_for_key(), None);
+        cpu.step();
+        assert_eq!(cpu.waiting_for_key(), Some(5));
+
+        cpu.handle_event(CpuEvent::KeyPressed(0x7));
+        assert_eq!(cpu.waiting_for_key(), None);
+        assert!(cpu.keyboard()[0x7]);
+    }
+}
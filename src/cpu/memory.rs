@@ -0,0 +1,96 @@
+//! Inspecting and editing RAM from the debugger.
+
+use super::{Cpu, BIG_SPRITES, BIG_SPRITES_ADDR, RAM_SIZE, SPRITES, SPRITES_ADDR};
+
+/// What a byte of RAM is used for, to colour it in the hex view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// The 4x5 or 8x10 built-in digit font.
+    Font,
+    /// The ROM as loaded at 0x200.
+    Rom,
+    Free,
+}
+
+impl Cpu {
+    /// Address of the first occurrence of `pattern` at or after `from`, wrapping around
+    /// to the start of RAM.
+    pub fn find(&self, pattern: &[u8], from: usize) -> Option<u16> {
+        if pattern.is_empty() || pattern.len() > RAM_SIZE {
+            return None;
+        }
+        let starts = RAM_SIZE - pattern.len() + 1;
+        (0..starts)
+            .map(|i| (from + i) % starts)
+            .find(|&addr| self.ram[addr..addr + pattern.len()] == *pattern)
+            .map(|addr| addr as u16)
+    }
+
+    /// Overwrite RAM at `addr` from the debugger, stopping at the end of RAM.
+    /// Returns the number of bytes written.
+    pub fn write_memory(&mut self, addr: u16, bytes: &[u8]) -> usize {
+        let addr = addr as usize;
+        let len = bytes.len().min(RAM_SIZE - addr);
+        self.ram[addr..addr + len].copy_from_slice(&bytes[..len]);
+        len
+    }
+
+    /// What the byte at `addr` holds, given the length of the loaded ROM.
+    pub fn region(&self, addr: u16, rom_len: usize) -> Region {
+        let addr = addr as usize;
+        let font = SPRITES_ADDR..SPRITES_ADDR + SPRITES.len();
+        let big_font = BIG_SPRITES_ADDR..BIG_SPRITES_ADDR + BIG_SPRITES.len();
+        if font.contains(&addr) || big_font.contains(&addr) {
+            Region::Font
+        } else if (0x200..0x200 + rom_len).contains(&addr) {
+            Region::Rom
+        } else {
+            Region::Free
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Region;
+    use crate::cpu::{Cpu, RAM_SIZE};
+
+    #[test]
+    fn test_find() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x12, 0x34, 0x00, 0x12, 0x34]);
+        assert_eq!(cpu.find(&[0x12, 0x34], 0x200), Some(0x200));
+        assert_eq!(cpu.find(&[0x12, 0x34], 0x201), Some(0x203));
+        assert_eq!(
+            cpu.find(&[0x12, 0x34], 0x204),
+            Some(0x200),
+            "Searching should wrap around to the start of RAM"
+        );
+        assert_eq!(cpu.find(&[0xAB, 0xCD, 0xEF], 0), None);
+        assert_eq!(cpu.find(&[], 0), None);
+    }
+
+    #[test]
+    fn test_write_memory() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.write_memory(0x300, &[1, 2, 3]), 3);
+        assert_eq!(&cpu.memory()[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(
+            cpu.write_memory((RAM_SIZE - 1) as u16, &[4, 5]),
+            1,
+            "Writes should stop at the end of RAM"
+        );
+        assert_eq!(cpu.memory()[RAM_SIZE - 1], 4);
+    }
+
+    #[test]
+    fn test_region() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0; 4]);
+        assert_eq!(cpu.region(0x000, 4), Region::Font);
+        assert_eq!(cpu.region(0x0EF, 4), Region::Font);
+        assert_eq!(cpu.region(0x0F0, 4), Region::Free);
+        assert_eq!(cpu.region(0x203, 4), Region::Rom);
+        assert_eq!(cpu.region(0x204, 4), Region::Free);
+    }
+}
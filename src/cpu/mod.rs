@@ -1,7 +1,53 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+mod condition;
+mod debug;
 mod execution;
+mod fault;
 mod graphics;
+mod instruction;
+mod keypad;
+mod memory;
+mod quirks;
+mod random;
+mod savestate;
+mod sprites;
+mod stack;
+mod trace;
 mod ui;
 
+pub use condition::{Condition, ConditionError};
+pub use debug::{Break, BreakReason, Breakpoint, Debugger, Register, Trigger};
+pub use fault::{CpuFault, FaultPolicy};
+pub use instruction::{Instruction, UnknownOpcode};
+pub use memory::Region;
+pub use quirks::{MemoryIncrement, QuirkProfile, Quirks};
+pub use random::{Random, RngAlgorithm};
+pub use savestate::{SaveState, SaveStateError};
+pub use sprites::{SpriteDraw, RECENT_SPRITES};
+pub use stack::{Frame, STACK_SIZE, STACK_WARNING};
+pub use trace::{first_divergence, Divergence, Trace};
+
+/// Address of the 4x5 hex digit font loaded by `FX29`.
+const SPRITES_ADDR: usize = 0x000;
+/// Address of the 8x10 SUPER-CHIP digit font loaded by `FX30`.
+const BIG_SPRITES_ADDR: usize = 0x050;
+
+/// Rate the delay and sound timers count down at, in emulated time.
+pub const TIMER_HZ: u32 = 60;
+/// Default instruction clock; fast enough for most CHIP-8 games.
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 700;
+
+/// XO-CHIP extends the address space to 64 KiB.
+pub const RAM_SIZE: usize = 0x10000;
+
+/// Width and height of the screen in low-resolution mode.
+pub const LORES_SIZE: (usize, usize) = (64, 32);
+/// Width and height of the screen in SUPER-CHIP high-resolution mode.
+pub const HIRES_SIZE: (usize, usize) = (128, 64);
+
 #[rustfmt::skip]
 static SPRITES: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0,
@@ -17,19 +63,51 @@ static SPRITES: [u8; 80] = [
     0xF0, 0x90, 0xF0, 0x90, 0x90,
     0xE0, 0x90, 0xE0, 0x90, 0xE0,
     0xF0, 0x80, 0x80, 0x80, 0xF0,
-    0xF0, 0x80, 0x80, 0x80, 0xF0,
+    0xE0, 0x90, 0x90, 0x90, 0xE0,
     0xF0, 0x80, 0xF0, 0x80, 0xF0,
     0xF0, 0x80, 0xF0, 0x80, 0x80,
 ];
 
-#[derive(Debug, PartialEq, Eq)]
+#[rustfmt::skip]
+static BIG_SPRITES: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0,
+];
+
+/// 64-bit FNV-1a hash, used to identify ROM images and machine states.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ExecutionState {
     Running,
     Paused,
+    /// Blocked on `LD Vx, K`; holds the index of the register the key goes into.
     WaitingForKey(usize),
+    /// The program exited with `EXIT` (`00FD`).
+    Halted,
+    /// Stopped by a fault under `FaultPolicy::Halt`; `pc` is the address of the faulting instruction.
+    Faulted { fault: CpuFault, pc: u16 },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CpuEvent {
     KeyPressed(usize),
     KeyReleased(usize),
@@ -37,27 +115,67 @@ pub enum CpuEvent {
 
 #[derive(Debug)]
 pub struct Cpu {
-    ram: [u8; 4096],
+    ram: [u8; RAM_SIZE],
     reg: [u8; 16],
     reg_i: u16,
     tim_delay: u8,
     tim_sound: u8,
     pc: u16,
     sp: u8,
-    stack: [u16; 16],
+    stack: [u16; STACK_SIZE],
     keyboard: [bool; 16],
-    pub vram: [[bool; 32]; 64],
+    /// Indexed as `vram[x][y]`. Only the top-left 64x32 pixels are used in low resolution.
+    /// Each pixel is a bitmask of the XO-CHIP bitplanes it is lit in, so its colour is 0-3.
+    pub vram: [[u8; 64]; 128],
+    hires: bool,
+    /// Bitmask of the XO-CHIP bitplanes drawing and scrolling apply to, set by `FN01`.
+    planes: u8,
+    /// XO-CHIP 1-bit audio pattern, loaded by `F002`.
+    audio_pattern: [u8; 16],
+    /// XO-CHIP pitch register, set by `FX3A`.
+    pitch: u8,
+    /// SUPER-CHIP "RPL user flags" saved and loaded by `FX75`/`FX85`.
+    rpl: [u8; 16],
+    /// Directory the RPL flags are persisted to, one file per ROM.
+    pub rpl_dir: Option<PathBuf>,
     pub execution_state: ExecutionState,
-    _cycle: usize,
-    rng: Box<rand::rngs::ThreadRng>,
+    pub quirks: Quirks,
+    /// What happens when the program faults.
+    pub fault_policy: FaultPolicy,
+    /// Set by `DXYN` under the display wait quirk, cleared on the next timer tick.
+    waiting_for_vblank: bool,
+    rom_hash: u64,
+    /// Instructions executed per second of emulated time.
+    pub instructions_per_second: u32,
+    /// Clock cycles since the ROM was loaded.
+    cycle: u64,
+    /// Progress towards the next timer tick, in units of `1 / (60 * instructions_per_second)` s.
+    timer_phase: u32,
+    /// Generator behind `CXNN`. Its seed is part of the machine state, so runs are reproducible.
+    pub rng: Random,
+    /// Breakpoints and watchpoints. Not part of the machine state.
+    pub debugger: Debugger,
+    /// Execution trace being written, if any. Not part of the machine state.
+    pub trace: Option<Trace>,
+    /// Sprites drawn lately, for the sprite viewer. Not part of the machine state.
+    recent_sprites: VecDeque<SpriteDraw>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Self {
-        let mut ram = [0;4096];
-        for (i, &byte) in SPRITES.iter().enumerate() {
-            ram[i] = byte;
-        }
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        let mut ram = [0; RAM_SIZE];
+        ram[SPRITES_ADDR..SPRITES_ADDR + SPRITES.len()].copy_from_slice(&SPRITES);
+        ram[BIG_SPRITES_ADDR..BIG_SPRITES_ADDR + BIG_SPRITES.len()].copy_from_slice(&BIG_SPRITES);
         Self {
             ram,
             reg: [0; 16],
@@ -66,12 +184,27 @@ impl Cpu {
             tim_sound: 0,
             pc: 0x200,
             sp: 0,
-            stack: [0; 16],
+            stack: [0; STACK_SIZE],
             keyboard: [false; 16],
-            vram: [[false; 32]; 64],
+            vram: [[0; 64]; 128],
+            hires: false,
+            planes: 1,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            rpl: [0; 16],
+            rpl_dir: None,
             execution_state: ExecutionState::Paused,
-            _cycle: 0,
-            rng: Box::new(rand::thread_rng())
+            quirks,
+            fault_policy: FaultPolicy::Halt,
+            waiting_for_vblank: false,
+            rom_hash: hash(&[]),
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            cycle: 0,
+            timer_phase: 0,
+            rng: Random::new(RngAlgorithm::Xorshift, rand::random()),
+            debugger: Debugger::default(),
+            trace: None,
+            recent_sprites: VecDeque::with_capacity(RECENT_SPRITES),
         }
     }
 
@@ -82,47 +215,217 @@ impl Cpu {
         self.reg = [0; 16];
         self.reg_i = 0;
         self.pc = 0x200;
-        self.stack = [0; 16];
+        self.stack = [0; STACK_SIZE];
         self.sp = 0;
         self.execution_state = ExecutionState::Paused;
         self.tim_delay = 0;
         self.tim_sound = 0;
-        self.vram = [[false; 32]; 64];
+        self.vram = [[0; 64]; 128];
+        self.hires = false;
+        self.planes = 1;
+        self.audio_pattern = [0; 16];
+        self.pitch = 64;
+        self.waiting_for_vblank = false;
+        self.cycle = 0;
+        self.timer_phase = 0;
+        self.rng.reset();
+        self.rom_hash = hash(rom);
+        self.debugger.last_break = None;
+        self.debugger.cancel_step();
+        self.recent_sprites.clear();
+        self.load_rpl();
+    }
+
+    /// Width and height of the screen in the current resolution.
+    pub fn screen_size(&self) -> (usize, usize) {
+        if self.hires {
+            HIRES_SIZE
+        } else {
+            LORES_SIZE
+        }
+    }
+
+    /// The XO-CHIP audio pattern, played back one bit per sample while the sound timer runs.
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    /// Audio pattern playback rate in bits per second, derived from the pitch register.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    fn rpl_path(&self) -> Option<PathBuf> {
+        let dir = self.rpl_dir.as_ref()?;
+        Some(dir.join(format!("{:016x}.rpl", self.rom_hash)))
+    }
+
+    fn load_rpl(&mut self) {
+        self.rpl = [0; 16];
+        if let Some(path) = self.rpl_path() {
+            if let Ok(flags) = std::fs::read(&path) {
+                let len = flags.len().min(self.rpl.len());
+                self.rpl[..len].copy_from_slice(&flags[..len]);
+            }
+        }
+    }
+
+    fn save_rpl(&self) {
+        if let Some(path) = self.rpl_path() {
+            let result = std::fs::create_dir_all(path.parent().unwrap())
+                .and_then(|_| std::fs::write(&path, self.rpl));
+            if let Err(e) = result {
+                log::error!("Failed to save RPL flags to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Hash of the complete machine state, for checking that two runs match.
+    pub fn state_hash(&self) -> u64 {
+        hash(&self.save_state().to_bytes())
+    }
+
+    /// Address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// The index register.
+    pub fn reg_i(&self) -> u16 {
+        self.reg_i
+    }
+
+    /// The whole address space.
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Hash of the ROM that was last loaded, used to key per-ROM settings.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
     }
 
     pub fn pause(&mut self) {
         if self.execution_state == ExecutionState::Running {
             self.execution_state = ExecutionState::Paused
         }
+        self.debugger.cancel_step();
     }
 
     pub fn resume(&mut self) {
         if self.execution_state == ExecutionState::Paused {
-            self.execution_state = ExecutionState::Running
+            self.execution_state = ExecutionState::Running;
+            self.debugger.last_break = None;
+        }
+    }
+
+    pub fn handle_event(&mut self, e: CpuEvent) {
+        match e {
+            CpuEvent::KeyPressed(key) if key < 16 => {
+                if self.keyboard[key] {
+                    // Auto-repeat from the host, the key is already down.
+                    return;
+                }
+                self.keyboard[key] = true;
+                if let ExecutionState::WaitingForKey(x) = self.execution_state {
+                    self.reg[x] = key as u8;
+                    self.execution_state = ExecutionState::Running;
+                }
+            }
+            CpuEvent::KeyReleased(key) if key < 16 => self.keyboard[key] = false,
+            _ => log::warn!("Ignoring {:?}: no such key", e),
         }
     }
 
-    pub fn handle_event(&mut self, e: CpuEvent) {}
+    /// Clock cycles executed since the ROM was loaded.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
 
-    pub fn clock_cycle(&mut self) {
-        self._cycle += 1;
-        self._cycle %= 10;
-        // if self._cycle != 0 {
-        //     return
-        // }
+    /// Run one instruction's worth of emulated time. Returns `true` if the
+    /// 60 Hz timers ticked at the end of it.
+    pub fn clock_cycle(&mut self) -> bool {
+        match self.execution_state {
+            ExecutionState::Running => {
+                if self.waiting_for_vblank {
+                    // Nothing runs until the next timer tick.
+                } else if !self.debugger.is_active() {
+                    self.execute_next_instruction();
+                } else if !self.execute_with_debugger() {
+                    return false;
+                }
+            }
+            // Timers keep running while the CPU is blocked on `LD Vx, K`.
+            ExecutionState::WaitingForKey(_) => {}
+            ExecutionState::Paused | ExecutionState::Halted | ExecutionState::Faulted { .. } => {
+                return false
+            }
+        }
+        self.cycle += 1;
 
-        if self.execution_state == ExecutionState::Running {
+        let mut ticked = false;
+        self.timer_phase += TIMER_HZ;
+        while self.timer_phase >= self.instructions_per_second.max(1) {
+            self.timer_phase -= self.instructions_per_second.max(1);
             self.tick_counters();
-            self.execute_next_instruction();
+            ticked = true;
+        }
+        ticked
+    }
+
+    /// Run until the end of the current 60 Hz frame, or until execution stops.
+    pub fn run_frame(&mut self) {
+        while self.is_active() {
+            if self.clock_cycle() {
+                break;
+            }
+        }
+    }
+
+    /// Run exactly one 60 Hz frame while paused, then pause again. If the frame
+    /// ends blocked on `LD Vx, K`, the CPU is left waiting for the key.
+    pub fn frame_advance(&mut self) {
+        if self.execution_state != ExecutionState::Paused {
+            return;
+        }
+        self.execution_state = ExecutionState::Running;
+        self.run_frame();
+        self.pause();
+    }
+
+    /// Run until the cycle counter reaches `cycle` or execution stops, then pause.
+    pub fn run_until(&mut self, cycle: u64) {
+        self.resume();
+        while self.cycle < cycle && self.is_active() {
+            self.clock_cycle();
         }
+        self.pause();
+    }
+
+    /// Whether emulated time is passing, i.e. the CPU is running or blocked on a key.
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.execution_state,
+            ExecutionState::Running | ExecutionState::WaitingForKey(_)
+        )
     }
 
     fn execute_next_instruction(&mut self) {
-        let i = self.fetch_next_instruction();
-        self.execute_instruction(i);
+        if self.trace.is_some() {
+            self.trace_instruction();
+        }
+        match self.fetch_next_instruction() {
+            Ok(i) => self.execute_instruction(i),
+            Err(fault) => {
+                let pc = self.pc;
+                self.pc = pc.wrapping_add(2);
+                self.fault(fault, pc);
+            }
+        }
     }
 
     fn tick_counters(&mut self) {
+        self.waiting_for_vblank = false;
         if self.tim_delay > 0 {
             self.tim_delay -= 1;
         }
@@ -131,3 +434,46 @@ impl Cpu {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Cpu, ExecutionState};
+
+    #[test]
+    fn test_timers_run_at_60hz() {
+        let mut cpu = Cpu::new();
+        cpu.instructions_per_second = 600;
+        cpu.load_rom(&[0x12, 0x00]); // JP 0x200
+        cpu.tim_delay = 100;
+        cpu.resume();
+        for _ in 0..600 {
+            cpu.clock_cycle();
+        }
+        assert_eq!(cpu.tim_delay, 40, "One second should take 60 off the delay timer");
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut cpu = Cpu::new();
+        cpu.instructions_per_second = 900;
+        cpu.load_rom(&[0x12, 0x00]); // JP 0x200
+        cpu.tim_sound = 10;
+        cpu.resume();
+        cpu.run_frame();
+        assert_eq!(cpu.cycle(), 15, "A frame should be 900 / 60 cycles");
+        assert_eq!(cpu.tim_sound, 9);
+        cpu.execution_state = ExecutionState::Paused;
+        cpu.run_frame();
+        assert_eq!(cpu.cycle(), 15, "Nothing should run while paused");
+    }
+
+    #[test]
+    fn test_frame_advance() {
+        let mut cpu = Cpu::new();
+        cpu.instructions_per_second = 600;
+        cpu.load_rom(&[0x12, 0x00]); // JP 0x200
+        cpu.frame_advance();
+        assert_eq!(cpu.cycle(), 10, "Exactly one frame should run");
+        assert_eq!(cpu.execution_state, ExecutionState::Paused);
+    }
+}
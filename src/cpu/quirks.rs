@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+/// What `Fx55`/`Fx65` leave in `I` after copying registers to or from memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryIncrement {
+    /// `I` is left untouched.
+    None,
+    /// `I` ends up at `I + X`.
+    X,
+    /// `I` ends up at `I + X + 1`, just past the last byte copied.
+    XPlusOne,
+}
+
+/// Behaviours of ambiguous instructions that differ between interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift `Vy` into `Vx` instead of shifting `Vx` in place.
+    pub shift_uses_vy: bool,
+    /// How `Fx55`/`Fx65` advance `I`.
+    pub memory_increment: MemoryIncrement,
+    /// `BXNN` jumps to `XNN + VX` instead of `NNN + V0`.
+    pub jump_with_vx: bool,
+    /// Sprites that run off the left/right edge wrap around instead of being clipped.
+    pub wrap_x: bool,
+    /// Sprites that run off the top/bottom edge wrap around instead of being clipped.
+    pub wrap_y: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset `VF` to 0.
+    pub vf_reset: bool,
+    /// `DXYN` waits for the next 60 Hz vertical blank before execution continues.
+    pub display_wait: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        QuirkProfile::CosmacVip.quirks()
+    }
+}
+
+impl Quirks {
+    /// The named profile these quirks match exactly, if any.
+    pub fn profile(&self) -> Option<QuirkProfile> {
+        QuirkProfile::ALL
+            .iter()
+            .copied()
+            .find(|profile| profile.quirks() == *self)
+    }
+}
+
+/// Quirk settings of well-known interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirkProfile {
+    /// The original interpreter on the RCA COSMAC VIP.
+    CosmacVip,
+    /// SUPER-CHIP 1.1 on the HP48.
+    SchipLegacy,
+    /// SUPER-CHIP as implemented by most modern interpreters (Octo's "SCHIP" mode).
+    SchipModern,
+    /// Octo's XO-CHIP extension.
+    XoChip,
+}
+
+impl QuirkProfile {
+    pub const ALL: [QuirkProfile; 4] = [
+        QuirkProfile::CosmacVip,
+        QuirkProfile::SchipLegacy,
+        QuirkProfile::SchipModern,
+        QuirkProfile::XoChip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            QuirkProfile::CosmacVip => "COSMAC VIP",
+            QuirkProfile::SchipLegacy => "SCHIP (legacy)",
+            QuirkProfile::SchipModern => "SCHIP (modern)",
+            QuirkProfile::XoChip => "XO-CHIP",
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            QuirkProfile::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                memory_increment: MemoryIncrement::XPlusOne,
+                jump_with_vx: false,
+                wrap_x: false,
+                wrap_y: false,
+                vf_reset: true,
+                display_wait: true,
+            },
+            QuirkProfile::SchipLegacy => Quirks {
+                shift_uses_vy: false,
+                memory_increment: MemoryIncrement::None,
+                jump_with_vx: true,
+                wrap_x: false,
+                wrap_y: false,
+                vf_reset: false,
+                display_wait: true,
+            },
+            QuirkProfile::SchipModern => Quirks {
+                shift_uses_vy: false,
+                memory_increment: MemoryIncrement::None,
+                jump_with_vx: true,
+                wrap_x: false,
+                wrap_y: false,
+                vf_reset: false,
+                display_wait: false,
+            },
+            QuirkProfile::XoChip => Quirks {
+                shift_uses_vy: true,
+                memory_increment: MemoryIncrement::XPlusOne,
+                jump_with_vx: false,
+                wrap_x: true,
+                wrap_y: true,
+                vf_reset: false,
+                display_wait: false,
+            },
+        }
+    }
+}
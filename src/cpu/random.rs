@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+/// Generators `CXNN` can draw its random bytes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RngAlgorithm {
+    /// xorshift64*, a fast generator with good statistical quality.
+    Xorshift,
+    /// SplitMix64, which behaves well even for seeds that differ in a single bit.
+    SplitMix,
+    /// The COSMAC VIP interpreter's own `CXNN` routine, which steps a pointer
+    /// through its code page and mixes each byte with the last result. The state
+    /// is register R9: the pointer in the low byte, the last result in the high.
+    CosmacVip,
+}
+
+impl RngAlgorithm {
+    pub const ALL: [RngAlgorithm; 3] = [
+        RngAlgorithm::Xorshift,
+        RngAlgorithm::SplitMix,
+        RngAlgorithm::CosmacVip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RngAlgorithm::Xorshift => "xorshift64*",
+            RngAlgorithm::SplitMix => "SplitMix64",
+            RngAlgorithm::CosmacVip => "COSMAC VIP",
+        }
+    }
+}
+
+/// Page 1 of the COSMAC VIP CHIP-8 interpreter (0x0100-0x01FF), which its `CXNN`
+/// routine at 0x01D9 reads as a source of noise.
+#[rustfmt::skip]
+static VIP_INTERPRETER_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98,
+    0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC,
+    0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4,
+    0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A,
+    0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA,
+    0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA,
+    0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE,
+    0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A,
+    0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01,
+    0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F,
+    0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52,
+    0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52,
+    0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22,
+    0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA,
+    0x0F, 0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x8A,
+    0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88,
+    0xD4, 0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30,
+    0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88,
+    0xD4, 0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7,
+    0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2,
+    0xFC, 0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45,
+    0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A,
+    0xC4, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3,
+    0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2,
+    0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56,
+    0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE,
+    0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45,
+    0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F,
+    0xBA, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x4B,
+];
+
+/// Seedable random number generator whose whole state is a `u64`, so it can
+/// be saved and restored along with the rest of the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Random {
+    algorithm: RngAlgorithm,
+    seed: u64,
+    state: u64,
+}
+
+impl Random {
+    pub fn new(algorithm: RngAlgorithm, seed: u64) -> Self {
+        let mut rng = Self {
+            algorithm,
+            seed,
+            state: 0,
+        };
+        rng.reset();
+        rng
+    }
+
+    /// Restart the sequence from the seed.
+    pub fn reset(&mut self) {
+        self.state = match self.algorithm {
+            // xorshift gets stuck on an all-zero state.
+            RngAlgorithm::Xorshift if self.seed == 0 => 0x9E37_79B9_7F4A_7C15,
+            RngAlgorithm::CosmacVip => self.seed & 0xFFFF,
+            _ => self.seed,
+        };
+    }
+
+    pub fn algorithm(&self) -> RngAlgorithm {
+        self.algorithm
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The current position in the sequence.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Resume the sequence from a state previously returned by `state`.
+    pub fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    /// Switch generators, restarting the sequence from the seed.
+    pub fn set_algorithm(&mut self, algorithm: RngAlgorithm) {
+        self.algorithm = algorithm;
+        self.reset();
+    }
+
+    /// Use a new seed, restarting the sequence.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.reset();
+    }
+
+    /// Draw the next random byte.
+    pub fn next_byte(&mut self) -> u8 {
+        match self.algorithm {
+            RngAlgorithm::Xorshift => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            }
+            RngAlgorithm::SplitMix => {
+                self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = self.state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                ((z ^ (z >> 31)) >> 56) as u8
+            }
+            RngAlgorithm::CosmacVip => {
+                // INC R9, then R9.1 + M(0x0100 + R9.0), as the 1802 adds with carry.
+                let r9 = (self.state as u16).wrapping_add(1);
+                let pointer = r9 as u8;
+                let (sum, carry) =
+                    ((r9 >> 8) as u8).overflowing_add(VIP_INTERPRETER_PAGE[pointer as usize]);
+                // STR R6 keeps the sum in VX, SHRC rotates the carry in, then VX is added.
+                let value = ((carry as u8) << 7 | sum >> 1).wrapping_add(sum);
+                self.state = u64::from(value) << 8 | u64::from(pointer);
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Random, RngAlgorithm};
+
+    #[test]
+    fn test_deterministic() {
+        for &algorithm in RngAlgorithm::ALL.iter() {
+            let mut a = Random::new(algorithm, 1234);
+            let mut b = Random::new(algorithm, 1234);
+            let a: Vec<u8> = (0..64).map(|_| a.next_byte()).collect();
+            let b: Vec<u8> = (0..64).map(|_| b.next_byte()).collect();
+            assert_eq!(a, b, "{} should repeat for the same seed", algorithm.name());
+        }
+    }
+
+    #[test]
+    fn test_cosmac_vip() {
+        // Pointer 0x05: 0x00 + M(0x0105) = 0x45, rotated to 0x22, plus 0x45 is 0x67.
+        let mut rng = Random::new(RngAlgorithm::CosmacVip, 0x0004);
+        assert_eq!(rng.next_byte(), 0x67);
+        assert_eq!(rng.state(), 0x6705, "R9 should hold the result and the pointer");
+        // 0x67 + M(0x0106) = 0x67 + 0xA3 = 0x10A: the carry rotates in to give 0x85.
+        assert_eq!(rng.next_byte(), 0x85 + 0x0A);
+
+        let mut rng = Random::new(RngAlgorithm::CosmacVip, 0x12_34FF);
+        rng.next_byte();
+        assert_eq!(
+            rng.state() & 0xFF,
+            0x00,
+            "The pointer should wrap around the interpreter page"
+        );
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let mut rng = Random::new(RngAlgorithm::Xorshift, 42);
+        rng.next_byte();
+        let state = rng.state();
+        let expected = rng.next_byte();
+        rng.next_byte();
+        rng.set_state(state);
+        assert_eq!(
+            rng.next_byte(),
+            expected,
+            "Restoring the state should replay"
+        );
+    }
+}
@@ -0,0 +1,540 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Cpu, CpuFault, ExecutionState, FaultPolicy, MemoryIncrement, Quirks, Random, RngAlgorithm,
+    RAM_SIZE,
+};
+
+/// First bytes of every save state file.
+const MAGIC: &[u8; 8] = b"CHIP8SAV";
+/// Layout version written by `SaveState::to_bytes`.
+///
+/// Fields are only ever appended. When the layout changes, bump this and teach
+/// `SaveState::from_bytes` to fill in the new fields for older versions, so
+/// states saved by earlier builds keep loading.
+pub const VERSION: u16 = 1;
+
+const VRAM_WIDTH: usize = 128;
+const VRAM_HEIGHT: usize = 64;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(std::io::Error),
+    /// The data does not start with the save state header.
+    NotASaveState,
+    /// Saved by a newer build that uses a layout this one does not know.
+    UnsupportedVersion(u16),
+    /// The data ends before the state is complete.
+    Truncated,
+    /// A field holds a value no `Cpu` can be in.
+    Corrupt(&'static str),
+    /// The state belongs to a different ROM than the one loaded.
+    WrongRom { expected: u64, found: u64 },
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::Io(e) => write!(f, "{}", e),
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is newer than the supported version {}",
+                version, VERSION
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Corrupt(what) => write!(f, "save state has an invalid {}", what),
+            SaveStateError::WrongRom { expected, found } => write!(
+                f,
+                "save state is for ROM {:016x}, but ROM {:016x} is loaded",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<std::io::Error> for SaveStateError {
+    fn from(e: std::io::Error) -> Self {
+        SaveStateError::Io(e)
+    }
+}
+
+/// Snapshot of everything that makes up the state of a `Cpu`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveState {
+    pub rom_hash: u64,
+    pub ram: Vec<u8>,
+    pub reg: [u8; 16],
+    pub reg_i: u16,
+    pub tim_delay: u8,
+    pub tim_sound: u8,
+    pub pc: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    pub keyboard: [bool; 16],
+    /// Rows of pixels, as `vram[y][x]`.
+    pub vram: Vec<Vec<u8>>,
+    pub hires: bool,
+    pub planes: u8,
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    pub rpl: [u8; 16],
+    pub execution_state: ExecutionState,
+    pub quirks: Quirks,
+    pub fault_policy: FaultPolicy,
+    pub waiting_for_vblank: bool,
+    pub instructions_per_second: u32,
+    pub cycle: u64,
+    pub timer_phase: u32,
+    pub rng: Random,
+}
+
+impl SaveState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Vec::with_capacity(RAM_SIZE + 0x4000);
+        w.extend_from_slice(MAGIC);
+        w.extend_from_slice(&VERSION.to_le_bytes());
+        w.extend_from_slice(&self.rom_hash.to_le_bytes());
+
+        w.extend_from_slice(&self.ram);
+        w.extend_from_slice(&self.reg);
+        w.extend_from_slice(&self.reg_i.to_le_bytes());
+        w.push(self.tim_delay);
+        w.push(self.tim_sound);
+        w.extend_from_slice(&self.pc.to_le_bytes());
+        w.push(self.sp);
+        for addr in self.stack.iter() {
+            w.extend_from_slice(&addr.to_le_bytes());
+        }
+        w.extend(self.keyboard.iter().map(|&key| key as u8));
+        for row in self.vram.iter() {
+            w.extend_from_slice(row);
+        }
+        w.push(self.hires as u8);
+        w.push(self.planes);
+        w.extend_from_slice(&self.audio_pattern);
+        w.push(self.pitch);
+        w.extend_from_slice(&self.rpl);
+        write_execution_state(&mut w, self.execution_state);
+        write_quirks(&mut w, &self.quirks);
+        w.push(self.fault_policy as u8);
+        w.push(self.waiting_for_vblank as u8);
+        w.extend_from_slice(&self.instructions_per_second.to_le_bytes());
+        w.extend_from_slice(&self.cycle.to_le_bytes());
+        w.extend_from_slice(&self.timer_phase.to_le_bytes());
+        w.push(self.rng.algorithm() as u8);
+        w.extend_from_slice(&self.rng.seed().to_le_bytes());
+        w.extend_from_slice(&self.rng.state().to_le_bytes());
+        w
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        let mut r = Reader(bytes);
+        if r.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(SaveStateError::NotASaveState);
+        }
+        let version = r.u16()?;
+        if version > VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let rom_hash = r.u64()?;
+
+        let ram = r.bytes(RAM_SIZE)?.to_vec();
+        let reg = r.array()?;
+        let reg_i = r.u16()?;
+        let tim_delay = r.u8()?;
+        let tim_sound = r.u8()?;
+        let pc = r.u16()?;
+        let sp = r.u8()?;
+        if sp > 16 {
+            return Err(SaveStateError::Corrupt("stack pointer"));
+        }
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        let mut keyboard = [false; 16];
+        for key in keyboard.iter_mut() {
+            *key = r.bool()?;
+        }
+        let mut vram = Vec::with_capacity(VRAM_HEIGHT);
+        for _ in 0..VRAM_HEIGHT {
+            vram.push(r.bytes(VRAM_WIDTH)?.to_vec());
+        }
+        let hires = r.bool()?;
+        let planes = r.u8()?;
+        let audio_pattern = r.array()?;
+        let pitch = r.u8()?;
+        let rpl = r.array()?;
+        let execution_state = read_execution_state(&mut r)?;
+        let quirks = read_quirks(&mut r)?;
+        let fault_policy = match r.u8()? {
+            0 => FaultPolicy::Halt,
+            1 => FaultPolicy::Warn,
+            2 => FaultPolicy::Wrap,
+            _ => return Err(SaveStateError::Corrupt("fault policy")),
+        };
+        let waiting_for_vblank = r.bool()?;
+        let instructions_per_second = r.u32()?;
+        let cycle = r.u64()?;
+        let timer_phase = r.u32()?;
+        let algorithm = *RngAlgorithm::ALL
+            .get(r.u8()? as usize)
+            .ok_or(SaveStateError::Corrupt("random number generator"))?;
+        let mut rng = Random::new(algorithm, r.u64()?);
+        rng.set_state(r.u64()?);
+
+        Ok(Self {
+            rom_hash,
+            ram,
+            reg,
+            reg_i,
+            tim_delay,
+            tim_sound,
+            pc,
+            sp,
+            stack,
+            keyboard,
+            vram,
+            hires,
+            planes,
+            audio_pattern,
+            pitch,
+            rpl,
+            execution_state,
+            quirks,
+            fault_policy,
+            waiting_for_vblank,
+            instructions_per_second,
+            cycle,
+            timer_phase,
+            rng,
+        })
+    }
+
+    /// Human-readable dump of the state, for attaching to bug reports.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("save states are always representable as JSON")
+    }
+}
+
+impl Cpu {
+    /// Capture the complete machine state.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            rom_hash: self.rom_hash,
+            ram: self.ram.to_vec(),
+            reg: self.reg,
+            reg_i: self.reg_i,
+            tim_delay: self.tim_delay,
+            tim_sound: self.tim_sound,
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack,
+            keyboard: self.keyboard,
+            vram: (0..VRAM_HEIGHT)
+                .map(|y| self.vram.iter().map(|column| column[y]).collect())
+                .collect(),
+            hires: self.hires,
+            planes: self.planes,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            rpl: self.rpl,
+            execution_state: self.execution_state,
+            quirks: self.quirks,
+            fault_policy: self.fault_policy,
+            waiting_for_vblank: self.waiting_for_vblank,
+            instructions_per_second: self.instructions_per_second,
+            cycle: self.cycle,
+            timer_phase: self.timer_phase,
+            rng: self.rng,
+        }
+    }
+
+    /// Restore a state captured by `save_state`. It must belong to the loaded ROM.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), SaveStateError> {
+        if state.rom_hash != self.rom_hash {
+            return Err(SaveStateError::WrongRom {
+                expected: self.rom_hash,
+                found: state.rom_hash,
+            });
+        }
+        if state.ram.len() != RAM_SIZE {
+            return Err(SaveStateError::Corrupt("memory size"));
+        }
+        if state.vram.len() != VRAM_HEIGHT || state.vram.iter().any(|row| row.len() != VRAM_WIDTH)
+        {
+            return Err(SaveStateError::Corrupt("screen size"));
+        }
+        if state.sp > 16 {
+            return Err(SaveStateError::Corrupt("stack pointer"));
+        }
+        // xorshift would draw nothing but zeros from here on.
+        if state.rng.algorithm() == RngAlgorithm::Xorshift && state.rng.state() == 0 {
+            return Err(SaveStateError::Corrupt("random number generator state"));
+        }
+        self.ram.copy_from_slice(&state.ram);
+        self.reg = state.reg;
+        self.reg_i = state.reg_i;
+        self.tim_delay = state.tim_delay;
+        self.tim_sound = state.tim_sound;
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.stack = state.stack;
+        self.keyboard = state.keyboard;
+        for (y, row) in state.vram.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                self.vram[x][y] = pixel;
+            }
+        }
+        self.hires = state.hires;
+        self.planes = state.planes;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.rpl = state.rpl;
+        self.execution_state = state.execution_state;
+        self.quirks = state.quirks;
+        self.fault_policy = state.fault_policy;
+        self.waiting_for_vblank = state.waiting_for_vblank;
+        self.instructions_per_second = state.instructions_per_second;
+        self.cycle = state.cycle;
+        self.timer_phase = state.timer_phase;
+        self.rng = state.rng;
+        Ok(())
+    }
+}
+
+fn write_execution_state(w: &mut Vec<u8>, state: ExecutionState) {
+    match state {
+        ExecutionState::Running => w.push(0),
+        ExecutionState::Paused => w.push(1),
+        ExecutionState::WaitingForKey(x) => w.extend_from_slice(&[2, x as u8]),
+        ExecutionState::Halted => w.push(3),
+        ExecutionState::Faulted { fault, pc } => {
+            w.push(4);
+            match fault {
+                CpuFault::StackOverflow => w.push(0),
+                CpuFault::StackUnderflow => w.push(1),
+                CpuFault::OutOfBounds(addr) => {
+                    w.push(2);
+                    w.extend_from_slice(&(addr as u32).to_le_bytes());
+                }
+                CpuFault::InvalidOpcode(opcode) => {
+                    w.push(3);
+                    w.extend_from_slice(&opcode);
+                }
+                CpuFault::BadKey(key) => w.extend_from_slice(&[4, key]),
+            }
+            w.extend_from_slice(&pc.to_le_bytes());
+        }
+    }
+}
+
+fn read_execution_state(r: &mut Reader) -> Result<ExecutionState, SaveStateError> {
+    Ok(match r.u8()? {
+        0 => ExecutionState::Running,
+        1 => ExecutionState::Paused,
+        2 => match r.u8()? {
+            x @ 0..=15 => ExecutionState::WaitingForKey(x as usize),
+            _ => return Err(SaveStateError::Corrupt("key register")),
+        },
+        3 => ExecutionState::Halted,
+        4 => {
+            let fault = match r.u8()? {
+                0 => CpuFault::StackOverflow,
+                1 => CpuFault::StackUnderflow,
+                2 => CpuFault::OutOfBounds(r.u32()? as usize),
+                3 => CpuFault::InvalidOpcode(r.array()?),
+                4 => CpuFault::BadKey(r.u8()?),
+                _ => return Err(SaveStateError::Corrupt("fault")),
+            };
+            ExecutionState::Faulted {
+                fault,
+                pc: r.u16()?,
+            }
+        }
+        _ => return Err(SaveStateError::Corrupt("execution state")),
+    })
+}
+
+fn write_quirks(w: &mut Vec<u8>, quirks: &Quirks) {
+    w.extend_from_slice(&[
+        quirks.shift_uses_vy as u8,
+        quirks.memory_increment as u8,
+        quirks.jump_with_vx as u8,
+        quirks.wrap_x as u8,
+        quirks.wrap_y as u8,
+        quirks.vf_reset as u8,
+        quirks.display_wait as u8,
+    ]);
+}
+
+fn read_quirks(r: &mut Reader) -> Result<Quirks, SaveStateError> {
+    Ok(Quirks {
+        shift_uses_vy: r.bool()?,
+        memory_increment: match r.u8()? {
+            0 => MemoryIncrement::None,
+            1 => MemoryIncrement::X,
+            2 => MemoryIncrement::XPlusOne,
+            _ => return Err(SaveStateError::Corrupt("memory increment quirk")),
+        },
+        jump_with_vx: r.bool()?,
+        wrap_x: r.bool()?,
+        wrap_y: r.bool()?,
+        vf_reset: r.bool()?,
+        display_wait: r.bool()?,
+    })
+}
+
+/// Little-endian cursor over a save state.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.0.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Corrupt("flag")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SaveState, SaveStateError, VERSION};
+    use crate::cpu::{Cpu, CpuFault, ExecutionState, QuirkProfile};
+
+    fn running_cpu() -> Cpu {
+        let mut cpu = Cpu::with_quirks(QuirkProfile::XoChip.quirks());
+        cpu.load_rom(&[
+            0x60, 0x05, // LD V0, 5
+            0xF0, 0x29, // LD F, V0
+            0xD0, 0x15, // DRW V0, V1, 5
+            0xC2, 0xFF, // RND V2, 0xFF
+            0x22, 0x00, // CALL 0x200
+        ]);
+        cpu.resume();
+        for _ in 0..13 {
+            cpu.clock_cycle();
+        }
+        cpu
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut cpu = running_cpu();
+        let state = cpu.save_state();
+        let bytes = state.to_bytes();
+        assert_eq!(SaveState::from_bytes(&bytes).unwrap(), state);
+
+        let expected: Vec<u8> = (0..20)
+            .map(|_| {
+                cpu.clock_cycle();
+                cpu.reg[2]
+            })
+            .collect();
+        cpu.load_state(&SaveState::from_bytes(&bytes).unwrap()).unwrap();
+        let replayed: Vec<u8> = (0..20)
+            .map(|_| {
+                cpu.clock_cycle();
+                cpu.reg[2]
+            })
+            .collect();
+        assert_eq!(replayed, expected, "Execution should resume exactly");
+    }
+
+    #[test]
+    fn test_roundtrip_fault() {
+        let mut cpu = Cpu::new();
+        cpu.execution_state = ExecutionState::Faulted {
+            fault: CpuFault::OutOfBounds(0x10002),
+            pc: 0x345,
+        };
+        let state = cpu.save_state();
+        assert_eq!(SaveState::from_bytes(&state.to_bytes()).unwrap(), state);
+    }
+
+    #[test]
+    fn test_wrong_rom() {
+        let state = running_cpu().save_state();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x00, 0xE0]);
+        assert!(
+            matches!(cpu.load_state(&state), Err(SaveStateError::WrongRom { .. })),
+            "A state should only load into the ROM it was saved from"
+        );
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut bytes = Cpu::new().save_state().to_bytes();
+        assert!(matches!(
+            SaveState::from_bytes(&bytes[..100]),
+            Err(SaveStateError::Truncated)
+        ));
+        bytes[8..10].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            SaveState::from_bytes(&bytes),
+            Err(SaveStateError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            SaveState::from_bytes(b"not a state"),
+            Err(SaveStateError::NotASaveState)
+        ));
+    }
+
+    #[test]
+    fn test_stuck_rng() {
+        let mut cpu = running_cpu();
+        let mut bytes = cpu.save_state().to_bytes();
+        let len = bytes.len();
+        bytes[len - 8..].copy_from_slice(&[0; 8]);
+        let state = SaveState::from_bytes(&bytes).unwrap();
+        assert!(
+            matches!(cpu.load_state(&state), Err(SaveStateError::Corrupt(_))),
+            "An all-zero xorshift state should be rejected"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let state = running_cpu().save_state();
+        let json = state.to_json();
+        let parsed: SaveState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, state);
+    }
+}
@@ -0,0 +1,121 @@
+//! Looking at sprites in memory and the ones the program drew recently.
+
+use std::collections::VecDeque;
+
+use super::{Cpu, RAM_SIZE};
+
+/// How many `DXYN` draws the sprite viewer remembers.
+pub const RECENT_SPRITES: usize = 16;
+
+/// A sprite drawn by `DXYN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteDraw {
+    /// Address of the `DXYN` instruction.
+    pub pc: u16,
+    /// Where the sprite data was read from.
+    pub address: u16,
+    pub x: u8,
+    pub y: u8,
+    /// Rows drawn; 16 for a SUPER-CHIP 16x16 sprite.
+    pub height: u8,
+    /// Whether it is a 16 pixel wide SUPER-CHIP sprite, two bytes per row.
+    pub wide: bool,
+    /// Whether it erased any pixel, as reported in VF.
+    pub collided: bool,
+}
+
+impl SpriteDraw {
+    /// Bytes per row of the sprite.
+    pub fn row_bytes(&self) -> usize {
+        if self.wide {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+impl Cpu {
+    /// The last `RECENT_SPRITES` sprites drawn, oldest first.
+    pub fn recent_sprites(&self) -> &VecDeque<SpriteDraw> {
+        &self.recent_sprites
+    }
+
+    pub(super) fn record_sprite(&mut self, draw: SpriteDraw) {
+        if self.recent_sprites.len() == RECENT_SPRITES {
+            self.recent_sprites.pop_front();
+        }
+        self.recent_sprites.push_back(draw);
+    }
+
+    /// `len` bytes of sprite data at `addr`, cut short at the end of RAM.
+    pub fn sprite_bytes(&self, addr: u16, len: usize) -> &[u8] {
+        let addr = addr as usize;
+        &self.ram[addr..(addr + len).min(RAM_SIZE)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SpriteDraw, RECENT_SPRITES};
+    use crate::cpu::{Cpu, RAM_SIZE};
+
+    #[test]
+    fn test_recent_sprites() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[
+            0x60, 0x05, // LD V0, 0x05
+            0xA0, 0x50, // LD I, 0x050
+            0xD0, 0x13, // DRW V0, V1, 3
+            0xD0, 0x11, // DRW V0, V1, 1
+        ]);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        let draws: Vec<SpriteDraw> = cpu.recent_sprites().iter().copied().collect();
+        assert_eq!(
+            draws,
+            [
+                SpriteDraw {
+                    pc: 0x204,
+                    address: 0x050,
+                    x: 5,
+                    y: 0,
+                    height: 3,
+                    wide: false,
+                    collided: false,
+                },
+                SpriteDraw {
+                    pc: 0x206,
+                    address: 0x050,
+                    x: 5,
+                    y: 0,
+                    height: 1,
+                    wide: false,
+                    collided: true,
+                },
+            ]
+        );
+
+        cpu.load_rom(&[0xD0, 0x11, 0x12, 0x00]);
+        assert!(
+            cpu.recent_sprites().is_empty(),
+            "Loading a ROM should forget the draws"
+        );
+        for _ in 0..RECENT_SPRITES * 3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.recent_sprites().len(), RECENT_SPRITES);
+    }
+
+    #[test]
+    fn test_sprite_bytes() {
+        let cpu = Cpu::new();
+        assert_eq!(cpu.sprite_bytes(0x000, 5), [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert_eq!(
+            cpu.sprite_bytes((RAM_SIZE - 2) as u16, 15).len(),
+            2,
+            "Sprites should be cut short at the end of RAM"
+        );
+    }
+}
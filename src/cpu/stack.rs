@@ -0,0 +1,147 @@
+//! Inspecting and editing the call stack from the debugger.
+
+use super::{Cpu, CpuFault, Instruction};
+
+/// Number of return addresses the stack holds.
+pub const STACK_SIZE: usize = 16;
+/// Stack depth from which the debugger warns about an overflow coming.
+pub const STACK_WARNING: usize = STACK_SIZE - 2;
+
+/// One entry of the call stack, as the debugger shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Index into the stack; 0 is the outermost call.
+    pub depth: usize,
+    pub return_address: u16,
+    /// Address of the `CALL` that pushed the frame, assuming it was one.
+    pub call_site: u16,
+    /// The instruction at the call site.
+    pub call: String,
+    /// The instruction execution returns to.
+    pub returns_to: String,
+}
+
+impl Cpu {
+    /// The instruction at `addr` as text, or `???` if it is not one.
+    pub fn instruction_text(&self, addr: u16) -> String {
+        let byte = |addr: u16| self.ram[addr as usize];
+        let (hi, lo) = (byte(addr), byte(addr.wrapping_add(1)));
+        match Instruction::decode([hi, lo]) {
+            Ok(Instruction::LdLongI) => {
+                let (hi, lo) = (byte(addr.wrapping_add(2)), byte(addr.wrapping_add(3)));
+                format!("LD I, LONG 0x{:04X}", u16::from_be_bytes([hi, lo]))
+            }
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => "???".to_string(),
+        }
+    }
+
+    /// Entries of the call stack in use, outermost first.
+    pub fn call_stack(&self) -> Vec<Frame> {
+        self.stack[..(self.sp as usize).min(STACK_SIZE)]
+            .iter()
+            .enumerate()
+            .map(|(depth, &return_address)| {
+                let call_site = return_address.wrapping_sub(2);
+                Frame {
+                    depth,
+                    return_address,
+                    call_site,
+                    call: self.instruction_text(call_site),
+                    returns_to: self.instruction_text(return_address),
+                }
+            })
+            .collect()
+    }
+
+    /// Number of entries on the stack.
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// Change the number of entries on the stack, keeping the addresses above it
+    /// so they come back if it is raised again.
+    pub fn set_sp(&mut self, sp: u8) {
+        self.sp = sp.min(STACK_SIZE as u8);
+    }
+
+    /// Push a return address, as `CALL` would.
+    pub fn push_frame(&mut self, return_address: u16) -> Result<(), CpuFault> {
+        if self.sp as usize >= STACK_SIZE {
+            return Err(CpuFault::StackOverflow);
+        }
+        self.stack[self.sp as usize] = return_address;
+        self.sp += 1;
+        Ok(())
+    }
+
+    /// Pop the innermost return address without returning to it.
+    pub fn pop_frame(&mut self) -> Result<u16, CpuFault> {
+        if self.sp == 0 {
+            return Err(CpuFault::StackUnderflow);
+        }
+        self.sp -= 1;
+        Ok(self.stack[self.sp as usize])
+    }
+
+    /// Whether the stack is close to overflowing.
+    pub fn stack_warning(&self) -> bool {
+        self.sp as usize >= STACK_WARNING
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{STACK_SIZE, STACK_WARNING};
+    use crate::cpu::{Cpu, CpuFault};
+
+    #[test]
+    fn test_call_stack() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[
+            0x22, 0x04, // CALL 0x204
+            0x00, 0x00, // (padding)
+            0x22, 0x08, // CALL 0x208
+            0x00, 0xEE, // RET
+            0xF0, 0x00, 0x12, 0x34, // LD I, LONG 0x1234
+        ]);
+        cpu.step();
+        cpu.step();
+        let frames = cpu.call_stack();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].return_address, 0x202);
+        assert_eq!(frames[0].call_site, 0x200);
+        assert_eq!(frames[0].call, "CALL 0x204");
+        assert_eq!(frames[1].depth, 1);
+        assert_eq!(frames[1].call, "CALL 0x208");
+        assert_eq!(frames[1].returns_to, "RET");
+        assert_eq!(cpu.instruction_text(0x208), "LD I, LONG 0x1234");
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.pop_frame(), Err(CpuFault::StackUnderflow));
+        cpu.push_frame(0x300).unwrap();
+        cpu.push_frame(0x400).unwrap();
+        assert_eq!(cpu.pop_frame(), Ok(0x400));
+        assert_eq!(cpu.sp(), 1);
+        for i in 1..STACK_SIZE {
+            assert_eq!(cpu.stack_warning(), i >= STACK_WARNING);
+            cpu.push_frame(i as u16).unwrap();
+        }
+        assert!(cpu.stack_warning());
+        assert_eq!(cpu.push_frame(0x500), Err(CpuFault::StackOverflow));
+
+        cpu.set_sp(1);
+        assert_eq!(cpu.call_stack().len(), 1);
+        cpu.set_sp(2);
+        assert_eq!(
+            cpu.call_stack()[1].return_address,
+            1,
+            "Raising SP should bring back the entries above it"
+        );
+        cpu.set_sp(200);
+        assert_eq!(cpu.sp() as usize, STACK_SIZE);
+    }
+}
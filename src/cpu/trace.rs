@@ -0,0 +1,280 @@
+//! Execution traces: one line per instruction, in a fixed format meant for diffing.
+//!
+//! Unlike the `trace!` logging, this works in release builds and is switched on and
+//! off at run time by setting `Cpu::trace`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+
+use super::Cpu;
+
+/// First line of every trace, naming the columns.
+pub const HEADER: &str =
+    "#    cycle   pc   op  instruction              V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF     I SP DT ST";
+
+/// Where trace lines go, and which instructions they are written for.
+pub struct Trace {
+    out: Box<dyn Write>,
+    range: (u16, u16),
+    lines: u64,
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("range", &self.range)
+            .field("lines", &self.lines)
+            .finish()
+    }
+}
+
+impl Trace {
+    /// Trace every instruction to `out`.
+    pub fn new(out: impl Write + 'static) -> io::Result<Self> {
+        let mut out = Box::new(out);
+        writeln!(out, "{}", HEADER)?;
+        Ok(Self {
+            out,
+            range: (0, 0xFFFF),
+            lines: 0,
+        })
+    }
+
+    /// Trace every instruction to a new file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Only trace instructions at addresses from `start` to `end` inclusive.
+    pub fn range(mut self, start: u16, end: u16) -> Self {
+        self.range = (start, end);
+        self
+    }
+
+    /// Instructions traced so far.
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        if let Err(e) = self.out.flush() {
+            log::error!("Failed to finish the trace: {}", e);
+        }
+    }
+}
+
+impl Cpu {
+    /// Write the trace line for the instruction about to execute, if it is in range.
+    pub(super) fn trace_instruction(&mut self) {
+        let pc = self.pc;
+        match &self.trace {
+            Some(trace) if (trace.range.0..=trace.range.1).contains(&pc) => {}
+            _ => return,
+        }
+        let line = self.trace_line();
+        let trace = self.trace.as_mut().unwrap();
+        match writeln!(trace.out, "{}", line) {
+            Ok(()) => trace.lines += 1,
+            Err(e) => {
+                log::error!("Failed to write the trace, stopping it: {}", e);
+                self.trace = None;
+            }
+        }
+    }
+
+    /// The machine state before the instruction at PC executes, in the trace format.
+    fn trace_line(&self) -> String {
+        let pc = self.pc;
+        let op = [self.ram[pc as usize], self.ram[pc.wrapping_add(1) as usize]];
+        let text = self.instruction_text(pc);
+        let registers: Vec<String> = self.reg.iter().map(|v| format!("{:02X}", v)).collect();
+        format!(
+            "{:>10} {:04X} {:04X}  {:<24} {}  {:04X} {:>2} {:02X} {:02X}",
+            self.cycle,
+            pc,
+            u16::from_be_bytes(op),
+            text,
+            registers.join(" "),
+            self.reg_i,
+            self.sp,
+            self.tim_delay,
+            self.tim_sound
+        )
+    }
+}
+
+/// Where two traces first differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Line number, counting from 1.
+    pub line: usize,
+    /// The line in each trace, or `None` where that trace already ended.
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+impl Divergence {
+    /// Character position of the first difference, counting from 0.
+    pub fn column(&self) -> usize {
+        match (&self.left, &self.right) {
+            (Some(left), Some(right)) => left
+                .chars()
+                .zip(right.chars())
+                .take_while(|(a, b)| a == b)
+                .count(),
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "traces diverge at line {}:", self.line)?;
+        let line = |line: &Option<String>| match line {
+            Some(line) => line.clone(),
+            None => "(end of trace)".to_string(),
+        };
+        writeln!(f, "< {}", line(&self.left))?;
+        writeln!(f, "> {}", line(&self.right))?;
+        write!(f, "  {:>width$}", "^", width = self.column() + 1)
+    }
+}
+
+/// Compare two traces line by line, returning the first line that differs.
+pub fn first_divergence(left: impl BufRead, right: impl BufRead) -> io::Result<Option<Divergence>> {
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut line = 0;
+    loop {
+        line += 1;
+        let (a, b) = (left.next().transpose()?, right.next().transpose()?);
+        if a.is_none() && b.is_none() {
+            return Ok(None);
+        }
+        if a != b {
+            return Ok(Some(Divergence {
+                line,
+                left: a,
+                right: b,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use super::{first_divergence, Trace, HEADER};
+    use crate::cpu::Cpu;
+
+    /// A writer that can still be read after the trace owning it is dropped.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn traced(rom: &[u8], cycles: usize, trace: impl FnOnce(Trace) -> Trace) -> String {
+        let out = Shared::default();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(rom);
+        cpu.trace = Some(trace(Trace::new(out.clone()).unwrap()));
+        cpu.resume();
+        for _ in 0..cycles {
+            cpu.clock_cycle();
+        }
+        cpu.trace = None;
+        out.text()
+    }
+
+    #[test]
+    fn test_format() {
+        let rom = [
+            0x6A, 0x02, // LD VA, 0x02
+            0xF0, 0x00, 0x12, 0x34, // LD I, LONG 0x1234
+            0x12, 0x06, // JP 0x206
+        ];
+        let text = traced(&rom, 3, |trace| trace);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], HEADER);
+        assert_eq!(
+            lines[1],
+            "         0 0200 6A02  LD VA, 0x02              \
+             00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  0000  0 00 00"
+        );
+        assert_eq!(
+            lines[2],
+            "         1 0202 F000  LD I, LONG 0x1234        \
+             00 00 00 00 00 00 00 00 00 00 02 00 00 00 00 00  0000  0 00 00"
+        );
+        assert!(lines[3].starts_with("         2 0206 1206  JP 0x206"));
+        assert!(
+            lines[3].ends_with("  1234  0 00 00"),
+            "Each line should show the state before its instruction"
+        );
+        assert_eq!(
+            lines[0].find("V0"),
+            lines[1].find("00 00"),
+            "The header should line up with the columns"
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        let rom = [
+            0x22, 0x04, // CALL 0x204
+            0x12, 0x02, // JP 0x202
+            0x60, 0x01, // LD V0, 0x01
+            0x00, 0xEE, // RET
+        ];
+        let text = traced(&rom, 6, |trace| trace.range(0x204, 0x2FF));
+        let pcs: Vec<&str> = text.lines().skip(1).map(|line| &line[11..15]).collect();
+        assert_eq!(pcs, ["0204", "0206"]);
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let a = "# header\n1 0200 A\n2 0202 B\n";
+        assert_eq!(first_divergence(a.as_bytes(), a.as_bytes()).unwrap(), None);
+
+        let b = "# header\n1 0200 A\n2 0202 C\n";
+        let divergence = first_divergence(a.as_bytes(), b.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.line, 3);
+        assert_eq!(divergence.column(), 7);
+        assert_eq!(
+            divergence.to_string(),
+            "traces diverge at line 3:\n< 2 0202 B\n> 2 0202 C\n         ^"
+        );
+
+        let c = "# header\n1 0200 A\n";
+        let divergence = first_divergence(a.as_bytes(), c.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            divergence.right, None,
+            "A shorter trace should diverge where it ends"
+        );
+    }
+}
@@ -1,8 +1,10 @@
-use super::ExecutionState;
+use super::{ExecutionState, FaultPolicy, MemoryIncrement, QuirkProfile, RngAlgorithm, STACK_SIZE};
 use egui::{DragValue, Ui};
+use std::path::PathBuf;
 
 impl super::Cpu {
-    pub fn draw_ui(&mut self, ui: &mut Ui) {
+    /// `movie_active` locks the settings a movie records in its header, and the stack.
+    pub fn draw_ui(&mut self, ui: &mut Ui, movie_active: bool) {
         ui.horizontal(|ui| {
             ui.label(format!("Cpu state: {:?}", self.execution_state));
 
@@ -27,16 +29,32 @@ impl super::Cpu {
                 }
             }
 
+            if let ExecutionState::Faulted { .. } = self.execution_state {
+                if ui.button("Clear fault").clicked() {
+                    self.clear_fault();
+                }
+            }
+
             ui.separator();
 
-            ui.set_enabled(self.execution_state == ExecutionState::Paused);
+            ui.set_enabled(!self.is_active());
             ui.label("Program counter:");
             ui.add(
-                egui::DragValue::u16(&mut self.pc).clamp_range(0x200 as f32..=4096_f32), // .prefix("Program Counter:"),
+                egui::DragValue::u16(&mut self.pc).clamp_range(0x200 as f32..=0xFFFE as f32), // .prefix("Program Counter:"),
             );
         });
 
-        ui.set_enabled(self.execution_state == ExecutionState::Paused);
+        if let ExecutionState::Faulted { fault, pc } = self.execution_state {
+            ui.colored_label(egui::Color32::RED, format!("Fault: {} at 0x{:X}", fault, pc));
+        }
+        if let Some(hit) = self.debugger.last_break {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!("Breakpoint {}: {}", hit.breakpoint, hit.reason),
+            );
+        }
+
+        ui.set_enabled(!self.is_active());
 
         ui.separator();
 
@@ -71,11 +89,130 @@ impl super::Cpu {
             ui.add(DragValue::u8(&mut self.tim_sound));
         });
         ui.separator();
-        ui.label("Stack:");
+        ui.collapsing("Call stack", |ui| self.draw_stack(ui, movie_active));
+
+        ui.separator();
+        ui.set_enabled(!movie_active);
+        ui.add(
+            egui::Slider::u32(&mut self.instructions_per_second, 60..=5000)
+                .text("instructions per second"),
+        );
+
+        ui.horizontal(|ui| {
+            ui.label("On fault:");
+            for &policy in FaultPolicy::ALL.iter() {
+                ui.radio_value(&mut self.fault_policy, policy, policy.name());
+            }
+        });
+
+        ui.separator();
+        let mut persist_rpl = self.rpl_dir.is_some();
+        if ui
+            .checkbox(&mut persist_rpl, "Save SUPER-CHIP RPL flags to disk")
+            .clicked()
+        {
+            self.rpl_dir = if persist_rpl {
+                Some(PathBuf::from("rpl"))
+            } else {
+                None
+            };
+        }
+
+        ui.separator();
+        ui.collapsing("Random numbers", |ui| {
+            ui.horizontal(|ui| {
+                for &algorithm in RngAlgorithm::ALL.iter() {
+                    if ui
+                        .radio(self.rng.algorithm() == algorithm, algorithm.name())
+                        .clicked()
+                    {
+                        self.rng.set_algorithm(algorithm);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("Seed: {:016X}", self.rng.seed()));
+                if ui.button("New seed").clicked() {
+                    self.rng.reseed(rand::random());
+                }
+                if ui.button("Restart sequence").clicked() {
+                    self.rng.reset();
+                }
+            });
+        });
+
+        ui.separator();
+        ui.collapsing("Quirks", |ui| {
+            ui.horizontal(|ui| {
+                for &profile in QuirkProfile::ALL.iter() {
+                    if ui
+                        .selectable_label(self.quirks.profile() == Some(profile), profile.name())
+                        .clicked()
+                    {
+                        self.quirks = profile.quirks();
+                    }
+                }
+            });
+            let quirks = &mut self.quirks;
+            ui.checkbox(&mut quirks.shift_uses_vy, "8XY6/8XYE shift Vy into Vx");
+            ui.horizontal(|ui| {
+                ui.label("FX55/FX65 leave I at:");
+                ui.radio_value(&mut quirks.memory_increment, MemoryIncrement::None, "I");
+                ui.radio_value(&mut quirks.memory_increment, MemoryIncrement::X, "I + X");
+                ui.radio_value(
+                    &mut quirks.memory_increment,
+                    MemoryIncrement::XPlusOne,
+                    "I + X + 1",
+                );
+            });
+            ui.checkbox(&mut quirks.jump_with_vx, "BXNN jumps to XNN + VX");
+            ui.checkbox(&mut quirks.wrap_x, "Sprites wrap horizontally");
+            ui.checkbox(&mut quirks.wrap_y, "Sprites wrap vertically");
+            ui.checkbox(&mut quirks.vf_reset, "Logic ops reset VF");
+            ui.checkbox(&mut quirks.display_wait, "DXYN waits for vertical blank");
+        });
+    }
+
+    fn draw_stack(&mut self, ui: &mut Ui, movie_active: bool) {
+        // A changed stack would not be in the movie, and would break its replay.
+        ui.set_enabled(!movie_active);
         ui.horizontal(|ui| {
-            for i in 0..(self.sp as usize) {
-                ui.add(DragValue::u16(&mut self.stack[i]));
+            let mut sp = self.sp;
+            ui.label("SP:");
+            ui.add(DragValue::u8(&mut sp).clamp_range(0.0..=STACK_SIZE as f32));
+            self.set_sp(sp);
+            let full = self.sp as usize >= STACK_SIZE;
+            if ui
+                .add(egui::Button::new("Push PC").enabled(!full))
+                .clicked()
+            {
+                self.push_frame(self.pc).ok();
+            }
+            if ui
+                .add(egui::Button::new("Pop").enabled(self.sp > 0))
+                .clicked()
+            {
+                self.pop_frame().ok();
             }
         });
+        if self.stack_warning() {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!("Stack nearly full: {} of {} entries", self.sp, STACK_SIZE),
+            );
+        }
+        for frame in self.call_stack().iter().rev() {
+            ui.horizontal(|ui| {
+                ui.label(format!("#{}", frame.depth));
+                ui.add(DragValue::u16(&mut self.stack[frame.depth]));
+                ui.add(
+                    egui::Label::new(format!(
+                        "{:03X}: {:<16} -> {:03X}: {}",
+                        frame.call_site, frame.call, frame.return_address, frame.returns_to
+                    ))
+                    .text_style(egui::TextStyle::Monospace),
+                );
+            });
+        }
     }
 }
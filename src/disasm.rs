@@ -0,0 +1,650 @@
+//! Disassembling ROMs by following control flow, so that code is told apart from data.
+//!
+//! Tracing starts at the entry points and follows jumps, calls and both outcomes of
+//! skips. Whatever it does not reach is data: bytes read as sprites by a nearby `DRW`
+//! are shown as bit patterns, everything else as `db` lines. The text output is
+//! accepted by the assembler and reassembles to the same bytes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::asm::ORIGIN;
+use crate::cpu::Instruction;
+
+/// Bytes per `db` line of data that is not a sprite.
+const DATA_PER_LINE: usize = 8;
+/// How many instructions after an `LD I` to look for the `DRW` that uses it.
+const SPRITE_SEARCH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// An instruction reachable from an entry point.
+    Code,
+    /// Bytes read by `DRW`, one row of pixels per line.
+    Sprite,
+    /// Anything else.
+    Data,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 3] = [Kind::Code, Kind::Sprite, Kind::Data];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Code => "code",
+            Kind::Sprite => "sprite",
+            Kind::Data => "data",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub kind: Kind,
+    /// Label defined at this address.
+    pub label: Option<String>,
+    /// Assembly source for the bytes, using labels for known addresses.
+    pub text: String,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    /// Addresses of `BNNN` jumps, whose targets depend on a register and were not followed.
+    pub computed_jumps: Vec<u16>,
+}
+
+impl Disassembly {
+    /// Index of the line holding the byte at `address`.
+    pub fn line_at(&self, address: u16) -> Option<usize> {
+        let index = match self
+            .lines
+            .binary_search_by_key(&address, |line| line.address)
+        {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let line = &self.lines[index];
+        if (address - line.address) < line.bytes.len() as u16 {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Address of every label, by name.
+    pub fn labels(&self) -> BTreeMap<&str, u16> {
+        self.lines
+            .iter()
+            .filter_map(|line| line.label.as_deref().map(|label| (label, line.address)))
+            .collect()
+    }
+
+    /// Source that `asm::assemble` turns back into the same bytes.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        if let Some(first) = self.lines.first() {
+            if first.address != ORIGIN {
+                writeln!(out, "    org 0x{:03X}", first.address).unwrap();
+            }
+        }
+        for line in &self.lines {
+            if let Some(label) = &line.label {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                writeln!(out, "{}:", label).unwrap();
+            }
+            write!(out, "    {:<28}; {:04X}", line.text, line.address).unwrap();
+            if let Some(comment) = &line.comment {
+                write!(out, "  {}", comment).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("disassemblies are always representable as JSON")
+    }
+}
+
+/// Disassemble a ROM loaded at the usual address, starting at its first instruction.
+pub fn disassemble_rom(rom: &[u8]) -> Disassembly {
+    disassemble(rom, ORIGIN, &[ORIGIN])
+}
+
+/// Disassemble `bytes` loaded at `origin`, tracing code from each of `entries`.
+pub fn disassemble(bytes: &[u8], origin: u16, entries: &[u16]) -> Disassembly {
+    let mut analysis = Analysis {
+        bytes,
+        origin,
+        code: BTreeMap::new(),
+        claimed: vec![false; bytes.len()],
+        labels: BTreeMap::new(),
+        sprites: BTreeMap::new(),
+        invalid: BTreeSet::new(),
+        computed_jumps: Vec::new(),
+    };
+    for (i, &entry) in entries.iter().enumerate() {
+        let name = if i == 0 {
+            "start".to_string()
+        } else {
+            format!("entry_{:03X}", entry)
+        };
+        analysis.labels.entry(entry).or_insert(name);
+    }
+    analysis.trace(entries);
+    analysis.find_sprites();
+    analysis.layout()
+}
+
+struct Analysis<'a> {
+    bytes: &'a [u8],
+    origin: u16,
+    /// Reachable instructions by address.
+    code: BTreeMap<u16, Instruction>,
+    /// Which bytes belong to reachable instructions.
+    claimed: Vec<bool>,
+    labels: BTreeMap<u16, String>,
+    /// Start and length of every sprite, with the bytes per row.
+    sprites: BTreeMap<u16, (usize, usize)>,
+    /// Addresses reached by control flow that hold no valid instruction.
+    invalid: BTreeSet<u16>,
+    computed_jumps: Vec<u16>,
+}
+
+impl Analysis<'_> {
+    fn offset(&self, address: u16) -> Option<usize> {
+        let offset = address.checked_sub(self.origin)? as usize;
+        if offset < self.bytes.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn word(&self, address: u16) -> Option<[u8; 2]> {
+        let offset = self.offset(address)?;
+        match self.bytes.get(offset..offset + 2) {
+            Some(&[hi, lo]) => Some([hi, lo]),
+            _ => None,
+        }
+    }
+
+    fn label(&mut self, address: u16, prefix: &str) {
+        self.labels
+            .entry(address)
+            .or_insert_with(|| format!("{}_{:03X}", prefix, address));
+    }
+
+    /// Size of whatever instruction is at `address`, for skipping over it.
+    fn size_at(&self, address: u16) -> u16 {
+        match self.word(address) {
+            Some([0xF0, 0x00]) => 4,
+            _ => 2,
+        }
+    }
+
+    fn trace(&mut self, entries: &[u16]) {
+        let mut work: Vec<u16> = entries.iter().rev().copied().collect();
+        while let Some(address) = work.pop() {
+            if self.code.contains_key(&address) || self.invalid.contains(&address) {
+                continue;
+            }
+            let offset = match (self.offset(address), self.word(address)) {
+                (Some(offset), Some(_)) => offset,
+                _ => continue,
+            };
+            let instruction = match Instruction::decode(self.word(address).unwrap()) {
+                Ok(instruction) => instruction,
+                Err(_) => {
+                    self.invalid.insert(address);
+                    continue;
+                }
+            };
+            let size = instruction.size() as usize;
+            let bytes = offset..offset + size;
+            if bytes.end > self.bytes.len() {
+                self.invalid.insert(address);
+                continue;
+            }
+            if self.claimed[bytes.clone()].iter().any(|&claimed| claimed) {
+                // Overlaps an instruction found earlier; keep the first reading.
+                continue;
+            }
+            self.claimed[bytes]
+                .iter_mut()
+                .for_each(|claimed| *claimed = true);
+            self.code.insert(address, instruction);
+
+            let next = address.wrapping_add(size as u16);
+            match instruction {
+                Instruction::Jp(target) => {
+                    self.label(target, "label");
+                    work.push(target);
+                }
+                Instruction::Call(target) => {
+                    self.label(target, "sub");
+                    work.push(target);
+                }
+                Instruction::JpV0(table) => {
+                    self.label(table, "table");
+                    self.computed_jumps.push(address);
+                }
+                _ => {}
+            }
+            if instruction.is_skip() {
+                work.push(next.wrapping_add(self.size_at(next)));
+            }
+            if instruction.falls_through() {
+                work.push(next);
+            }
+        }
+    }
+
+    /// Mark the targets of `LD I` as sprites when a `DRW` follows before `I` changes,
+    /// and as plain data otherwise.
+    fn find_sprites(&mut self) {
+        let loads: Vec<(u16, u16)> = self
+            .code
+            .iter()
+            .filter_map(|(&address, &instruction)| match instruction {
+                Instruction::LdI(target) => Some((address, target)),
+                Instruction::LdLongI => self
+                    .word(address.wrapping_add(2))
+                    .map(|word| (address, u16::from_be_bytes(word))),
+                _ => None,
+            })
+            .collect();
+        for (load, target) in loads {
+            let size = self.code[&load].size();
+            match self.sprite_after(load.wrapping_add(size)) {
+                Some((len, row)) => {
+                    let sprite = self.sprites.entry(target).or_insert((len, row));
+                    if len > sprite.0 {
+                        *sprite = (len, row);
+                    }
+                    self.label(target, "sprite");
+                }
+                None => self.label(target, "data"),
+            }
+        }
+        // A sprite label loses to a data label only if no DRW used the address.
+        for (&address, (_, _)) in self.sprites.iter() {
+            if let Some(label) = self.labels.get_mut(&address) {
+                if label.starts_with("data_") {
+                    *label = format!("sprite_{:03X}", address);
+                }
+            }
+        }
+    }
+
+    /// Length and row size of the sprite drawn by the first `DRW` from `address` on,
+    /// if `I` is left alone until then.
+    fn sprite_after(&self, mut address: u16) -> Option<(usize, usize)> {
+        for _ in 0..SPRITE_SEARCH {
+            let instruction = *self.code.get(&address)?;
+            match instruction {
+                Instruction::Drw(_, _, 0) => return Some((32, 2)),
+                Instruction::Drw(_, _, n) => return Some((n as usize, 1)),
+                Instruction::LdI(_)
+                | Instruction::LdLongI
+                | Instruction::AddI(_)
+                | Instruction::LdF(_)
+                | Instruction::LdHf(_)
+                | Instruction::LdIVx(_)
+                | Instruction::LdVxI(_)
+                | Instruction::SaveRange(..)
+                | Instruction::LoadRange(..)
+                | Instruction::Call(_) => return None,
+                i if !i.falls_through() => return None,
+                _ => {}
+            }
+            address = address.wrapping_add(instruction.size());
+        }
+        None
+    }
+
+    /// Whether a line may start at `address`: it is in the ROM and not inside an instruction.
+    fn is_boundary(&self, address: u16) -> bool {
+        match self.offset(address) {
+            Some(offset) => !self.claimed[offset] || self.code.contains_key(&address),
+            None => false,
+        }
+    }
+
+    fn layout(mut self) -> Disassembly {
+        let labels: BTreeMap<u16, String> = self
+            .labels
+            .iter()
+            .filter(|(&address, _)| self.is_boundary(address))
+            .map(|(&address, label)| (address, label.clone()))
+            .collect();
+        self.labels = labels;
+
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            let address = self.origin.wrapping_add(offset as u16);
+            let line = if let Some(&instruction) = self.code.get(&address) {
+                self.code_line(address, instruction)
+            } else if let Some(&(len, row)) = self.sprites.get(&address) {
+                let rows = self.sprite_lines(address, len, row);
+                if rows.is_empty() {
+                    self.data_line(address)
+                } else {
+                    offset += rows.iter().map(|line| line.bytes.len()).sum::<usize>();
+                    lines.extend(rows);
+                    continue;
+                }
+            } else {
+                self.data_line(address)
+            };
+            offset += line.bytes.len();
+            lines.push(line);
+        }
+        Disassembly {
+            lines,
+            computed_jumps: self.computed_jumps,
+        }
+    }
+
+    fn name(&self, address: u16) -> String {
+        match self.labels.get(&address) {
+            Some(label) => label.clone(),
+            None => format!("0x{:03X}", address),
+        }
+    }
+
+    fn code_line(&self, address: u16, instruction: Instruction) -> Line {
+        let offset = self.offset(address).unwrap();
+        let bytes = self.bytes[offset..offset + instruction.size() as usize].to_vec();
+        let mut comment = None;
+        let text = match instruction {
+            Instruction::Jp(target) => format!("JP {}", self.name(target)),
+            Instruction::Call(target) => format!("CALL {}", self.name(target)),
+            Instruction::LdI(target) => format!("LD I, {}", self.name(target)),
+            Instruction::LdLongI => {
+                format!(
+                    "LD I, LONG {}",
+                    self.name(u16::from_be_bytes([bytes[2], bytes[3]]))
+                )
+            }
+            Instruction::JpV0(table) => {
+                comment = Some("computed jump".to_string());
+                format!("JP V0, {}", self.name(table))
+            }
+            instruction => instruction.to_string(),
+        };
+        Line {
+            address,
+            bytes,
+            kind: Kind::Code,
+            label: self.labels.get(&address).cloned(),
+            text,
+            comment,
+        }
+    }
+
+    /// Whether data starting before `address` has to end there.
+    fn breaks_data(&self, address: u16) -> bool {
+        !matches!(self.offset(address), Some(offset) if !self.claimed[offset])
+            || self.labels.contains_key(&address)
+            || self.sprites.contains_key(&address)
+            || self.invalid.contains(&address)
+    }
+
+    fn sprite_lines(&self, address: u16, len: usize, row: usize) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut row_address = address;
+        while lines.len() * row < len {
+            let fits = (0..row as u16).all(|i| {
+                let byte = row_address.wrapping_add(i);
+                self.offset(byte).is_some() && (byte == address || !self.breaks_data(byte))
+            });
+            if !fits {
+                break;
+            }
+            let offset = self.offset(row_address).unwrap();
+            let bytes = self.bytes[offset..offset + row].to_vec();
+            let text = bytes
+                .iter()
+                .map(|byte| format!("0b{:08b}", byte))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let pattern = bytes
+                .iter()
+                .map(|byte| {
+                    (0..8)
+                        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                        .collect::<String>()
+                })
+                .collect::<String>();
+            lines.push(Line {
+                address: row_address,
+                bytes,
+                kind: Kind::Sprite,
+                label: self.labels.get(&row_address).cloned(),
+                text: format!("db {}", text),
+                comment: Some(pattern),
+            });
+            row_address = row_address.wrapping_add(row as u16);
+        }
+        lines
+    }
+
+    fn data_line(&self, address: u16) -> Line {
+        let offset = self.offset(address).unwrap();
+        let mut len = 1;
+        while len < DATA_PER_LINE
+            && offset + len < self.bytes.len()
+            && !self.breaks_data(address.wrapping_add(len as u16))
+        {
+            len += 1;
+        }
+        let bytes = self.bytes[offset..offset + len].to_vec();
+        let text = bytes
+            .iter()
+            .map(|byte| format!("0x{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let comment = if self.invalid.contains(&address) {
+            Some("reachable, but not an instruction".to_string())
+        } else {
+            None
+        };
+        Line {
+            address,
+            bytes,
+            kind: Kind::Data,
+            label: self.labels.get(&address).cloned(),
+            text: format!("db {}", text),
+            comment,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disassemble, disassemble_rom, Kind};
+    use crate::asm::assemble;
+
+    fn kinds(rom: &[u8]) -> Vec<(u16, Kind)> {
+        disassemble_rom(rom)
+            .lines
+            .iter()
+            .map(|line| (line.address, line.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_reachability() {
+        let rom = assemble(
+            "
+                CALL draw
+                SE V0, 1
+                JP skipped
+                JP end
+            skipped:
+                LD V1, 2
+            end:
+                JP end
+                db 0xFF, 0x00
+            draw:
+                RET
+            ",
+        )
+        .unwrap()
+        .rom;
+        let kinds = kinds(&rom);
+        assert_eq!(
+            kinds.iter().filter(|(_, kind)| *kind == Kind::Data).count(),
+            1,
+            "Only the bytes after the endless loop should be data: {:?}",
+            kinds
+        );
+        assert_eq!(kinds[6], (0x20C, Kind::Data));
+        assert_eq!(
+            kinds[7],
+            (0x20E, Kind::Code),
+            "Called code should be reached"
+        );
+
+        let disassembly = disassemble_rom(&rom);
+        let labels = disassembly.labels();
+        assert_eq!(labels["start"], 0x200);
+        assert_eq!(labels["sub_20E"], 0x20E);
+        assert_eq!(labels["label_208"], 0x208);
+        assert_eq!(labels["label_20A"], 0x20A);
+        assert_eq!(disassembly.lines[0].text, "CALL sub_20E");
+    }
+
+    #[test]
+    fn test_skips_over_long_load() {
+        let rom = [
+            0x30, 0x01, // SE V0, 1
+            0xF0, 0x00, 0x02, 0x08, // LD I, LONG 0x208
+            0x00, 0xFD, // EXIT
+            0x12, 0x34,
+        ];
+        let disassembly = disassemble_rom(&rom);
+        let code: Vec<u16> = disassembly
+            .lines
+            .iter()
+            .filter(|line| line.kind == Kind::Code)
+            .map(|line| line.address)
+            .collect();
+        assert_eq!(
+            code,
+            [0x200, 0x202, 0x206],
+            "A skip should jump over all four bytes of LD I, LONG"
+        );
+        assert_eq!(disassembly.lines[1].text, "LD I, LONG data_208");
+    }
+
+    #[test]
+    fn test_computed_jump() {
+        let rom = [
+            0xB2, 0x04, // JP V0, 0x204
+            0x00, 0x00, // never reached
+            0x12, 0x08, // JP 0x208
+            0x12, 0x08, // JP 0x208
+            0x00, 0xFD, // EXIT
+        ];
+        let disassembly = disassemble_rom(&rom);
+        assert_eq!(disassembly.computed_jumps, [0x200]);
+        assert_eq!(disassembly.lines[0].text, "JP V0, table_204");
+        assert_eq!(
+            disassembly.lines[0].comment.as_deref(),
+            Some("computed jump")
+        );
+        assert!(
+            disassembly
+                .lines
+                .iter()
+                .all(|line| line.kind == Kind::Data || line.address == 0x200),
+            "Targets of computed jumps should not be followed"
+        );
+
+        let disassembly = disassemble(&rom, 0x200, &[0x200, 0x204]);
+        assert_eq!(disassembly.lines[2].label.as_deref(), Some("entry_204"));
+        assert_eq!(disassembly.lines[2].kind, Kind::Code);
+    }
+
+    #[test]
+    fn test_sprites() {
+        let rom = [
+            0xA2, 0x08, // LD I, 0x208
+            0xD0, 0x13, // DRW V0, V1, 3
+            0xA2, 0x0B, // LD I, 0x20B
+            0x00, 0xFD, // EXIT
+            0xF0, 0x90, 0xF0, // sprite
+            0x01, 0x02, // data read some other way
+        ];
+        let disassembly = disassemble_rom(&rom);
+        let sprite: Vec<_> = disassembly
+            .lines
+            .iter()
+            .filter(|line| line.kind == Kind::Sprite)
+            .collect();
+        assert_eq!(sprite.len(), 3, "Each sprite row should be its own line");
+        assert_eq!(sprite[0].label.as_deref(), Some("sprite_208"));
+        assert_eq!(sprite[1].text, "db 0b10010000");
+        assert_eq!(sprite[1].comment.as_deref(), Some("#..#...."));
+
+        let data = disassembly.lines.last().unwrap();
+        assert_eq!(data.kind, Kind::Data);
+        assert_eq!(data.label.as_deref(), Some("data_20B"));
+        assert_eq!(data.text, "db 0x01, 0x02");
+    }
+
+    #[test]
+    fn test_invalid_opcode() {
+        let rom = [0x60, 0x01, 0xFF, 0xFF];
+        let disassembly = disassemble_rom(&rom);
+        let last = disassembly.lines.last().unwrap();
+        assert_eq!(last.kind, Kind::Data);
+        assert_eq!(
+            last.comment.as_deref(),
+            Some("reachable, but not an instruction")
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let json = disassemble_rom(&[0xA2, 0x04, 0xD0, 0x11, 0x80]).to_json();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["lines"][0]["kind"], "code");
+        assert_eq!(value["lines"][0]["text"], "LD I, sprite_204");
+        assert_eq!(value["lines"][2]["kind"], "sprite");
+        assert_eq!(value["lines"][2]["address"], 0x204);
+    }
+
+    #[test]
+    fn test_reassembles() {
+        for (name, rom) in &[
+            ("maze", &include_bytes!("maze.ch8")[..]),
+            ("pong", &include_bytes!("pong.ch8")[..]),
+            ("trip8", &include_bytes!("trip8.ch8")[..]),
+        ] {
+            let text = disassemble_rom(rom).text();
+            let assembly = assemble(&text).unwrap_or_else(|errors| {
+                panic!("{} did not reassemble: {}\n{}", name, errors[0], text)
+            });
+            assert_eq!(
+                &assembly.rom[..],
+                &rom[..],
+                "{} should reassemble to the same bytes",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Integer expressions with the operators and precedence of C, shared by assembler
+//! operands and breakpoint conditions.
+//!
+//! Numbers are decimal, hex (`0x`) or binary (`0b`), with `_` allowed between digits,
+//! or a character in single quotes. Other words, like `loop` or `V3`, and `$` are
+//! names, which the caller gives a meaning to.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    /// Character position of the problem, counting from 1.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+/// Which parts of the syntax an expression may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Syntax {
+    /// `|| && == != < <= > >=` and the unary `!`, which give 1 or 0.
+    pub comparisons: bool,
+    /// `[address]`, the byte at an address.
+    pub memory: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Op {
+    fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            Op::Or | Op::And | Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge
+        )
+    }
+}
+
+/// Binary operators by spelling, longest first so `<=` is not read as `<`, and
+/// their precedence, higher binding tighter.
+const OPERATORS: &[(&str, Op, u8)] = &[
+    ("||", Op::Or, 1),
+    ("&&", Op::And, 2),
+    ("==", Op::Eq, 6),
+    ("!=", Op::Ne, 6),
+    ("<=", Op::Le, 7),
+    (">=", Op::Ge, 7),
+    ("<<", Op::Shl, 8),
+    (">>", Op::Shr, 8),
+    ("|", Op::BitOr, 3),
+    ("^", Op::BitXor, 4),
+    ("&", Op::BitAnd, 5),
+    ("<", Op::Lt, 7),
+    (">", Op::Gt, 7),
+    ("+", Op::Add, 9),
+    ("-", Op::Sub, 9),
+    ("*", Op::Mul, 10),
+    ("/", Op::Div, 10),
+    ("%", Op::Rem, 10),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unary {
+    Not,
+    Neg,
+    Invert,
+}
+
+/// A parsed expression whose names have been turned into `T`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr<T> {
+    Number(i64),
+    /// A name and the column it starts at.
+    Name(T, usize),
+    /// The byte at an address.
+    Memory(Box<Expr<T>>),
+    Unary(Unary, Box<Expr<T>>),
+    /// An operator, the column it is at, and its operands.
+    Binary(Op, usize, Box<Expr<T>>, Box<Expr<T>>),
+}
+
+/// What the names and memory in an expression hold.
+pub trait Env<T> {
+    /// The value of `name`, which is at `column`.
+    fn value(&self, name: &T, column: usize) -> Result<i64, ExprError>;
+
+    /// The byte at `address`, for `[address]`.
+    fn memory(&self, _address: i64) -> i64 {
+        0
+    }
+}
+
+impl<T> Expr<T> {
+    /// Parse `text`, which starts at `column`. `name` turns each name into a `T`, or
+    /// explains why it cannot.
+    pub fn parse(
+        text: &str,
+        column: usize,
+        syntax: Syntax,
+        name: impl FnMut(&str) -> Result<T, String>,
+    ) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            column,
+            syntax,
+            name,
+        };
+        let expr = parser.expr(0)?;
+        parser.skip_space();
+        if parser.pos < text.len() {
+            return Err(parser.error("expected an operator"));
+        }
+        Ok(expr)
+    }
+
+    /// Evaluate with wrapping arithmetic. Dividing by zero or overflowing a division
+    /// is an error.
+    pub fn eval<E: Env<T> + ?Sized>(&self, env: &E) -> Result<i64, ExprError> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Name(name, column) => env.value(name, *column)?,
+            Expr::Memory(address) => env.memory(address.eval(env)?),
+            Expr::Unary(Unary::Not, e) => (e.eval(env)? == 0) as i64,
+            Expr::Unary(Unary::Neg, e) => e.eval(env)?.wrapping_neg(),
+            Expr::Unary(Unary::Invert, e) => !e.eval(env)?,
+            Expr::Binary(Op::Or, _, a, b) => (a.eval(env)? != 0 || b.eval(env)? != 0) as i64,
+            Expr::Binary(Op::And, _, a, b) => (a.eval(env)? != 0 && b.eval(env)? != 0) as i64,
+            Expr::Binary(op, column, a, b) => {
+                let (a, b) = (a.eval(env)?, b.eval(env)?);
+                let error = |message: &str| ExprError {
+                    column: *column,
+                    message: message.to_string(),
+                };
+                match op {
+                    Op::BitOr => a | b,
+                    Op::BitXor => a ^ b,
+                    Op::BitAnd => a & b,
+                    Op::Eq => (a == b) as i64,
+                    Op::Ne => (a != b) as i64,
+                    Op::Lt => (a < b) as i64,
+                    Op::Le => (a <= b) as i64,
+                    Op::Gt => (a > b) as i64,
+                    Op::Ge => (a >= b) as i64,
+                    Op::Shl => a.checked_shl(b as u32).unwrap_or(0),
+                    Op::Shr => a.checked_shr(b as u32).unwrap_or(0),
+                    Op::Add => a.wrapping_add(b),
+                    Op::Sub => a.wrapping_sub(b),
+                    Op::Mul => a.wrapping_mul(b),
+                    Op::Div | Op::Rem if b == 0 => return Err(error("division by zero")),
+                    Op::Div => a
+                        .checked_div(b)
+                        .ok_or_else(|| error("division overflows"))?,
+                    Op::Rem => a
+                        .checked_rem(b)
+                        .ok_or_else(|| error("division overflows"))?,
+                    Op::Or | Op::And => unreachable!(),
+                }
+            }
+        })
+    }
+}
+
+struct Parser<'a, F> {
+    text: &'a str,
+    /// Byte offset of the next character.
+    pos: usize,
+    /// Column of the first character of `text`.
+    column: usize,
+    syntax: Syntax,
+    name: F,
+}
+
+impl<T, F: FnMut(&str) -> Result<T, String>> Parser<'_, F> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn here(&self) -> usize {
+        self.column + self.text[..self.pos].chars().count()
+    }
+
+    fn error(&self, message: &str) -> ExprError {
+        ExprError {
+            column: self.here(),
+            message: message.to_string(),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// An expression whose binary operators bind tighter than `min_precedence`.
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr<T>, ExprError> {
+        let mut lhs = self.unary()?;
+        loop {
+            self.skip_space();
+            let rest = self.rest();
+            let comparisons = self.syntax.comparisons;
+            let (token, op, precedence) = match OPERATORS
+                .iter()
+                .filter(|(_, op, _)| comparisons || !op.is_comparison())
+                .find(|(token, _, _)| rest.starts_with(token))
+            {
+                Some(&operator) => operator,
+                None => return Ok(lhs),
+            };
+            if precedence <= min_precedence {
+                return Ok(lhs);
+            }
+            let column = self.here();
+            self.pos += token.len();
+            let rhs = self.expr(precedence)?;
+            lhs = Expr::Binary(op, column, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr<T>, ExprError> {
+        // `!=` never starts an operand, so a leading `!` is always "not".
+        let op = if self.syntax.comparisons && self.eat("!") {
+            Unary::Not
+        } else if self.eat("-") {
+            Unary::Neg
+        } else if self.eat("~") {
+            Unary::Invert
+        } else if self.eat("+") {
+            return self.unary();
+        } else {
+            return self.primary();
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr<T>, ExprError> {
+        self.skip_space();
+        if self.eat("(") {
+            let expr = self.expr(0)?;
+            if !self.eat(")") {
+                return Err(self.error("expected `)`"));
+            }
+            return Ok(expr);
+        }
+        if self.syntax.memory && self.eat("[") {
+            let expr = self.expr(0)?;
+            if !self.eat("]") {
+                return Err(self.error("expected `]`"));
+            }
+            return Ok(Expr::Memory(Box::new(expr)));
+        }
+        if self.rest().starts_with('\'') {
+            let mut chars = self.rest()[1..].chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), Some('\'')) => {
+                    self.pos += 2 + c.len_utf8();
+                    Ok(Expr::Number(c as i64))
+                }
+                _ => Err(self.error("bad character literal")),
+            };
+        }
+
+        let len = if self.rest().starts_with('$') {
+            1
+        } else {
+            self.rest()
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.')
+                .unwrap_or_else(|| self.rest().len())
+        };
+        if len == 0 {
+            return Err(self.error("expected a value"));
+        }
+        let word = &self.text[self.pos..self.pos + len];
+        let column = self.here();
+        let expr = if word.starts_with(|c: char| c.is_ascii_digit()) {
+            Expr::Number(
+                parse_number(word).ok_or_else(|| self.error(&format!("bad number `{}`", word)))?,
+            )
+        } else {
+            let name = (self.name)(word).map_err(|message| ExprError { column, message })?;
+            Expr::Name(name, column)
+        };
+        self.pos += len;
+        Ok(expr)
+    }
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let lower = word.replace('_', "").to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Env, Expr, ExprError, Syntax};
+
+    const FULL: Syntax = Syntax {
+        comparisons: true,
+        memory: true,
+    };
+
+    /// Names are their length, and memory holds the low byte of the address.
+    struct Lengths;
+
+    impl Env<String> for Lengths {
+        fn value(&self, name: &String, _column: usize) -> Result<i64, ExprError> {
+            Ok(name.len() as i64)
+        }
+
+        fn memory(&self, address: i64) -> i64 {
+            address & 0xFF
+        }
+    }
+
+    fn parse(text: &str, syntax: Syntax) -> Result<Expr<String>, ExprError> {
+        Expr::parse(text, 1, syntax, |name| Ok(name.to_string()))
+    }
+
+    fn eval(text: &str) -> Result<i64, ExprError> {
+        parse(text, FULL)?.eval(&Lengths)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("6 & 3 == 2"), Ok(0), "== should bind tighter than &");
+        assert_eq!(eval("1 < 2 && 2 <= 2 && 3 >= 4 || 5 != 5"), Ok(0));
+        assert_eq!(
+            eval("10 - 3 - 2"),
+            Ok(5),
+            "Operators should be left associative"
+        );
+        assert_eq!(eval("!0 + -1 + +1 + ~0"), Ok(0));
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(eval("0x1_F + 0b11 + 10 + 'A'"), Ok(0x1F + 3 + 10 + 65));
+        assert_eq!(eval("abc * $"), Ok(3));
+        assert_eq!(eval("[0x1234]"), Ok(0x34));
+    }
+
+    #[test]
+    fn test_syntax() {
+        let plain = Syntax {
+            comparisons: false,
+            memory: false,
+        };
+        assert_eq!(parse("1 == 1", plain).unwrap_err().column, 3);
+        assert_eq!(parse("[1]", plain).unwrap_err().column, 1);
+        assert!(parse("1 << 2", plain).is_ok());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text| eval(text).unwrap_err();
+        assert_eq!(error("3 ==").column, 5);
+        assert_eq!(error("(1 + 2").message, "expected `)`");
+        assert_eq!(error("1 2").column, 3);
+        assert_eq!(error("").column, 1);
+        assert_eq!(error("0xZ").message, "bad number `0xZ`");
+        assert_eq!(error("1 / 0").message, "division by zero");
+        assert_eq!(error("(1 << 63) % -1").column, 11);
+        assert_eq!(
+            Expr::parse("1 + x", 5, FULL, |_| Err::<(), _>("no".to_string())),
+            Err(ExprError {
+                column: 9,
+                message: "no".to_string()
+            }),
+            "Columns should count from where the text starts"
+        );
+    }
+}
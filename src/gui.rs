@@ -3,8 +3,17 @@ use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
 use egui_winit_platform::{Platform, PlatformDescriptor};
 use pixels::{wgpu, PixelsContext};
 use std::time::Instant;
+use winit::event::{ElementState, KeyboardInput};
 
-use crate::{World, cpu::ExecutionState};
+use crate::breakpoints::Draft;
+use crate::code_view::CodeView;
+use crate::keymap::KeyBindings;
+use crate::keypad::Keypad;
+use crate::memory_view::MemoryView;
+use crate::sprite_view::SpriteView;
+use chip8::cpu::{CpuEvent, ExecutionState, Trace};
+use chip8::movie::Movie;
+use chip8::world::{World, MAX_SPEED, MIN_SPEED, SAVE_SLOTS};
 
 /// Manages all state required for rendering egui over `Pixels`.
 pub(crate) struct Gui {
@@ -18,6 +27,17 @@ pub(crate) struct Gui {
     // State for the demo app.
     // window_open: bool,
     pub world: World,
+    pub keys: KeyBindings,
+    /// Address entered for "Run to".
+    run_to: u16,
+    /// Addresses of the instructions to trace, inclusive.
+    trace_range: (u16, u16),
+    /// Breakpoint being entered in the CPU controls window.
+    breakpoint: Draft,
+    memory: MemoryView,
+    sprites: SpriteView,
+    keypad: Keypad,
+    code_view: CodeView,
 }
 
 impl Gui {
@@ -28,6 +48,7 @@ impl Gui {
         scale_factor: f64,
         context: &PixelsContext,
         world: World,
+        keys: KeyBindings,
     ) -> Self {
         let platform = Platform::new(PlatformDescriptor {
             physical_width: width,
@@ -51,6 +72,14 @@ impl Gui {
             paint_jobs: Vec::new(),
             // window_open: true,
             world,
+            keys,
+            run_to: 0x200,
+            trace_range: (0x000, 0xFFFF),
+            breakpoint: Draft::default(),
+            memory: MemoryView::default(),
+            sprites: SpriteView::default(),
+            keypad: Keypad::default(),
+            code_view: CodeView::default(),
         }
     }
 
@@ -59,6 +88,23 @@ impl Gui {
         self.platform.handle_event(event);
     }
 
+    /// Route a host key to the rebinding prompt or to the emulated keypad.
+    pub(crate) fn handle_key(&mut self, input: KeyboardInput) {
+        let rom_hash = self.world.cpu.rom_hash();
+        if input.state == ElementState::Pressed
+            && self.keys.capture(rom_hash, input.virtual_keycode, input.scancode)
+        {
+            return;
+        }
+        let keymap = self.keys.active(rom_hash);
+        if let Some(index) = keymap.lookup(input.virtual_keycode, input.scancode) {
+            self.world.handle_event(match input.state {
+                ElementState::Pressed => CpuEvent::KeyPressed(index),
+                ElementState::Released => CpuEvent::KeyReleased(index),
+            });
+        }
+    }
+
     /// Resize egui.
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
         self.screen_descriptor.physical_width = width;
@@ -91,8 +137,10 @@ impl Gui {
         egui::TopPanel::top("menubar_container").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 egui::menu::menu(ui, "File", |ui| {
-                    ui.set_enabled(self.world.cpu.execution_state == ExecutionState::Paused);
-                    if ui.button("Load ROM").clicked() {
+                    if ui
+                        .add(egui::Button::new("Load ROM").enabled(!self.world.cpu.is_active()))
+                        .clicked()
+                    {
                         log::debug!("Opening file dialog...");
                         let file = rfd::FileDialog::new().set_directory(".").pick_file();
                         if let Some(file) = file {
@@ -101,10 +149,93 @@ impl Gui {
                                 use std::io::Read;
                                 let mut rom = Vec::new();
                                 if file.read_to_end(&mut rom).is_ok() {
-                                    self.world.cpu.load_rom(&rom)
+                                    self.world.load_rom(&rom)
+                                }
+                            }
+                        }
+                    }
+                    ui.separator();
+                    let world = &mut self.world;
+                    if ui.button("Save state (F5)").clicked() {
+                        if let Err(e) = world.save_slot() {
+                            log::error!("Failed to save state: {}", e);
+                        }
+                    }
+                    if ui
+                        .add(
+                            egui::Button::new("Load state (F9)")
+                                .enabled(world.slot_used(world.slot)),
+                        )
+                        .clicked()
+                    {
+                        if let Err(e) = world.load_slot() {
+                            log::error!("Failed to load state: {}", e);
+                        }
+                    }
+                    ui.label("Slot (PgUp/PgDn):");
+                    ui.horizontal(|ui| {
+                        for slot in 0..SAVE_SLOTS {
+                            let label = if world.slot_used(slot) {
+                                format!("{}*", slot)
+                            } else {
+                                slot.to_string()
+                            };
+                            ui.radio_value(&mut world.slot, slot, label);
+                        }
+                    });
+                    if ui.button("Export state as JSON").clicked() {
+                        let file = rfd::FileDialog::new()
+                            .set_directory(".")
+                            .add_filter("JSON", &["json"])
+                            .save_file();
+                        if let Some(file) = file {
+                            if let Err(e) = world.export_json(&file) {
+                                log::error!("Failed to export state: {}", e);
+                            }
+                        }
+                    }
+                    ui.separator();
+                    let movie_active = world.recording().is_some() || world.playing().is_some();
+                    if world.recording().is_some() {
+                        if ui.button("Stop recording").clicked() {
+                            if let Some(movie) = world.stop_recording() {
+                                let file = rfd::FileDialog::new()
+                                    .set_directory(".")
+                                    .add_filter("CHIP-8 movie", &["c8m"])
+                                    .save_file();
+                                if let Some(file) = file {
+                                    if let Err(e) = movie.save(&file) {
+                                        log::error!("Failed to save movie: {}", e);
+                                    }
                                 }
                             }
                         }
+                    } else if ui
+                        .add(egui::Button::new("Record movie").enabled(!movie_active))
+                        .clicked()
+                    {
+                        world.start_recording();
+                    }
+                    if world.playing().is_some() {
+                        if ui.button("Stop playback").clicked() {
+                            world.stop_playback();
+                        }
+                    } else if ui
+                        .add(egui::Button::new("Play movie").enabled(!movie_active))
+                        .clicked()
+                    {
+                        let file = rfd::FileDialog::new()
+                            .set_directory(".")
+                            .add_filter("CHIP-8 movie", &["c8m"])
+                            .pick_file();
+                        if let Some(file) = file {
+                            let result = Movie::load(&file).and_then(|movie| {
+                                world.play_movie(movie).map_err(|e| e.to_string())
+                            });
+                            if let Err(e) = result {
+                                log::error!("Failed to play movie: {}", e);
+                            }
+                        }
                     }
                     // if ui.button("About...").clicked() {
                     //     self.window_open = true;
@@ -114,7 +245,151 @@ impl Gui {
         });
 
         egui::Window::new("CPU controls").show(ctx, |ui| {
-            self.world.cpu.draw_ui(ui);
+            let world = &mut self.world;
+            let run_to = &mut self.run_to;
+            let trace_range = &mut self.trace_range;
+            let breakpoint = &mut self.breakpoint;
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::Slider::f32(&mut world.speed, MIN_SPEED..=MAX_SPEED).text("speed"),
+                );
+                for &speed in [0.25, 0.5, 1.0, 2.0, 4.0, 16.0].iter() {
+                    if ui.button(format!("{}x", speed)).clicked() {
+                        world.speed = speed;
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut world.fast_forward, "Fast-forward (hold Tab)");
+                if ui
+                    .add(
+                        egui::Button::new("Frame advance (F6)")
+                            .enabled(world.cpu.execution_state == ExecutionState::Paused),
+                    )
+                    .clicked()
+                {
+                    world.frame_advance();
+                }
+            });
+            if let Some(frame) = world.recording() {
+                ui.label(format!("Recording movie: frame {}", frame));
+            }
+            if let Some(frame) = world.playing() {
+                ui.label(format!("Playing movie: frame {}", frame));
+            }
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut world.rewinding, "Rewind (hold F4)");
+                let paused = world.cpu.execution_state == ExecutionState::Paused;
+                if ui
+                    .add(egui::Button::new("Step back frame").enabled(paused))
+                    .clicked()
+                {
+                    world.step_back_frame();
+                }
+                if ui
+                    .add(egui::Button::new("Step back instruction").enabled(paused))
+                    .clicked()
+                {
+                    world.step_back_instruction();
+                }
+                ui.label(format!(
+                    "{} frames ({:.1} MiB)",
+                    world.rewind.len(),
+                    world.rewind.size() as f32 / (1024.0 * 1024.0)
+                ));
+            });
+            ui.horizontal(|ui| {
+                let paused = world.cpu.execution_state == ExecutionState::Paused;
+                if ui
+                    .add(egui::Button::new("Step (F11)").enabled(paused))
+                    .clicked()
+                {
+                    world.step();
+                }
+                if ui
+                    .add(egui::Button::new("Step over (F10)").enabled(paused))
+                    .clicked()
+                {
+                    world.step_over();
+                }
+                if ui
+                    .add(egui::Button::new("Step out (Shift+F11)").enabled(paused))
+                    .clicked()
+                {
+                    world.step_out();
+                }
+                ui.separator();
+                if ui
+                    .add(egui::Button::new("Run to").enabled(paused))
+                    .clicked()
+                {
+                    world.run_to(*run_to);
+                }
+                ui.add(egui::DragValue::u16(run_to).clamp_range(0.0..=0xFFFE as f32));
+                ui.label(format!("0x{:03X}", run_to));
+            });
+            ui.horizontal(|ui| {
+                let traced = world.cpu.trace.as_ref().map(|trace| trace.lines());
+                if let Some(lines) = traced {
+                    if ui.button("Stop trace").clicked() {
+                        world.cpu.trace = None;
+                    }
+                    ui.label(format!("{} instructions traced", lines));
+                } else {
+                    if ui.button("Trace to file").clicked() {
+                        let file = rfd::FileDialog::new()
+                            .set_directory(".")
+                            .add_filter("Trace", &["trace", "txt"])
+                            .save_file();
+                        if let Some(file) = file {
+                            match Trace::create(&file) {
+                                Ok(trace) => {
+                                    let (start, end) = *trace_range;
+                                    world.cpu.trace = Some(trace.range(start, end));
+                                }
+                                Err(e) => log::error!("Failed to start trace: {}", e),
+                            }
+                        }
+                    }
+                    ui.label("addresses");
+                    ui.add(egui::DragValue::u16(&mut trace_range.0));
+                    ui.label("to");
+                    ui.add(egui::DragValue::u16(&mut trace_range.1));
+                }
+            });
+            ui.collapsing("Breakpoints", |ui| {
+                breakpoint.draw_ui(ui, &mut world.cpu.debugger)
+            });
+            ui.separator();
+            let movie_active = world.recording().is_some() || world.playing().is_some();
+            world.cpu.draw_ui(ui, movie_active);
+        });
+
+        let world = &mut self.world;
+        let memory = &mut self.memory;
+        egui::Window::new("Memory").show(ctx, |ui| memory.draw_ui(ui, world));
+        let sprites = &mut self.sprites;
+        egui::Window::new("Sprites").show(ctx, |ui| {
+            if let Some(addr) = sprites.draw_ui(ui, &world.cpu) {
+                memory.go_to(addr);
+            }
+        });
+
+        let world = &mut self.world;
+        let code_view = &mut self.code_view;
+        egui::Window::new("Disassembly").show(ctx, |ui| {
+            code_view.draw_ui(ui, world);
+        });
+
+        let keypad = &mut self.keypad;
+        egui::Window::new("Keypad").show(ctx, |ui| {
+            keypad.draw_ui(ui, world);
+        });
+
+        let rom_hash = self.world.cpu.rom_hash();
+        let keys = &mut self.keys;
+        egui::Window::new("Key bindings").show(ctx, |ui| {
+            keys.draw_ui(ui, rom_hash);
         });
     }
 
@@ -0,0 +1,251 @@
+//! Running ROMs without a window, for `chip8-headless` and automated tests.
+
+use std::fmt;
+
+use crate::cpu::{Cpu, CpuFault, ExecutionState, Instruction};
+use crate::movie::{Movie, MovieError, Player};
+
+/// Frames to run when neither a frame count nor a movie says otherwise: 10 seconds.
+pub const DEFAULT_FRAMES: u64 = 600;
+
+/// Stop as soon as this happens, before the frame limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopCondition {
+    /// The CPU is about to execute the instruction at this address.
+    Pc(u16),
+    /// The program is spinning on a jump to itself, or waiting for a key no input will press.
+    Idle,
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Maximum number of frames to run. Defaults to the length of the movie, or `DEFAULT_FRAMES`.
+    pub frames: Option<u64>,
+    pub until: Vec<StopCondition>,
+    /// Input to feed the ROM.
+    pub movie: Option<Movie>,
+}
+
+/// Why a headless run ended.
+#[derive(Debug)]
+pub enum Outcome {
+    /// All frames ran and no stop condition was given.
+    Finished,
+    Stopped(StopCondition),
+    /// The program exited with `00FD`.
+    Exited,
+    Faulted {
+        fault: CpuFault,
+        pc: u16,
+    },
+    /// Stop conditions were given, but none was met within the frame limit.
+    TimedOut,
+    Movie(MovieError),
+}
+
+impl Outcome {
+    /// Process exit code for the outcome: 0 on success, 2 on a fault, 3 if the
+    /// movie did not replay, 4 on a timeout. Code 1 is left for usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Finished | Outcome::Stopped(_) | Outcome::Exited => 0,
+            Outcome::Faulted { .. } => 2,
+            Outcome::Movie(_) => 3,
+            Outcome::TimedOut => 4,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Finished => write!(f, "ran to the frame limit"),
+            Outcome::Stopped(StopCondition::Pc(pc)) => write!(f, "reached 0x{:03X}", pc),
+            Outcome::Stopped(StopCondition::Idle) => write!(f, "program went idle"),
+            Outcome::Exited => write!(f, "program exited"),
+            Outcome::Faulted { fault, pc } => write!(f, "{} at 0x{:X}", fault, pc),
+            Outcome::TimedOut => write!(f, "no stop condition was met within the frame limit"),
+            Outcome::Movie(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Load `rom` into `cpu` and run it as described by `options`. The CPU's quirks,
+/// clock and RNG are used as they are, unless a movie overrides them.
+pub fn run(cpu: &mut Cpu, rom: &[u8], options: Options) -> Outcome {
+    let mut player = None;
+    let frames = match options.movie {
+        Some(movie) => {
+            let length = movie.frames;
+            match Player::new(movie, cpu, rom) {
+                Ok(p) => player = Some(p),
+                Err(e) => return Outcome::Movie(e),
+            }
+            options.frames.unwrap_or(length)
+        }
+        None => {
+            cpu.load_rom(rom);
+            cpu.resume();
+            options.frames.unwrap_or(DEFAULT_FRAMES)
+        }
+    };
+
+    for _ in 0..frames {
+        if let Some(player) = &mut player {
+            player.begin_frame(cpu);
+        }
+        loop {
+            let input_pending = matches!(&player, Some(p) if p.pending_events() > 0);
+            if let Some(&condition) = options
+                .until
+                .iter()
+                .find(|&&condition| is_met(cpu, condition, input_pending))
+            {
+                return Outcome::Stopped(condition);
+            }
+            let ticked = cpu.clock_cycle();
+            match cpu.execution_state {
+                ExecutionState::Faulted { fault, pc } => return Outcome::Faulted { fault, pc },
+                ExecutionState::Halted => return Outcome::Exited,
+                _ => {}
+            }
+            if ticked || !cpu.is_active() {
+                break;
+            }
+        }
+        if let Some(player) = &mut player {
+            if let Err(e) = player.end_frame(cpu) {
+                return Outcome::Movie(e);
+            }
+        }
+    }
+    if options.until.is_empty() {
+        Outcome::Finished
+    } else {
+        Outcome::TimedOut
+    }
+}
+
+fn is_met(cpu: &Cpu, condition: StopCondition, input_pending: bool) -> bool {
+    match condition {
+        StopCondition::Pc(pc) => cpu.pc() == pc,
+        StopCondition::Idle => match cpu.execution_state {
+            ExecutionState::WaitingForKey(_) => !input_pending,
+            ExecutionState::Running => {
+                let pc = cpu.pc() as usize;
+                match cpu.memory().get(pc..pc + 2) {
+                    Some(&[hi, lo]) => {
+                        Instruction::decode([hi, lo]) == Ok(Instruction::Jp(pc as u16))
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, Options, Outcome, StopCondition};
+    use crate::cpu::{Cpu, CpuFault};
+    use crate::movie::Movie;
+
+    #[test]
+    fn test_frame_limit() {
+        let mut cpu = Cpu::new();
+        cpu.instructions_per_second = 600;
+        let options = Options {
+            frames: Some(3),
+            ..Options::default()
+        };
+        let outcome = run(&mut cpu, &[0x12, 0x00], options);
+        assert!(matches!(outcome, Outcome::Finished));
+        assert_eq!(cpu.cycle(), 30);
+    }
+
+    #[test]
+    fn test_until_pc() {
+        let mut cpu = Cpu::new();
+        let options = Options {
+            until: vec![StopCondition::Pc(0x204)],
+            ..Options::default()
+        };
+        let rom = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03];
+        let outcome = run(&mut cpu, &rom, options);
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped(StopCondition::Pc(0x204))
+        ));
+        assert_eq!(
+            cpu.cycle(),
+            2,
+            "The instruction at the address should not run"
+        );
+    }
+
+    #[test]
+    fn test_idle() {
+        let options = || Options {
+            until: vec![StopCondition::Idle],
+            ..Options::default()
+        };
+        let mut cpu = Cpu::new();
+        let outcome = run(&mut cpu, &[0x00, 0xE0, 0x12, 0x02], options());
+        assert!(matches!(outcome, Outcome::Stopped(StopCondition::Idle)));
+        assert_eq!(cpu.pc(), 0x202);
+
+        let mut cpu = Cpu::new();
+        let outcome = run(&mut cpu, &[0xF0, 0x0A, 0x12, 0x00], options());
+        assert!(
+            matches!(outcome, Outcome::Stopped(StopCondition::Idle)),
+            "Waiting for a key with no input should count as idle"
+        );
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let mut cpu = Cpu::new();
+        let outcome = run(&mut cpu, &[0x00, 0xEE], Options::default());
+        assert!(matches!(
+            outcome,
+            Outcome::Faulted {
+                fault: CpuFault::StackUnderflow,
+                pc: 0x200
+            }
+        ));
+        assert_eq!(outcome.exit_code(), 2);
+
+        let outcome = run(&mut Cpu::new(), &[0x00, 0xFD], Options::default());
+        assert_eq!(outcome.exit_code(), 0);
+
+        let options = Options {
+            frames: Some(1),
+            until: vec![StopCondition::Pc(0x300)],
+            ..Options::default()
+        };
+        let outcome = run(&mut Cpu::new(), &[0x12, 0x00], options);
+        assert_eq!(outcome.exit_code(), 4);
+    }
+
+    #[test]
+    fn test_input_script() {
+        let rom = [
+            0xF0, 0x0A, // LD V0, K
+            0x00, 0xFD, // EXIT
+        ];
+        let script = format!(
+            "rom {:016x}\nhold key 7 frames 30-31\n",
+            crate::cpu::hash(&rom)
+        );
+        let options = Options {
+            frames: Some(60),
+            until: vec![StopCondition::Idle],
+            movie: Some(Movie::parse(&script).unwrap()),
+        };
+        let mut cpu = Cpu::new();
+        let outcome = run(&mut cpu, &rom, options);
+        assert!(matches!(outcome, Outcome::Exited), "got {}", outcome);
+        assert_eq!(cpu.save_state().reg[0], 7);
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use winit::event::VirtualKeyCode;
+
+mod ui;
+
+/// File the key bindings are persisted to, relative to the working directory.
+pub const CONFIG_PATH: &str = "keymap.cfg";
+
+/// A key on the host keyboard, either by its meaning or by its physical position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostKey {
+    Virtual(VirtualKeyCode),
+    Scancode(u32),
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKey::Virtual(key) => write!(f, "{:?}", key),
+            HostKey::Scancode(code) => write!(f, "scancode:{}", code),
+        }
+    }
+}
+
+impl HostKey {
+    fn parse(s: &str) -> Option<Self> {
+        if let Some(code) = s.strip_prefix("scancode:") {
+            return code.parse().ok().map(HostKey::Scancode);
+        }
+        BINDABLE_KEYS
+            .iter()
+            .find(|key| format!("{:?}", key) == s)
+            .map(|&key| HostKey::Virtual(key))
+    }
+}
+
+/// Keys that can be written to and read back from the config file by name.
+#[rustfmt::skip]
+static BINDABLE_KEYS: &[VirtualKeyCode] = {
+    use VirtualKeyCode::*;
+    &[
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        NumpadAdd, NumpadDivide, NumpadDecimal, NumpadComma, NumpadEnter, NumpadEquals,
+        NumpadMultiply, NumpadSubtract,
+        Up, Down, Left, Right, Space, Return, Tab, Back,
+        Apostrophe, Comma, Period, Semicolon, Slash, Backslash, Minus, Equals,
+        LBracket, RBracket, Grave,
+    ]
+};
+
+/// Built-in layouts. All of them follow the shape of the COSMAC VIP keypad:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// `1234`/`QWER`/`ASDF`/`ZXCV`.
+    Qwerty,
+    /// `1234`/`AZER`/`QSDF`/`WXCV`.
+    Azerty,
+    /// `1234`/`',.P`/`AOEU`/`;QJK`.
+    Dvorak,
+    /// Digits on the numpad map to themselves, `A`-`F` go on the operator keys.
+    Numpad,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [Preset::Qwerty, Preset::Azerty, Preset::Dvorak, Preset::Numpad];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Qwerty => "QWERTY",
+            Preset::Azerty => "AZERTY",
+            Preset::Dvorak => "Dvorak",
+            Preset::Numpad => "Numpad",
+        }
+    }
+}
+
+/// Bindings from host keys to the 16 CHIP-8 keys, indexed by hex key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    pub keys: [Option<HostKey>; 16],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::preset(Preset::Qwerty)
+    }
+}
+
+impl Keymap {
+    pub fn preset(preset: Preset) -> Self {
+        use VirtualKeyCode::*;
+        // Host keys for 0x0..=0xF, in order.
+        let keys = match preset {
+            Preset::Qwerty => [X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V],
+            Preset::Azerty => [X, Key1, Key2, Key3, A, Z, E, Q, S, D, W, C, Key4, R, F, V],
+            Preset::Dvorak => [
+                Q, Key1, Key2, Key3, Apostrophe, Comma, Period, A, O, E, Semicolon, J, Key4, P, U,
+                K,
+            ],
+            Preset::Numpad => [
+                Numpad0,
+                Numpad1,
+                Numpad2,
+                Numpad3,
+                Numpad4,
+                Numpad5,
+                Numpad6,
+                Numpad7,
+                Numpad8,
+                Numpad9,
+                NumpadDivide,
+                NumpadMultiply,
+                NumpadSubtract,
+                NumpadAdd,
+                NumpadEnter,
+                NumpadDecimal,
+            ],
+        };
+        let mut keymap = Self { keys: [None; 16] };
+        for (slot, &key) in keymap.keys.iter_mut().zip(keys.iter()) {
+            *slot = Some(HostKey::Virtual(key));
+        }
+        keymap
+    }
+
+    /// Find the hex key a host key is bound to. Bindings by key meaning take
+    /// priority over bindings by scancode.
+    pub fn lookup(&self, key: Option<VirtualKeyCode>, scancode: u32) -> Option<usize> {
+        let position = |host: HostKey| self.keys.iter().position(|&k| k == Some(host));
+        key.and_then(|key| position(HostKey::Virtual(key)))
+            .or_else(|| position(HostKey::Scancode(scancode)))
+    }
+
+    /// Bind `host` to `index`, taking it away from any other hex key.
+    pub fn bind(&mut self, index: usize, host: HostKey) {
+        for key in self.keys.iter_mut() {
+            if *key == Some(host) {
+                *key = None;
+            }
+        }
+        self.keys[index] = Some(host);
+    }
+}
+
+/// The global keymap plus per-ROM overrides, keyed by ROM hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyBindings {
+    pub global: Keymap,
+    pub overrides: HashMap<u64, Keymap>,
+    /// Hex key waiting for a host key press from the rebinding window.
+    rebinding: Option<usize>,
+}
+
+impl KeyBindings {
+    /// Load bindings from `path`, falling back to the defaults if it is missing.
+    /// Malformed lines are skipped with a warning.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        match std::fs::read_to_string(path.as_ref()) {
+            Ok(text) => {
+                let (bindings, errors) = Self::parse(&text);
+                for e in errors {
+                    log::warn!("{}: {}", path.as_ref().display(), e);
+                }
+                bindings
+            }
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) {
+        if let Err(e) = std::fs::write(path.as_ref(), self.to_string()) {
+            log::error!("Failed to save key bindings: {}", e);
+        }
+    }
+
+    /// Keymap to use for the ROM with the given hash.
+    pub fn active(&self, rom_hash: u64) -> &Keymap {
+        self.overrides.get(&rom_hash).unwrap_or(&self.global)
+    }
+
+    /// Offer a host key press to the rebinding prompt. Returns `true` if it was consumed.
+    pub fn capture(&mut self, rom_hash: u64, key: Option<VirtualKeyCode>, scancode: u32) -> bool {
+        let index = match self.rebinding.take() {
+            Some(index) => index,
+            None => return false,
+        };
+        let host = match key {
+            Some(key) if BINDABLE_KEYS.contains(&key) => HostKey::Virtual(key),
+            Some(key) => {
+                // It could not be read back from the config file; keep waiting.
+                log::warn!("{:?} cannot be bound, try another key", key);
+                self.rebinding = Some(index);
+                return true;
+            }
+            None => HostKey::Scancode(scancode),
+        };
+        let keymap = match self.overrides.get_mut(&rom_hash) {
+            Some(keymap) => keymap,
+            None => &mut self.global,
+        };
+        keymap.bind(index, host);
+        self.save(CONFIG_PATH);
+        true
+    }
+
+    /// Parse the config file format written by the `Display` impl:
+    ///
+    /// ```text
+    /// [global]
+    /// 0 = none
+    /// 1 = X
+    /// [rom 0123456789abcdef]
+    /// 0 = scancode:45
+    /// ```
+    ///
+    /// Returns the bindings from every line that could be read, and an error for each
+    /// line that could not.
+    fn parse(text: &str) -> (Self, Vec<String>) {
+        let mut bindings = Self::default();
+        let mut errors = Vec::new();
+        // Lines of a section with a bad header go here and are thrown away.
+        let mut skipped = Keymap { keys: [None; 16] };
+        let mut current = &mut bindings.global;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", number + 1, msg);
+            if line == "[global]" {
+                current = &mut bindings.global;
+            } else if let Some(hash) = line
+                .strip_prefix("[rom ")
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                current = match u64::from_str_radix(hash, 16) {
+                    Ok(hash) => bindings
+                        .overrides
+                        .entry(hash)
+                        .or_insert(Keymap { keys: [None; 16] }),
+                    Err(_) => {
+                        errors.push(err("bad ROM hash, skipping its section"));
+                        &mut skipped
+                    }
+                };
+            } else {
+                let mut parts = line.splitn(2, '=').map(str::trim);
+                let index = parts
+                    .next()
+                    .and_then(|index| usize::from_str_radix(index, 16).ok())
+                    .filter(|&index| index < 16);
+                // `none` is written for unbound keys, so they stay unbound over the defaults.
+                let host = parts.next().and_then(|host| match host {
+                    "none" => Some(None),
+                    host => HostKey::parse(host).map(Some),
+                });
+                match (index, host) {
+                    (Some(index), Some(host)) => current.keys[index] = host,
+                    (None, _) => errors.push(err("expected a hex key from 0 to F")),
+                    (_, None) => errors.push(err("unknown host key")),
+                }
+            }
+        }
+        (bindings, errors)
+    }
+}
+
+impl fmt::Display for KeyBindings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_keymap(f: &mut fmt::Formatter<'_>, keymap: &Keymap) -> fmt::Result {
+            for (index, key) in keymap.keys.iter().enumerate() {
+                match key {
+                    Some(key) => writeln!(f, "{:X} = {}", index, key)?,
+                    None => writeln!(f, "{:X} = none", index)?,
+                }
+            }
+            Ok(())
+        }
+
+        writeln!(f, "[global]")?;
+        write_keymap(f, &self.global)?;
+        let mut hashes: Vec<_> = self.overrides.keys().collect();
+        hashes.sort();
+        for hash in hashes {
+            writeln!(f, "[rom {:016x}]", hash)?;
+            write_keymap(f, &self.overrides[hash])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HostKey, KeyBindings, Keymap, Preset};
+    use winit::event::VirtualKeyCode;
+
+    #[test]
+    fn test_lookup() {
+        let keymap = Keymap::preset(Preset::Qwerty);
+        assert_eq!(keymap.lookup(Some(VirtualKeyCode::X), 0), Some(0x0));
+        assert_eq!(keymap.lookup(Some(VirtualKeyCode::Key4), 0), Some(0xC));
+        assert_eq!(keymap.lookup(Some(VirtualKeyCode::P), 0), None);
+    }
+
+    #[test]
+    fn test_bind_steals_key() {
+        let mut keymap = Keymap::preset(Preset::Qwerty);
+        keymap.bind(0x1, HostKey::Virtual(VirtualKeyCode::X));
+        assert_eq!(keymap.lookup(Some(VirtualKeyCode::X), 0), Some(0x1));
+        assert_eq!(keymap.keys[0x0], None, "X should no longer be bound to 0");
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let mut bindings = KeyBindings::default();
+        let mut keymap = Keymap::preset(Preset::Dvorak);
+        keymap.bind(0xF, HostKey::Scancode(57));
+        bindings.overrides.insert(0xdead_beef, keymap);
+        let (parsed, errors) = KeyBindings::parse(&bindings.to_string());
+        assert_eq!(parsed, bindings);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_config_roundtrip_after_steal() {
+        let mut bindings = KeyBindings::default();
+        bindings
+            .global
+            .bind(0x1, HostKey::Virtual(VirtualKeyCode::X));
+        let (parsed, errors) = KeyBindings::parse(&bindings.to_string());
+        assert!(errors.is_empty(), "got {:?}", errors);
+        assert_eq!(
+            parsed.global, bindings.global,
+            "The key X was taken from should stay unbound"
+        );
+        assert_eq!(parsed.global.lookup(Some(VirtualKeyCode::X), 0), Some(0x1));
+    }
+
+    #[test]
+    fn test_parse_skips_bad_lines() {
+        let text =
+            "[global]\n0 = F1\n1 = Q\nG = W\n[rom nothex]\n2 = E\n[rom 00000000000000ff]\n3 = R\n";
+        let (parsed, errors) = KeyBindings::parse(text);
+        assert_eq!(errors.len(), 3, "got {:?}", errors);
+        assert_eq!(
+            parsed.global.keys[0x1],
+            Some(HostKey::Virtual(VirtualKeyCode::Q)),
+            "Good lines should survive bad ones"
+        );
+        assert_eq!(
+            parsed.global.keys[0x0],
+            Keymap::default().keys[0x0],
+            "A bad line should leave the default binding"
+        );
+        assert_eq!(parsed.global.keys[0x2], Keymap::default().keys[0x2]);
+        assert_eq!(
+            parsed.overrides[&0xff].keys[0x3],
+            Some(HostKey::Virtual(VirtualKeyCode::R))
+        );
+    }
+
+    #[test]
+    fn test_capture_only_bindable_keys() {
+        let mut bindings = KeyBindings {
+            rebinding: Some(0x5),
+            ..KeyBindings::default()
+        };
+        let before = bindings.global.clone();
+        assert!(bindings.capture(0, Some(VirtualKeyCode::F1), 0));
+        assert_eq!(
+            bindings.global, before,
+            "F1 cannot be saved, so it should not bind"
+        );
+        assert_eq!(
+            bindings.rebinding,
+            Some(0x5),
+            "The prompt should keep waiting"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use super::{Keymap, Preset, CONFIG_PATH};
+use crate::keypad::KEYPAD_LAYOUT;
+use egui::Ui;
+
+impl super::KeyBindings {
+    pub fn draw_ui(&mut self, ui: &mut Ui, rom_hash: u64) {
+        let mut changed = false;
+
+        let mut per_rom = self.overrides.contains_key(&rom_hash);
+        if ui
+            .checkbox(&mut per_rom, "Override bindings for this ROM")
+            .clicked()
+        {
+            if per_rom {
+                self.overrides.insert(rom_hash, self.global.clone());
+            } else {
+                self.overrides.remove(&rom_hash);
+            }
+            changed = true;
+        }
+
+        let rebinding = &mut self.rebinding;
+        let keymap = match self.overrides.get_mut(&rom_hash) {
+            Some(keymap) => keymap,
+            None => &mut self.global,
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Presets:");
+            for &preset in Preset::ALL.iter() {
+                if ui.button(preset.name()).clicked() {
+                    *keymap = Keymap::preset(preset);
+                    changed = true;
+                }
+            }
+        });
+
+        ui.separator();
+
+        ui.label(match *rebinding {
+            Some(index) => format!("Press a host key for {:X}...", index),
+            None => "Click a key to rebind it.".to_string(),
+        });
+
+        egui::Grid::new("keymap_grid").striped(false).show(ui, |ui| {
+            for row in KEYPAD_LAYOUT.iter() {
+                for &index in row.iter() {
+                    let binding = match keymap.keys[index] {
+                        Some(key) => key.to_string(),
+                        None => "-".to_string(),
+                    };
+                    let waiting = *rebinding == Some(index);
+                    if ui
+                        .selectable_label(waiting, format!("{:X}: {}", index, binding))
+                        .clicked()
+                    {
+                        *rebinding = if waiting { None } else { Some(index) };
+                    }
+                }
+                ui.end_row();
+            }
+        });
+
+        if changed {
+            self.save(CONFIG_PATH);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use chip8::cpu::CpuEvent;
+use chip8::world::World;
+
+mod ui;
+
+/// Hex keys in the order they appear on the COSMAC VIP keypad.
+pub const KEYPAD_LAYOUT: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// State of the on-screen keypad.
+#[derive(Debug, Clone, Default)]
+pub struct Keypad {
+    /// Clicking a key toggles it instead of tapping it.
+    pub latch: bool,
+    /// Keys latched down from the keypad.
+    latched: [bool; 16],
+}
+
+impl Keypad {
+    /// Tap `key`, or toggle it when latching.
+    pub fn click(&mut self, key: usize, world: &mut World) {
+        if !self.latch {
+            world.tap_key(key);
+        } else if world.cpu.keyboard()[key] {
+            self.latched[key] = false;
+            world.handle_event(CpuEvent::KeyReleased(key));
+        } else {
+            self.latched[key] = true;
+            world.handle_event(CpuEvent::KeyPressed(key));
+        }
+    }
+
+    /// Release the keys the keypad is holding, leaving the ones held on the keyboard.
+    pub fn release_all(&mut self, world: &mut World) {
+        for key in 0..16 {
+            if self.latched[key] {
+                self.latched[key] = false;
+                world.handle_event(CpuEvent::KeyReleased(key));
+            }
+        }
+        world.release_taps();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keypad;
+    use chip8::cpu::CpuEvent;
+    use chip8::world::{World, TAP_FRAMES};
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.load_rom(&[0x12, 0x00]); // JP 0x200
+        world
+    }
+
+    #[test]
+    fn test_tap() {
+        let mut world = world();
+        let mut keypad = Keypad::default();
+        keypad.click(0xA, &mut world);
+        for _ in 1..TAP_FRAMES {
+            world.frame_advance();
+            assert!(world.cpu.keyboard()[0xA]);
+        }
+        world.frame_advance();
+        assert!(
+            !world.cpu.keyboard()[0xA],
+            "A tap should last TAP_FRAMES emulated frames"
+        );
+
+        world.handle_event(CpuEvent::KeyPressed(0x1));
+        keypad.click(0x1, &mut world);
+        for _ in 0..TAP_FRAMES * 2 {
+            world.frame_advance();
+        }
+        assert!(
+            world.cpu.keyboard()[0x1],
+            "Tapping a key held on the keyboard should not release it"
+        );
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut world = world();
+        let mut keypad = Keypad {
+            latch: true,
+            ..Keypad::default()
+        };
+        keypad.click(0x3, &mut world);
+        for _ in 0..TAP_FRAMES * 2 {
+            world.frame_advance();
+        }
+        assert!(world.cpu.keyboard()[0x3], "Latched keys should stay down");
+        keypad.click(0x3, &mut world);
+        assert!(!world.cpu.keyboard()[0x3]);
+    }
+
+    #[test]
+    fn test_release_all() {
+        let mut world = world();
+        let mut keypad = Keypad::default();
+        world.handle_event(CpuEvent::KeyPressed(0x1));
+        keypad.click(0x2, &mut world);
+        keypad.latch = true;
+        keypad.click(0x3, &mut world);
+        keypad.release_all(&mut world);
+        assert_eq!(
+            &world.cpu.keyboard()[1..4],
+            &[true, false, false],
+            "Keys held on the keyboard should stay down"
+        );
+        for _ in 0..TAP_FRAMES {
+            world.frame_advance();
+        }
+        assert!(world.cpu.keyboard()[0x1]);
+    }
+}
@@ -0,0 +1,48 @@
+use super::{Keypad, KEYPAD_LAYOUT};
+use chip8::world::World;
+use egui::{Color32, TextStyle, Ui};
+
+impl Keypad {
+    pub fn draw_ui(&mut self, ui: &mut Ui, world: &mut World) {
+        let waiting = world.cpu.waiting_for_key();
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.latch, "Latch keys");
+            if ui.button("Release all").clicked() {
+                self.release_all(world);
+            }
+        });
+        match waiting {
+            Some(x) => {
+                ui.colored_label(Color32::YELLOW, format!("Waiting for a key into V{:X}", x))
+            }
+            None => ui.label("Not waiting for a key"),
+        };
+        let mut clicked = None;
+        egui::Grid::new("keypad_grid")
+            .striped(false)
+            .show(ui, |ui| {
+                for row in KEYPAD_LAYOUT.iter() {
+                    for &key in row.iter() {
+                        let held = world.cpu.keyboard()[key];
+                        let colour = if held {
+                            Color32::GREEN
+                        } else if waiting.is_some() {
+                            Color32::YELLOW
+                        } else {
+                            Color32::WHITE
+                        };
+                        let button = egui::Button::new(format!(" {:X} ", key))
+                            .text_style(TextStyle::Heading)
+                            .text_color(colour);
+                        if ui.add(button).clicked() {
+                            clicked = Some(key);
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(key) = clicked {
+            self.click(key, world);
+        }
+    }
+}
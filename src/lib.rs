@@ -0,0 +1,15 @@
+//! CHIP-8 emulator core, shared by the windowed front end and `chip8-headless`.
+
+#![deny(clippy::all)]
+#![forbid(unsafe_code)]
+
+pub mod asm;
+pub mod conformance;
+pub mod cpu;
+pub mod disasm;
+pub mod expr;
+pub mod headless;
+pub mod movie;
+pub mod rewind;
+pub mod screenshot;
+pub mod world;
@@ -3,19 +3,24 @@
 #![windows_subsystem = "windows"]
 
 use crate::gui::Gui;
-use crate::world::World;
+use crate::keymap::{KeyBindings, CONFIG_PATH};
+use chip8::world::{World, SAVE_SLOTS};
 use log::error;
 use pixels::{Error, Pixels, SurfaceTexture};
 use simple_logger::SimpleLogger;
 use winit::dpi::LogicalSize;
-use winit::event::{Event, VirtualKeyCode};
+use winit::event::{Event, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-mod cpu;
+mod breakpoints;
+mod code_view;
 mod gui;
-mod world;
+mod keymap;
+mod keypad;
+mod memory_view;
+mod sprite_view;
 
 const WIDTH: u32 = 640;
 const HEIGHT: u32 = 320;
@@ -49,6 +54,7 @@ fn main() -> Result<(), Error> {
             scale_factor,
             pixels.context(),
             World::new(),
+            KeyBindings::load(CONFIG_PATH),
         );
 
         (pixels, gui)
@@ -56,7 +62,7 @@ fn main() -> Result<(), Error> {
 
     let rom = include_bytes!("./trip8.ch8");
     
-    gui.world.cpu.load_rom(rom);
+    gui.world.load_rom(rom);
 
     // gui.world.cpu.load_rom(&[
     //     0xF0, 0x29, // LD F, V0
@@ -66,12 +72,30 @@ fn main() -> Result<(), Error> {
     //     0x00, 0xEE, // RET #(halt)
     //     ]);
 
+    let mut buffer_size = (64, 32);
+
     event_loop.run(move |event, _, control_flow| {
         // Update egui inputs
         gui.handle_event(&event);
 
+        // Forward the hex keypad to the CPU
+        if let Event::WindowEvent {
+            event: WindowEvent::KeyboardInput { input, .. },
+            ..
+        } = event
+        {
+            gui.handle_key(input);
+        }
+
         // Draw the current frame
         if let Event::RedrawRequested(_) = event {
+            // Follow SUPER-CHIP resolution switches
+            let screen_size = gui.world.screen_size();
+            if screen_size != buffer_size {
+                pixels.resize_buffer(screen_size.0, screen_size.1);
+                buffer_size = screen_size;
+            }
+
             // Draw the world
             gui.world.draw(pixels.get_frame());
 
@@ -105,6 +129,54 @@ fn main() -> Result<(), Error> {
                 return;
             }
 
+            // Speed control hotkeys
+            // Only on press and release, so the checkboxes still work while the keys are up
+            if input.key_pressed(VirtualKeyCode::Tab) {
+                gui.world.fast_forward = true;
+            }
+            if input.key_released(VirtualKeyCode::Tab) {
+                gui.world.fast_forward = false;
+            }
+            if input.key_pressed(VirtualKeyCode::F4) {
+                gui.world.rewinding = true;
+            }
+            if input.key_released(VirtualKeyCode::F4) {
+                gui.world.rewinding = false;
+            }
+            if input.key_pressed(VirtualKeyCode::F6) {
+                gui.world.frame_advance();
+            }
+
+            // Stepping hotkeys
+            if input.key_pressed(VirtualKeyCode::F10) {
+                gui.world.step_over();
+            }
+            if input.key_pressed(VirtualKeyCode::F11) {
+                if input.held_shift() {
+                    gui.world.step_out();
+                } else {
+                    gui.world.step();
+                }
+            }
+
+            // Save state hotkeys
+            if input.key_pressed(VirtualKeyCode::F5) {
+                if let Err(e) = gui.world.save_slot() {
+                    error!("Failed to save state: {}", e);
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::F9) {
+                if let Err(e) = gui.world.load_slot() {
+                    error!("Failed to load state: {}", e);
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::PageUp) {
+                gui.world.slot = (gui.world.slot + 1) % SAVE_SLOTS;
+            }
+            if input.key_pressed(VirtualKeyCode::PageDown) {
+                gui.world.slot = (gui.world.slot + SAVE_SLOTS - 1) % SAVE_SLOTS;
+            }
+
             // Update the scale factor
             if let Some(scale_factor) = input.scale_factor() {
                 gui.scale_factor(scale_factor);
@@ -117,9 +189,8 @@ fn main() -> Result<(), Error> {
             }
 
             // Update internal state and request a redraw
+            gui.world.update();
             window.request_redraw();
         }
-
-        gui.world.update();
     });
 }
@@ -0,0 +1,278 @@
+use std::io;
+use std::path::Path;
+
+use chip8::cpu::{Cpu, RAM_SIZE};
+use chip8::world::World;
+
+mod ui;
+
+/// Bytes shown per row of the hex view.
+pub const BYTES_PER_ROW: usize = 16;
+/// Rows shown at once in the hex view.
+pub const VISIBLE_ROWS: usize = 16;
+
+/// State of the RAM viewer window.
+#[derive(Debug, Clone)]
+pub struct MemoryView {
+    /// First row shown.
+    pub top: u32,
+    /// Byte being edited, and where searches start from.
+    pub selected: u16,
+    /// Hex text in the selected cell, committed on Enter.
+    pub edit: String,
+    /// Address `edit` was last filled in from.
+    edit_addr: Option<u16>,
+    /// Hex address entered for "Go to".
+    pub go_to: String,
+    /// Byte pattern entered for "Find", in `parse_pattern` syntax.
+    pub search: String,
+    /// Addresses to import into or export from, inclusive.
+    pub range: (u16, u16),
+    /// Result of the last search, import or export.
+    pub message: Option<String>,
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        Self {
+            top: 0x200 / BYTES_PER_ROW as u32,
+            selected: 0x200,
+            edit: String::new(),
+            edit_addr: None,
+            go_to: String::new(),
+            search: String::new(),
+            range: (0x200, 0xFFF),
+            message: None,
+        }
+    }
+}
+
+impl MemoryView {
+    /// Last row that can be scrolled to the top.
+    pub const MAX_TOP: u32 = (RAM_SIZE / BYTES_PER_ROW - VISIBLE_ROWS) as u32;
+
+    /// Select `addr`, scrolling only if it is not already in view.
+    pub fn go_to(&mut self, addr: u16) {
+        self.selected = addr;
+        let row = addr as u32 / BYTES_PER_ROW as u32;
+        if row < self.top || row >= self.top + VISIBLE_ROWS as u32 {
+            self.top = row
+                .saturating_sub(VISIBLE_ROWS as u32 / 2)
+                .min(Self::MAX_TOP);
+        }
+    }
+
+    /// Select the next occurrence of the search pattern after the selected byte.
+    pub fn find_next(&mut self, cpu: &Cpu) {
+        self.message = match parse_pattern(&self.search) {
+            Ok(pattern) => match cpu.find(&pattern, self.selected as usize + 1) {
+                Some(addr) => {
+                    self.go_to(addr);
+                    None
+                }
+                None => Some("Not found".to_string()),
+            },
+            Err(e) => Some(e),
+        };
+    }
+
+    /// Refill the edit field from RAM when the selection moves, or while the
+    /// program may be changing it.
+    pub fn sync_edit(&mut self, cpu: &Cpu) {
+        if cpu.is_active() || self.edit_addr != Some(self.selected) {
+            self.edit = format!("{:02X}", cpu.memory()[self.selected as usize]);
+            self.edit_addr = Some(self.selected);
+        }
+    }
+
+    /// Write the hex byte in the edit field to the selected address.
+    pub fn commit_edit(&mut self, world: &mut World) {
+        let text = self.edit.trim().to_string();
+        let value = match u8::from_str_radix(text.trim_start_matches("0x"), 16) {
+            Ok(value) => value,
+            Err(_) => {
+                self.message = Some(format!("bad byte `{}`", text));
+                return;
+            }
+        };
+        match world.write_memory(self.selected, &[value]) {
+            Ok(_) => {
+                self.edit = format!("{:02X}", value);
+                self.message = None;
+            }
+            Err(e) => self.message = Some(e),
+        }
+    }
+
+    /// `range`, unless it ends before it starts.
+    fn checked_range(&self) -> io::Result<(u16, u16)> {
+        let (start, end) = self.range;
+        if end < start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the range ends before it starts",
+            ));
+        }
+        Ok((start, end))
+    }
+
+    /// Write the bytes in `range` to a file.
+    pub fn export(&self, cpu: &Cpu, path: &Path) -> io::Result<()> {
+        let (start, end) = self.checked_range()?;
+        std::fs::write(path, &cpu.memory()[start as usize..=end as usize])
+    }
+
+    /// Load a file into RAM at the start of `range`, as much of it as fits in the range.
+    pub fn import(&self, world: &mut World, path: &Path) -> io::Result<usize> {
+        let (start, end) = self.checked_range()?;
+        let bytes = std::fs::read(path)?;
+        let len = bytes.len().min((end - start) as usize + 1);
+        world
+            .write_memory(start, &bytes[..len])
+            .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))
+    }
+}
+
+/// Parse hex bytes (`A2 0F`, `a20f`) or a quoted string (`"HI"`) into a byte pattern.
+pub fn parse_pattern(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        return Ok(text.as_bytes()[1..text.len() - 1].to_vec());
+    }
+    let digits: String = text.split_whitespace().collect();
+    let digits = digits.trim_start_matches("0x");
+    if digits.is_empty() {
+        return Err("enter hex bytes or a quoted string".to_string());
+    }
+    if digits.len() % 2 == 1 {
+        return Err(format!("odd number of hex digits in `{}`", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("bad hex byte `{}`", &digits[i..i + 2]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_pattern, MemoryView, VISIBLE_ROWS};
+    use chip8::cpu::Cpu;
+    use chip8::world::World;
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(parse_pattern("A2 0f"), Ok(vec![0xA2, 0x0F]));
+        assert_eq!(parse_pattern("0xA20F"), Ok(vec![0xA2, 0x0F]));
+        assert_eq!(parse_pattern("\"HI\""), Ok(b"HI".to_vec()));
+        assert!(
+            parse_pattern("A2 0").is_err(),
+            "Half a byte is not a pattern"
+        );
+        assert!(parse_pattern("ZZ").is_err());
+        assert!(parse_pattern("  ").is_err());
+    }
+
+    #[test]
+    fn test_go_to() {
+        let mut view = MemoryView::default();
+        let top = view.top;
+        view.go_to(0x210);
+        assert_eq!(view.top, top, "A visible address should not scroll");
+        view.go_to(0x1000);
+        assert_eq!(view.selected, 0x1000);
+        assert_eq!(view.top, 0x100 - VISIBLE_ROWS as u32 / 2);
+        view.go_to(0xFFFF);
+        assert_eq!(view.top, MemoryView::MAX_TOP);
+    }
+
+    #[test]
+    fn test_find_next() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(b"HELLO HELLO");
+        let mut view = MemoryView {
+            search: "\"HELLO\"".to_string(),
+            ..MemoryView::default()
+        };
+        view.find_next(&cpu);
+        assert_eq!(
+            view.selected, 0x206,
+            "The search should start after the selection"
+        );
+        view.search = "AB CD EF".to_string();
+        view.find_next(&cpu);
+        assert_eq!(view.selected, 0x206);
+        assert_eq!(view.message.as_deref(), Some("Not found"));
+    }
+
+    #[test]
+    fn test_import_export() {
+        let dir = std::env::temp_dir().join(format!("chip8-memory-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("range.bin");
+        let mut world = World::new();
+        world.load_rom(&[1, 2, 3, 4]);
+        let mut view = MemoryView {
+            range: (0x201, 0x202),
+            ..MemoryView::default()
+        };
+        view.export(&world.cpu, &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [2, 3]);
+
+        std::fs::write(&path, [9, 9, 9, 9]).unwrap();
+        view.range = (0x300, 0x302);
+        assert_eq!(
+            view.import(&mut world, &path).unwrap(),
+            3,
+            "Imports should be cut to the range"
+        );
+        assert_eq!(&world.cpu.memory()[0x300..0x304], &[9, 9, 9, 0]);
+
+        view.range = (0x302, 0x300);
+        let error = view.export(&world.cpu, &path).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the range ends before it starts",
+            "An inverted range should not export anything"
+        );
+        assert!(view.import(&mut world, &path).is_err());
+
+        world.start_recording();
+        view.range = (0x200, 0x203);
+        assert!(
+            view.import(&mut world, &path).is_err(),
+            "RAM should not change under a movie"
+        );
+        assert_eq!(&world.cpu.memory()[0x200..0x204], &[1, 2, 3, 4]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut world = World::new();
+        world.load_rom(&[0xAB, 0xCD]);
+        let mut view = MemoryView::default();
+        view.sync_edit(&world.cpu);
+        assert_eq!(view.edit, "AB");
+        view.edit = "1f".to_string();
+        view.commit_edit(&mut world);
+        assert_eq!(world.cpu.memory()[0x200], 0x1F);
+        assert_eq!(view.edit, "1F");
+
+        view.go_to(0x201);
+        view.sync_edit(&world.cpu);
+        assert_eq!(view.edit, "CD", "Selecting a byte should show its value");
+        view.edit = "XY".to_string();
+        view.commit_edit(&mut world);
+        assert_eq!(world.cpu.memory()[0x201], 0xCD);
+        assert_eq!(view.message.as_deref(), Some("bad byte `XY`"));
+
+        world.start_recording();
+        view.edit = "00".to_string();
+        view.commit_edit(&mut world);
+        assert_eq!(world.cpu.memory()[0x201], 0xCD, "RAM should not change under a movie");
+        assert!(view.message.is_some());
+    }
+}
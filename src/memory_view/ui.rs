@@ -0,0 +1,137 @@
+use super::{MemoryView, BYTES_PER_ROW, VISIBLE_ROWS};
+use chip8::cpu::Region;
+use chip8::world::World;
+use egui::{Color32, DragValue, Key, TextStyle, Ui};
+
+impl MemoryView {
+    pub fn draw_ui(&mut self, ui: &mut Ui, world: &mut World) {
+        let rom_len = world.rom().len();
+        // Edits would not be in a movie being recorded, and would break one being played.
+        let editable = !world.cpu.is_active() && !world.movie_active();
+        ui.horizontal(|ui| {
+            ui.label("Go to:");
+            ui.text_edit_singleline(&mut self.go_to);
+            if ui.button("Go").clicked() {
+                let text = self.go_to.trim().to_string();
+                match u16::from_str_radix(text.trim_start_matches("0x"), 16) {
+                    Ok(addr) => {
+                        self.go_to(addr);
+                        self.message = None;
+                    }
+                    Err(_) => self.message = Some(format!("bad address `{}`", text)),
+                }
+            }
+            if ui.button("PC").clicked() {
+                self.go_to(world.cpu.pc());
+            }
+            if ui.button("I").clicked() {
+                self.go_to(world.cpu.reg_i());
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Find:");
+            ui.text_edit_singleline(&mut self.search);
+            if ui.button("Find next").clicked() {
+                self.find_next(&world.cpu);
+            }
+        });
+        if let Some(message) = &self.message {
+            ui.colored_label(Color32::YELLOW, message.as_str());
+        }
+        ui.add(egui::Slider::u32(&mut self.top, 0..=MemoryView::MAX_TOP).text("row"));
+        self.sync_edit(&world.cpu);
+
+        ui.separator();
+        for row in self.top as usize..self.top as usize + VISIBLE_ROWS {
+            let start = row * BYTES_PER_ROW;
+            ui.horizontal(|ui| {
+                ui.add(egui::Label::new(format!("{:04X}", start)).text_style(TextStyle::Monospace));
+                for addr in start..start + BYTES_PER_ROW {
+                    let addr = addr as u16;
+                    let pc = world.cpu.pc();
+                    let colour = if addr == pc || addr == pc.wrapping_add(1) {
+                        Color32::YELLOW
+                    } else if addr == world.cpu.reg_i() {
+                        Color32::from_rgb(255, 102, 0)
+                    } else {
+                        match world.cpu.region(addr, rom_len) {
+                            Region::Font => Color32::from_rgb(140, 180, 255),
+                            Region::Rom => Color32::WHITE,
+                            Region::Free => Color32::GRAY,
+                        }
+                    };
+                    if editable && addr == self.selected {
+                        let edit = egui::TextEdit::singleline(&mut self.edit)
+                            .text_style(TextStyle::Monospace)
+                            .desired_width(16.0);
+                        if ui.add(edit).lost_kb_focus() && ui.input().key_pressed(Key::Enter) {
+                            self.commit_edit(world);
+                        }
+                        continue;
+                    }
+                    let button =
+                        egui::Button::new(format!("{:02X}", world.cpu.memory()[addr as usize]))
+                            .text_style(TextStyle::Monospace)
+                            .text_color(colour)
+                            .frame(addr == self.selected);
+                    if ui.add(button).clicked() {
+                        self.selected = addr;
+                    }
+                }
+                let ascii: String = world.cpu.memory()[start..start + BYTES_PER_ROW]
+                    .iter()
+                    .map(|&byte| {
+                        if byte.is_ascii_graphic() || byte == b' ' {
+                            byte as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                ui.add(egui::Label::new(ascii).text_style(TextStyle::Monospace));
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.colored_label(Color32::YELLOW, "PC");
+            ui.colored_label(Color32::from_rgb(255, 102, 0), "I");
+            ui.colored_label(Color32::from_rgb(140, 180, 255), "font");
+            ui.colored_label(Color32::WHITE, format!("ROM ({} bytes)", rom_len));
+        });
+
+        ui.separator();
+        ui.set_enabled(!world.cpu.is_active());
+        ui.label(format!(
+            "Selected 0x{:04X}. Type a hex byte in it and press Enter to write it.",
+            self.selected
+        ));
+        ui.horizontal(|ui| {
+            ui.label("Range:");
+            ui.add(DragValue::u16(&mut self.range.0));
+            ui.label("to");
+            ui.add(DragValue::u16(&mut self.range.1));
+            if ui.button("Export...").clicked() {
+                let file = rfd::FileDialog::new()
+                    .set_directory(".")
+                    .add_filter("Binary", &["bin", "ch8"])
+                    .save_file();
+                if let Some(file) = file {
+                    if let Err(e) = self.export(&world.cpu, &file) {
+                        self.message = Some(format!("Failed to export: {}", e));
+                    }
+                }
+            }
+            if ui
+                .add(egui::Button::new("Import...").enabled(editable))
+                .clicked()
+            {
+                let file = rfd::FileDialog::new().set_directory(".").pick_file();
+                if let Some(file) = file {
+                    self.message = Some(match self.import(world, &file) {
+                        Ok(len) => format!("Imported {} bytes", len),
+                        Err(e) => format!("Failed to import: {}", e),
+                    });
+                }
+            }
+        });
+    }
+}
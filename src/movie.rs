@@ -0,0 +1,551 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::cpu::{Cpu, CpuEvent, FaultPolicy, MemoryIncrement, Quirks, Random, RngAlgorithm};
+
+/// A recorded session: the machine configuration, the key presses of every
+/// frame and a hash of the state after every frame.
+///
+/// Frames are counted from the ROM being loaded, one per 60 Hz timer tick.
+/// Movies are stored as text, one directive per line:
+///
+/// ```text
+/// rom 0123456789abcdef
+/// ips 700
+/// fault_policy halt
+/// rng xorshift 1234
+/// rpl zero
+/// quirk shift_uses_vy true
+/// quirk memory_increment x+1
+/// frames 600
+/// press key 5 frame 12
+/// release key 5 frame 14
+/// hold key A frames 120-140
+/// hash frame 0 89abcdef01234567
+/// ```
+///
+/// `hold` presses the key at the start of the first frame and releases it after
+/// the last. Quirks that are not mentioned keep their default value, and `hash`
+/// lines are optional, so short input scripts can be written by hand. `rpl zero`
+/// notes that the RPL flags start cleared and are neither loaded from nor saved to
+/// disk, so a movie never depends on what earlier runs left there; it is the only
+/// choice, and may be left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    pub fault_policy: FaultPolicy,
+    pub rng_algorithm: RngAlgorithm,
+    pub seed: u64,
+    /// Number of frames the movie runs for.
+    pub frames: u64,
+    /// Key events and the frame they happen before, in order.
+    pub events: Vec<(u64, CpuEvent)>,
+    /// Hash of the machine state after each frame, if known.
+    pub hashes: Vec<Option<u64>>,
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    /// The movie was recorded with a different ROM.
+    WrongRom { expected: u64, found: u64 },
+    /// The state after `frame` does not match the recording.
+    Desync { frame: u64, expected: u64, found: u64 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::WrongRom { expected, found } => write!(
+                f,
+                "movie is for ROM {:016x}, but ROM {:016x} is loaded",
+                expected, found
+            ),
+            MovieError::Desync {
+                frame,
+                expected,
+                found,
+            } => write!(
+                f,
+                "replay diverged at frame {}: state hash is {:016x}, expected {:016x}",
+                frame, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    /// An empty movie using the configuration `cpu` is currently in.
+    pub fn new(cpu: &Cpu) -> Self {
+        Self {
+            rom_hash: cpu.rom_hash(),
+            quirks: cpu.quirks,
+            instructions_per_second: cpu.instructions_per_second,
+            fault_policy: cpu.fault_policy,
+            rng_algorithm: cpu.rng.algorithm(),
+            seed: cpu.rng.seed(),
+            frames: 0,
+            events: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    /// Parse the text format written by the `Display` impl.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut movie = Self {
+            rom_hash: 0,
+            quirks: Quirks::default(),
+            instructions_per_second: crate::cpu::DEFAULT_INSTRUCTIONS_PER_SECOND,
+            fault_policy: FaultPolicy::Halt,
+            rng_algorithm: RngAlgorithm::Xorshift,
+            seed: 0,
+            frames: 0,
+            events: Vec::new(),
+            hashes: Vec::new(),
+        };
+        let mut has_rom = false;
+        let mut has_length = false;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", number + 1, msg);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["rom", hash] => {
+                    movie.rom_hash = parse_hex(hash).ok_or_else(|| err("bad ROM hash"))?;
+                    has_rom = true;
+                }
+                ["ips", ips] => {
+                    movie.instructions_per_second = ips
+                        .parse()
+                        .ok()
+                        .filter(|&ips| ips > 0)
+                        .ok_or_else(|| err("bad instructions per second"))?;
+                }
+                ["fault_policy", policy] => {
+                    movie.fault_policy = FaultPolicy::ALL
+                        .iter()
+                        .copied()
+                        .find(|p| policy_word(*p) == *policy)
+                        .ok_or_else(|| err("unknown fault policy"))?;
+                }
+                ["rng", algorithm, seed] => {
+                    movie.rng_algorithm = RngAlgorithm::ALL
+                        .iter()
+                        .copied()
+                        .find(|a| rng_word(*a) == *algorithm)
+                        .ok_or_else(|| err("unknown random number generator"))?;
+                    movie.seed = seed.parse().map_err(|_| err("bad seed"))?;
+                }
+                ["rpl", "zero"] => {}
+                ["quirk", name, value] => {
+                    set_quirk(&mut movie.quirks, name, value).ok_or_else(|| err("bad quirk"))?;
+                }
+                ["frames", frames] => {
+                    movie.frames = frames.parse().map_err(|_| err("bad frame count"))?;
+                    has_length = true;
+                }
+                [action @ "press", "key", key, "frame", frame]
+                | [action @ "release", "key", key, "frame", frame] => {
+                    let key = parse_key(key).ok_or_else(|| err("expected a key from 0 to F"))?;
+                    let frame = frame.parse().map_err(|_| err("bad frame number"))?;
+                    let event = if *action == "press" {
+                        CpuEvent::KeyPressed(key)
+                    } else {
+                        CpuEvent::KeyReleased(key)
+                    };
+                    movie.events.push((frame, event));
+                }
+                ["hold", "key", key, "frames", range] => {
+                    let key = parse_key(key).ok_or_else(|| err("expected a key from 0 to F"))?;
+                    let (first, last) = parse_range(range)
+                        .ok_or_else(|| err("expected a frame range like 120-140"))?;
+                    movie.events.push((first, CpuEvent::KeyPressed(key)));
+                    movie.events.push((last + 1, CpuEvent::KeyReleased(key)));
+                }
+                ["hash", "frame", frame, hash] => {
+                    let frame: usize = frame.parse().map_err(|_| err("bad frame number"))?;
+                    let hash = parse_hex(hash).ok_or_else(|| err("bad state hash"))?;
+                    if movie.hashes.len() <= frame {
+                        movie.hashes.resize(frame + 1, None);
+                    }
+                    movie.hashes[frame] = Some(hash);
+                }
+                _ => return Err(err("unknown directive")),
+            }
+        }
+        if !has_rom {
+            return Err("missing `rom` line".to_string());
+        }
+        // Stable, so events on the same frame keep the order they were written in.
+        movie.events.sort_by_key(|&(frame, _)| frame);
+        if !has_length {
+            let last_event = movie.events.last().map_or(0, |&(frame, _)| frame + 1);
+            movie.frames = last_event.max(movie.hashes.len() as u64);
+        }
+        Ok(movie)
+    }
+
+    /// Reset `cpu` to the start of the movie: apply its configuration and reload the ROM.
+    pub fn prepare(&self, cpu: &mut Cpu, rom: &[u8]) -> Result<(), MovieError> {
+        let found = crate::cpu::hash(rom);
+        if found != self.rom_hash {
+            return Err(MovieError::WrongRom {
+                expected: self.rom_hash,
+                found,
+            });
+        }
+        self.reset(cpu, rom);
+        Ok(())
+    }
+
+    fn reset(&self, cpu: &mut Cpu, rom: &[u8]) {
+        // With no directory, loading the ROM clears the RPL flags.
+        cpu.rpl_dir = None;
+        cpu.quirks = self.quirks;
+        cpu.instructions_per_second = self.instructions_per_second;
+        cpu.fault_policy = self.fault_policy;
+        cpu.rng = Random::new(self.rng_algorithm, self.seed);
+        cpu.load_rom(rom);
+        cpu.resume();
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rom {:016x}", self.rom_hash)?;
+        writeln!(f, "ips {}", self.instructions_per_second)?;
+        writeln!(f, "fault_policy {}", policy_word(self.fault_policy))?;
+        writeln!(f, "rng {} {}", rng_word(self.rng_algorithm), self.seed)?;
+        writeln!(f, "rpl zero")?;
+        let q = &self.quirks;
+        writeln!(f, "quirk shift_uses_vy {}", q.shift_uses_vy)?;
+        let increment = match q.memory_increment {
+            MemoryIncrement::None => "none",
+            MemoryIncrement::X => "x",
+            MemoryIncrement::XPlusOne => "x+1",
+        };
+        writeln!(f, "quirk memory_increment {}", increment)?;
+        writeln!(f, "quirk jump_with_vx {}", q.jump_with_vx)?;
+        writeln!(f, "quirk wrap_x {}", q.wrap_x)?;
+        writeln!(f, "quirk wrap_y {}", q.wrap_y)?;
+        writeln!(f, "quirk vf_reset {}", q.vf_reset)?;
+        writeln!(f, "quirk display_wait {}", q.display_wait)?;
+        writeln!(f, "frames {}", self.frames)?;
+        for (frame, event) in self.events.iter() {
+            match event {
+                CpuEvent::KeyPressed(key) => writeln!(f, "press key {:X} frame {}", key, frame)?,
+                CpuEvent::KeyReleased(key) => {
+                    writeln!(f, "release key {:X} frame {}", key, frame)?
+                }
+            }
+        }
+        for (frame, hash) in self.hashes.iter().enumerate() {
+            if let Some(hash) = hash {
+                writeln!(f, "hash frame {} {:016x}", frame, hash)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn policy_word(policy: FaultPolicy) -> &'static str {
+    match policy {
+        FaultPolicy::Halt => "halt",
+        FaultPolicy::Warn => "warn",
+        FaultPolicy::Wrap => "wrap",
+    }
+}
+
+fn rng_word(algorithm: RngAlgorithm) -> &'static str {
+    match algorithm {
+        RngAlgorithm::Xorshift => "xorshift",
+        RngAlgorithm::SplitMix => "splitmix",
+        RngAlgorithm::CosmacVip => "cosmac-vip",
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_key(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok().filter(|&key| key < 16)
+}
+
+fn parse_range(s: &str) -> Option<(u64, u64)> {
+    let mut parts = s.splitn(2, '-');
+    let first = parts.next()?.parse().ok()?;
+    let last = parts.next()?.parse().ok()?;
+    Some((first, last)).filter(|_| first <= last)
+}
+
+fn set_quirk(quirks: &mut Quirks, name: &str, value: &str) -> Option<()> {
+    let flag = || value.parse::<bool>().ok();
+    match name {
+        "shift_uses_vy" => quirks.shift_uses_vy = flag()?,
+        "memory_increment" => {
+            quirks.memory_increment = match value {
+                "none" => MemoryIncrement::None,
+                "x" => MemoryIncrement::X,
+                "x+1" => MemoryIncrement::XPlusOne,
+                _ => return None,
+            }
+        }
+        "jump_with_vx" => quirks.jump_with_vx = flag()?,
+        "wrap_x" => quirks.wrap_x = flag()?,
+        "wrap_y" => quirks.wrap_y = flag()?,
+        "vf_reset" => quirks.vf_reset = flag()?,
+        "display_wait" => quirks.display_wait = flag()?,
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Records key events and state hashes as frames are run.
+pub struct Recorder {
+    movie: Movie,
+    /// Keys held down, so host auto-repeat does not end up in the movie.
+    down: [bool; 16],
+}
+
+impl Recorder {
+    /// Reset `cpu` to the start of `rom`, keeping its configuration, and start recording.
+    pub fn new(cpu: &mut Cpu, rom: &[u8]) -> Self {
+        let mut movie = Movie::new(cpu);
+        movie.rom_hash = crate::cpu::hash(rom);
+        movie.reset(cpu, rom);
+        Self {
+            movie,
+            down: [false; 16],
+        }
+    }
+
+    /// Note an event about to be handed to the CPU.
+    pub fn event(&mut self, event: CpuEvent) {
+        let (key, pressed) = match event {
+            CpuEvent::KeyPressed(key) => (key, true),
+            CpuEvent::KeyReleased(key) => (key, false),
+        };
+        if key < 16 && self.down[key] != pressed {
+            self.down[key] = pressed;
+            self.movie.events.push((self.movie.frames, event));
+        }
+    }
+
+    /// Note that a frame was run.
+    pub fn frame(&mut self, cpu: &Cpu) {
+        self.movie.hashes.push(Some(cpu.state_hash()));
+        self.movie.frames += 1;
+    }
+
+    /// Frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.movie.frames
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Feeds a movie's key events to the CPU and checks the state after every frame.
+pub struct Player {
+    movie: Movie,
+    frame: u64,
+    next_event: usize,
+}
+
+impl Player {
+    /// Reset `cpu` to the start of `movie` and get ready to play it back.
+    pub fn new(movie: Movie, cpu: &mut Cpu, rom: &[u8]) -> Result<Self, MovieError> {
+        movie.prepare(cpu, rom)?;
+        Ok(Self {
+            movie,
+            frame: 0,
+            next_event: 0,
+        })
+    }
+
+    /// Frames played back so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames
+    }
+
+    /// Number of key events not yet played back.
+    pub fn pending_events(&self) -> usize {
+        self.movie.events.len() - self.next_event
+    }
+
+    /// Run the next frame of the movie. Returns `false` once the movie has ended.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<bool, MovieError> {
+        if self.is_finished() {
+            return Ok(false);
+        }
+        self.begin_frame(cpu);
+        cpu.run_frame();
+        self.end_frame(cpu)?;
+        Ok(true)
+    }
+
+    /// Feed the CPU the key events due before the next frame, for callers
+    /// that run the frame themselves rather than through `run_frame`.
+    pub fn begin_frame(&mut self, cpu: &mut Cpu) {
+        while let Some(&(frame, event)) = self.movie.events.get(self.next_event) {
+            if frame > self.frame {
+                break;
+            }
+            cpu.handle_event(event);
+            self.next_event += 1;
+        }
+    }
+
+    /// Check the state at the end of a frame started with `begin_frame`.
+    pub fn end_frame(&mut self, cpu: &Cpu) -> Result<(), MovieError> {
+        if let Some(&Some(expected)) = self.movie.hashes.get(self.frame as usize) {
+            let found = cpu.state_hash();
+            if found != expected {
+                return Err(MovieError::Desync {
+                    frame: self.frame,
+                    expected,
+                    found,
+                });
+            }
+        }
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Movie, MovieError, Player, Recorder};
+    use crate::cpu::{Cpu, CpuEvent};
+
+    /// Counts frames in V1 while key 5 is held, and draws a random digit every frame.
+    #[rustfmt::skip]
+    const ROM: &[u8] = &[
+        0x60, 0x05, // LD V0, 5
+        0xE0, 0xA1, // SKNP V0
+        0x71, 0x01, // ADD V1, 1
+        0xC2, 0x0F, // RND V2, 0x0F
+        0xF2, 0x29, // LD F, V2
+        0xD3, 0x35, // DRW V3, V3, 5
+        0x12, 0x02, // JP 0x202
+    ];
+
+    fn record() -> (Movie, Cpu) {
+        let mut cpu = Cpu::new();
+        let mut recorder = Recorder::new(&mut cpu, ROM);
+        for frame in 0..60 {
+            if frame == 10 {
+                recorder.event(CpuEvent::KeyPressed(5));
+                cpu.handle_event(CpuEvent::KeyPressed(5));
+            }
+            if frame == 20 {
+                recorder.event(CpuEvent::KeyReleased(5));
+                cpu.handle_event(CpuEvent::KeyReleased(5));
+            }
+            cpu.run_frame();
+            recorder.frame(&cpu);
+        }
+        (recorder.finish(), cpu)
+    }
+
+    #[test]
+    fn test_replay() {
+        let (movie, recorded) = record();
+        let movie = Movie::parse(&movie.to_string()).unwrap();
+        assert_eq!(movie.frames, 60);
+
+        let mut cpu = Cpu::new();
+        let mut player = Player::new(movie, &mut cpu, ROM).unwrap();
+        while player.run_frame(&mut cpu).unwrap() {}
+        assert_eq!(cpu.state_hash(), recorded.state_hash(), "Replay should match bit for bit");
+    }
+
+    #[test]
+    fn test_desync() {
+        let (mut movie, _) = record();
+        movie.events[0].0 = 11;
+        let mut cpu = Cpu::new();
+        let mut player = Player::new(movie, &mut cpu, ROM).unwrap();
+        let error = loop {
+            match player.run_frame(&mut cpu) {
+                Ok(true) => {}
+                Ok(false) => panic!("The replay should diverge"),
+                Err(e) => break e,
+            }
+        };
+        assert!(
+            matches!(error, MovieError::Desync { frame: 10, .. }),
+            "Divergence should be reported at the first different frame, got {}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_hand_written() {
+        let (recorded, expected) = record();
+        let mut script = format!("rom {:016x}\n", recorded.rom_hash);
+        script += &format!("rng xorshift {}\n", recorded.seed);
+        script += "# hold the counting key\nhold key 5 frames 10-19\nframes 60\n";
+        let movie = Movie::parse(&script).unwrap();
+        assert_eq!(movie.events, recorded.events);
+        assert_eq!(movie.quirks, recorded.quirks);
+
+        let mut cpu = Cpu::new();
+        let mut player = Player::new(movie, &mut cpu, ROM).unwrap();
+        while player.run_frame(&mut cpu).unwrap() {}
+        assert_eq!(cpu.state_hash(), expected.state_hash());
+    }
+
+    #[test]
+    fn test_rpl_zeroed() {
+        let dir = std::env::temp_dir().join(format!("chip8-movie-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (movie, recorded) = record();
+        std::fs::write(dir.join(format!("{:016x}.rpl", movie.rom_hash)), [0xFF; 16]).unwrap();
+        let mut cpu = Cpu::new();
+        cpu.rpl_dir = Some(dir.clone());
+        let player = Player::new(movie, &mut cpu, ROM);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut player = player.unwrap();
+        assert_eq!(cpu.rpl_dir, None, "A movie should not touch the RPL file");
+        while player.run_frame(&mut cpu).unwrap() {}
+        assert_eq!(
+            cpu.state_hash(),
+            recorded.state_hash(),
+            "Saved RPL flags should not change the replay"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Movie::parse("ips 700").is_err(), "The ROM hash is required");
+        assert_eq!(
+            Movie::parse("rom 0\nhold key G frames 1-2").unwrap_err(),
+            "line 2: expected a key from 0 to F"
+        );
+        assert!(Movie::parse("rom 0\nhold key 1 frames 5-2").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+
+use crate::cpu::{Cpu, SaveState};
+
+/// Default memory budget of the rewind buffer, enough for well over an hour of a typical game.
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Ring buffer of machine snapshots, one per frame.
+///
+/// Only the newest snapshot is kept whole. Every older one is stored as the
+/// difference to the snapshot after it, so stepping back undoes one delta at a
+/// time and the oldest deltas can be dropped without breaking the chain.
+pub struct Rewind {
+    /// Cycle count and serialized state of the newest snapshot.
+    newest: Option<(u64, Vec<u8>)>,
+    /// Cycle count of each older snapshot and the delta that rebuilds it from the one after it.
+    deltas: VecDeque<(u64, Vec<u8>)>,
+    /// Bytes used by `newest` and `deltas`.
+    size: usize,
+    /// Oldest snapshots are dropped once `size` exceeds this.
+    pub budget: usize,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET)
+    }
+}
+
+impl Rewind {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            newest: None,
+            deltas: VecDeque::new(),
+            size: 0,
+            budget,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.size = 0;
+    }
+
+    /// Number of snapshots held.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Memory used by the snapshots, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Record the current state. Does nothing if no time has passed since the last snapshot.
+    pub fn push(&mut self, cpu: &Cpu) {
+        let cycle = cpu.cycle();
+        let bytes = cpu.save_state().to_bytes();
+        if let Some((last_cycle, last)) = self.newest.take() {
+            if last_cycle == cycle {
+                self.newest = Some((last_cycle, last));
+                return;
+            }
+            let delta = diff(&bytes, &last);
+            self.size = self.size - last.len() + delta.len();
+            self.deltas.push_back((last_cycle, delta));
+        }
+        self.size += bytes.len();
+        self.newest = Some((cycle, bytes));
+        while self.size > self.budget {
+            match self.deltas.pop_front() {
+                Some((_, delta)) => self.size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Drop snapshots newer than `cycle` and return the newest one left,
+    /// or `None` (changing nothing) if the buffer does not reach back that far.
+    fn seek(&mut self, cycle: u64) -> Option<SaveState> {
+        let oldest = self
+            .deltas
+            .front()
+            .map(|&(cycle, _)| cycle)
+            .or_else(|| self.newest.as_ref().map(|&(cycle, _)| cycle))?;
+        if oldest > cycle {
+            return None;
+        }
+        while self.newest.as_ref()?.0 > cycle {
+            let (_, bytes) = self.newest.take()?;
+            let (older_cycle, delta) = self.deltas.pop_back()?;
+            let older = apply(&bytes, &delta);
+            self.size = self.size - bytes.len() - delta.len() + older.len();
+            self.newest = Some((older_cycle, older));
+        }
+        let (_, bytes) = self.newest.as_ref()?;
+        match SaveState::from_bytes(bytes) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                log::error!("Rewind buffer is corrupt: {}", e);
+                self.clear();
+                None
+            }
+        }
+    }
+
+    fn restore(&mut self, cpu: &mut Cpu, state: &SaveState) -> bool {
+        match cpu.load_state(state) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("Cannot rewind: {}", e);
+                self.clear();
+                false
+            }
+        }
+    }
+
+    /// Go back to the last snapshot taken before the current cycle.
+    pub fn step_back_frame(&mut self, cpu: &mut Cpu) -> bool {
+        match cpu.cycle().checked_sub(1).and_then(|cycle| self.seek(cycle)) {
+            Some(state) => self.restore(cpu, &state),
+            None => false,
+        }
+    }
+
+    /// Go back a single clock cycle by restoring the snapshot before it and running up to it again.
+    pub fn step_back_instruction(&mut self, cpu: &mut Cpu) -> bool {
+        let target = match cpu.cycle().checked_sub(1) {
+            Some(target) => target,
+            None => return false,
+        };
+        match self.seek(target) {
+            Some(state) if self.restore(cpu, &state) => {
+                cpu.run_until(target);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Encode what turns `from` into `to`: the length of `to`, then the XOR of the
+/// two as alternating runs of unchanged bytes and literal changed bytes.
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = (0..to.len())
+        .map(|i| from.get(i).copied().unwrap_or(0) ^ to[i])
+        .collect();
+    let mut delta = Vec::new();
+    write_varint(&mut delta, to.len());
+    let mut i = 0;
+    while i < xor.len() {
+        let unchanged = xor[i..].iter().take_while(|&&b| b == 0).count();
+        i += unchanged;
+        // Short runs of unchanged bytes are cheaper to store as literals.
+        let mut changed = 0;
+        while i + changed < xor.len() {
+            let zeros = xor[i + changed..].iter().take(4).take_while(|&&b| b == 0).count();
+            if zeros == 4 || i + changed + zeros == xor.len() {
+                break;
+            }
+            changed += zeros.max(1);
+        }
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend_from_slice(&xor[i..i + changed]);
+        i += changed;
+    }
+    delta
+}
+
+/// Rebuild the `to` that `diff(from, to)` was computed against.
+fn apply(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    let mut to = from.to_vec();
+    to.resize(len, 0);
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+        for (byte, x) in to[i..i + changed].iter_mut().zip(&delta[pos..pos + changed]) {
+            *byte ^= x;
+        }
+        pos += changed;
+        i += changed;
+    }
+    to
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, diff, Rewind};
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_diff() {
+        let a: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let mut b = a.clone();
+        b[3] = 0xFF;
+        b[5] = 0xFF;
+        b[150] = 0;
+        b.extend_from_slice(&[1, 2, 3]);
+        for (from, to) in [(&a, &b), (&b, &a), (&a, &a)].iter() {
+            assert_eq!(&apply(from, &diff(from, to)), *to);
+        }
+        assert!(diff(&a, &b).len() < 20, "Small changes should give small deltas");
+    }
+
+    fn v0(cpu: &Cpu) -> u8 {
+        cpu.save_state().reg[0]
+    }
+
+    fn counting_cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.instructions_per_second = 600;
+        cpu.load_rom(&[
+            0x70, 0x01, // ADD V0, 1
+            0x12, 0x00, // JP 0x200
+        ]);
+        cpu.resume();
+        cpu
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::default();
+        rewind.push(&cpu);
+        for _ in 0..3 {
+            cpu.run_frame();
+            rewind.push(&cpu);
+        }
+        assert_eq!(rewind.len(), 4);
+        assert_eq!(v0(&cpu), 15);
+
+        assert!(rewind.step_back_instruction(&mut cpu));
+        assert_eq!(cpu.cycle(), 29);
+        assert_eq!(v0(&cpu), 15, "The last cycle was a jump");
+        assert!(rewind.step_back_instruction(&mut cpu));
+        assert_eq!(v0(&cpu), 14);
+
+        assert!(rewind.step_back_frame(&mut cpu));
+        assert_eq!(cpu.cycle(), 20);
+        assert!(rewind.step_back_frame(&mut cpu));
+        assert!(rewind.step_back_frame(&mut cpu));
+        assert_eq!(cpu.cycle(), 0);
+        assert_eq!(v0(&cpu), 0);
+        assert!(!rewind.step_back_frame(&mut cpu), "There is nothing before the first frame");
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::new(0);
+        for _ in 0..10 {
+            cpu.run_frame();
+            rewind.push(&cpu);
+        }
+        assert_eq!(rewind.len(), 1, "Only the newest snapshot should be kept");
+        assert!(!rewind.step_back_frame(&mut cpu));
+    }
+}
@@ -0,0 +1,136 @@
+//! Encoding the emulated screen as an image file.
+
+use crate::cpu::Cpu;
+use crate::world::PALETTE;
+
+/// Colour index of every pixel on screen, row by row.
+fn pixels(cpu: &Cpu) -> (usize, usize, Vec<u8>) {
+    let (width, height) = cpu.screen_size();
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(cpu.vram[x][y] & 3);
+        }
+    }
+    (width, height, pixels)
+}
+
+/// Binary PBM (`P4`), lit in any plane is black.
+pub fn pbm(cpu: &Cpu) -> Vec<u8> {
+    let (width, height, pixels) = pixels(cpu);
+    let mut out = format!("P4\n{} {}\n", width, height).into_bytes();
+    for row in pixels.chunks(width) {
+        for byte in row.chunks(8) {
+            out.push(
+                byte.iter()
+                    .enumerate()
+                    .fold(0, |acc, (i, &px)| acc | ((px != 0) as u8) << (7 - i)),
+            );
+        }
+    }
+    out
+}
+
+/// Indexed-colour PNG using the same palette as the window.
+pub fn png(cpu: &Cpu) -> Vec<u8> {
+    let (width, height, pixels) = pixels(cpu);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per pixel, indexed colour, deflate, default filtering, no interlace.
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+    // Lit-nowhere pixels are transparent in the window, but black in the image.
+    let palette: Vec<u8> = PALETTE.iter().flat_map(|rgba| rgba[..3].to_vec()).collect();
+
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width) {
+        raw.push(0); // no filter
+        raw.extend_from_slice(row);
+    }
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"PLTE", &palette);
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of uncompressed deflate blocks. Screens are tiny, so compression is not worth a dependency.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    out.extend_from_slice(&(b << 16 | a).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32, pbm, png};
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_pbm() {
+        let mut cpu = Cpu::new();
+        cpu.vram[0][0] = 1;
+        cpu.vram[9][1] = 2;
+        let image = pbm(&cpu);
+        let header = b"P4\n64 32\n";
+        assert_eq!(&image[..header.len()], header);
+        let bits = &image[header.len()..];
+        assert_eq!(bits.len(), 8 * 32, "Each row should be packed into 8 bytes");
+        assert_eq!(bits[0], 0x80);
+        assert_eq!(bits[8 + 1], 0x40, "Pixels lit in any plane should be black");
+    }
+
+    #[test]
+    fn test_png() {
+        let image = png(&Cpu::new());
+        assert_eq!(&image[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&image[12..16], b"IHDR");
+        assert_eq!(&image[16..24], &[0, 0, 0, 64, 0, 0, 0, 32]);
+        assert_eq!(&image[image.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+    }
+}
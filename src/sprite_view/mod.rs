@@ -0,0 +1,52 @@
+use chip8::cpu::Cpu;
+
+mod ui;
+
+/// State of the sprite viewer window.
+#[derive(Debug, Clone)]
+pub struct SpriteView {
+    /// Show the sprite at I instead of at `address`.
+    pub follow_i: bool,
+    pub address: u16,
+    /// Rows to show.
+    pub height: u8,
+}
+
+impl Default for SpriteView {
+    fn default() -> Self {
+        Self {
+            follow_i: true,
+            address: 0x200,
+            height: 15,
+        }
+    }
+}
+
+impl SpriteView {
+    /// Address of the sprite shown.
+    pub fn address(&self, cpu: &Cpu) -> u16 {
+        if self.follow_i {
+            cpu.reg_i()
+        } else {
+            self.address
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpriteView;
+    use chip8::cpu::Cpu;
+
+    #[test]
+    fn test_address() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0xA3, 0x00]); // LD I, 0x300
+        cpu.step();
+        let mut view = SpriteView::default();
+        assert_eq!(view.address(&cpu), 0x300);
+        view.follow_i = false;
+        view.address = 0x208;
+        assert_eq!(view.address(&cpu), 0x208);
+    }
+}
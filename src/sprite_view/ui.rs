@@ -0,0 +1,87 @@
+use super::SpriteView;
+use chip8::cpu::Cpu;
+use egui::{vec2, Color32, DragValue, Rect, Response, Sense, TextStyle, Ui};
+
+/// Paint sprite data as pixels `scale` points wide, `row_bytes` bytes per row.
+fn draw_bitmap(ui: &mut Ui, bytes: &[u8], row_bytes: usize, scale: f32) -> Response {
+    let rows = bytes.chunks(row_bytes).count();
+    let size = vec2((row_bytes * 8) as f32, rows.max(1) as f32) * scale;
+    let (rect, response) = ui.allocate_exact_size(size, Sense::click());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0.0, Color32::BLACK);
+    for (row, line) in bytes.chunks(row_bytes).enumerate() {
+        for (column, &byte) in line.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let x = (column * 8 + bit) as f32;
+                    let min = rect.min + vec2(x, row as f32) * scale;
+                    painter.rect_filled(
+                        Rect::from_min_size(min, vec2(scale, scale)),
+                        0.0,
+                        Color32::WHITE,
+                    );
+                }
+            }
+        }
+    }
+    response
+}
+
+impl SpriteView {
+    /// Returns the address of a sprite that was clicked, to show it in the RAM viewer.
+    pub fn draw_ui(&mut self, ui: &mut Ui, cpu: &Cpu) -> Option<u16> {
+        let mut clicked = None;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.follow_i, "Follow I");
+            if !self.follow_i {
+                ui.label("Address:");
+                ui.add(DragValue::u16(&mut self.address));
+            }
+            ui.label("Height:");
+            ui.add(DragValue::u8(&mut self.height).clamp_range(1.0..=64.0));
+        });
+        let address = self.address(cpu);
+        let bytes = cpu.sprite_bytes(address, self.height as usize);
+        ui.horizontal(|ui| {
+            if draw_bitmap(ui, bytes, 1, 8.0)
+                .on_hover_text("Show in the RAM viewer")
+                .clicked()
+            {
+                clicked = Some(address);
+            }
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            ui.vertical(|ui| {
+                ui.label(format!("0x{:04X}", address));
+                for row in hex.chunks(4) {
+                    ui.add(egui::Label::new(row.join(" ")).text_style(TextStyle::Monospace));
+                }
+            });
+        });
+
+        ui.separator();
+        ui.label("Recently drawn:");
+        for draw in cpu.recent_sprites().iter().rev() {
+            ui.horizontal(|ui| {
+                let len = draw.height as usize * draw.row_bytes();
+                let bytes = cpu.sprite_bytes(draw.address, len);
+                if draw_bitmap(ui, bytes, draw.row_bytes(), 3.0).clicked() {
+                    clicked = Some(draw.address);
+                }
+                let text = format!(
+                    "{:03X}: {}x{} from 0x{:04X} at ({}, {})",
+                    draw.pc,
+                    draw.row_bytes() * 8,
+                    draw.height,
+                    draw.address,
+                    draw.x,
+                    draw.y
+                );
+                ui.add(egui::Label::new(text).text_style(TextStyle::Monospace));
+                if draw.collided {
+                    ui.colored_label(Color32::RED, "collision");
+                }
+            });
+        }
+        clicked
+    }
+}
@@ -1,35 +1,398 @@
-use crate::cpu::Cpu;
+use crate::asm;
+use crate::cpu::{Cpu, CpuEvent, ExecutionState, SaveState, SaveStateError, TIMER_HZ};
+use crate::disasm::{self, Disassembly};
+use crate::movie::{Movie, MovieError, Player, Recorder};
+use crate::rewind::Rewind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Length of one emulated 60 Hz frame.
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / TIMER_HZ as u64);
+/// How far emulation may fall behind the wall clock before frames are dropped, at 1x speed.
+const MAX_LAG: Duration = Duration::from_millis(250);
+/// Wall-clock time fast-forward may spend emulating per update, leaving the rest for rendering.
+const FAST_FORWARD_BUDGET: Duration = Duration::from_millis(12);
+
+/// Range of the emulation speed multiplier.
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 16.0;
+
+/// RGBA colours of pixels lit in no plane, plane 1, plane 2 and both XO-CHIP planes.
+pub const PALETTE: [[u8; 4]; 4] = [
+    [0, 0, 0, 0],
+    [255, 255, 255, 255],
+    [255, 102, 0, 255],
+    [102, 34, 0, 255],
+];
+
+/// Number of save state slots per ROM.
+pub const SAVE_SLOTS: usize = 10;
+
+/// Emulated frames a tapped key stays down for, long enough for games that poll once a frame.
+pub const TAP_FRAMES: u8 = 6;
+
 pub struct World {
-    pub cpu: Cpu
+    pub cpu: Cpu,
+    /// Emulated time per unit of wall-clock time, between `MIN_SPEED` and `MAX_SPEED`.
+    pub speed: f32,
+    /// Run as fast as possible, skipping rendering of the frames in between.
+    pub fast_forward: bool,
+    /// Play the game backwards from the rewind buffer.
+    pub rewinding: bool,
+    /// One snapshot per emulated frame, for rewinding and stepping back.
+    pub rewind: Rewind,
+    /// Emulated time not yet caught up with.
+    accumulator: Duration,
+    last_update: Instant,
+    /// Save state slot the hotkeys save to and load from.
+    pub slot: usize,
+    /// Directory save states are kept in, one file per ROM and slot.
+    pub state_dir: PathBuf,
+    /// The ROM last loaded, so movies can restart it.
+    rom: Vec<u8>,
+    /// Disassembly of `rom`, for the debugger.
+    disassembly: Disassembly,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    /// Emulated frames left before each key pressed by `tap_key` is released.
+    taps: [u8; 16],
 }
 
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl World {
-    /// Create a new `World` instance that can draw a moving box.
     pub fn new() -> Self {
         Self {
-            cpu: Cpu::new()
+            cpu: Cpu::new(),
+            speed: 1.0,
+            fast_forward: false,
+            rewinding: false,
+            rewind: Rewind::default(),
+            accumulator: Duration::from_secs(0),
+            last_update: Instant::now(),
+            slot: 0,
+            state_dir: PathBuf::from("states"),
+            rom: Vec::new(),
+            disassembly: disasm::disassemble_rom(&[]),
+            recorder: None,
+            player: None,
+            taps: [0; 16],
+        }
+    }
+
+    /// Load a ROM, forgetting the rewind history and any movie of the previous one.
+    pub fn load_rom(&mut self, rom: &[u8]) {
+        self.cpu.load_rom(rom);
+        self.rom = rom.to_vec();
+        self.disassembly = disasm::disassemble_rom(rom);
+        self.rewind.clear();
+        self.recorder = None;
+        self.player = None;
+        self.taps = [0; 16];
+    }
+
+    /// The ROM last loaded.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn disassembly(&self) -> &Disassembly {
+        &self.disassembly
+    }
+
+    /// Disassemble the ROM's part of RAM again, to pick up changes made to it.
+    pub fn refresh_disassembly(&mut self) {
+        let start = asm::ORIGIN as usize;
+        let code = &self.cpu.memory()[start..start + self.rom.len()];
+        self.disassembly = disasm::disassemble(code, asm::ORIGIN, &[asm::ORIGIN]);
+    }
+
+    /// Assemble `line` into RAM at `address`, returning the number of bytes written.
+    /// The line may use the disassembly's labels. Not while a movie is active, as
+    /// the movie would no longer replay.
+    pub fn patch(&mut self, address: u16, line: &str) -> Result<usize, String> {
+        if self.movie_active() {
+            return Err("cannot edit code while a movie is active".to_string());
+        }
+        let bytes = asm::assemble_at(line, address, &self.disassembly.labels())
+            .map_err(|e| format!("column {}: {}", e.column, e.message))?;
+        if bytes.is_empty() {
+            return Err("nothing to assemble".to_string());
+        }
+        let written = self.cpu.write_memory(address, &bytes);
+        self.refresh_disassembly();
+        Ok(written)
+    }
+
+    /// Overwrite RAM at `addr` from the debugger, stopping at the end of RAM, and
+    /// return the number of bytes written. Not while a movie is active either.
+    pub fn write_memory(&mut self, addr: u16, bytes: &[u8]) -> Result<usize, String> {
+        if self.movie_active() {
+            return Err("cannot edit memory while a movie is active".to_string());
+        }
+        let written = self.cpu.write_memory(addr, bytes);
+        self.refresh_disassembly();
+        Ok(written)
+    }
+
+    /// Pass a key event on to the CPU, unless a movie is supplying the input.
+    pub fn handle_event(&mut self, event: CpuEvent) {
+        if self.player.is_some() {
+            return;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.event(event);
+        }
+        self.cpu.handle_event(event);
+    }
+
+    /// Press `key` for `TAP_FRAMES` emulated frames, unless something else is already
+    /// holding it down.
+    pub fn tap_key(&mut self, key: usize) {
+        if self.taps[key] > 0 {
+            self.taps[key] = TAP_FRAMES;
+        } else if !self.cpu.keyboard()[key] {
+            self.handle_event(CpuEvent::KeyPressed(key));
+            if self.cpu.keyboard()[key] {
+                self.taps[key] = TAP_FRAMES;
+            }
+        }
+    }
+
+    /// Release the keys still down from `tap_key`.
+    pub fn release_taps(&mut self) {
+        for key in 0..16 {
+            if self.taps[key] > 0 {
+                self.taps[key] = 0;
+                self.handle_event(CpuEvent::KeyReleased(key));
+            }
+        }
+    }
+
+    /// Restart the ROM and record a movie of the session.
+    pub fn start_recording(&mut self) {
+        let rom = std::mem::take(&mut self.rom);
+        self.load_rom(&rom);
+        self.recorder = Some(Recorder::new(&mut self.cpu, &rom));
+    }
+
+    /// Stop recording and return the movie, if one was being recorded.
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recorder.take().map(Recorder::finish)
+    }
+
+    /// Restart the ROM and play `movie` back on it.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        let rom = std::mem::take(&mut self.rom);
+        self.load_rom(&rom);
+        self.player = Some(Player::new(movie, &mut self.cpu, &rom)?);
+        Ok(())
+    }
+
+    pub fn stop_playback(&mut self) {
+        self.player = None;
+    }
+
+    /// Frames recorded so far, if recording.
+    pub fn recording(&self) -> Option<u64> {
+        self.recorder.as_ref().map(Recorder::frames)
+    }
+
+    /// Frames played back so far, if playing a movie.
+    pub fn playing(&self) -> Option<u64> {
+        self.player.as_ref().map(Player::frame)
+    }
+
+    /// Whether a movie is being recorded or played back. Going back in time is
+    /// not allowed then, as it would break the movie's timeline.
+    pub fn movie_active(&self) -> bool {
+        self.recorder.is_some() || self.player.is_some()
+    }
+
+    /// Run one frame, feeding or recording a movie, and record it in the rewind buffer.
+    fn run_frame(&mut self) {
+        if let Some(player) = &mut self.player {
+            match player.run_frame(&mut self.cpu) {
+                Ok(true) => {}
+                Ok(false) => {
+                    log::info!("Movie finished after {} frames", player.frame());
+                    self.player = None;
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    self.player = None;
+                    self.cpu.pause();
+                }
+            }
+        } else {
+            self.cpu.run_frame();
+            if let Some(recorder) = &mut self.recorder {
+                recorder.frame(&self.cpu);
+            }
+        }
+        self.rewind.push(&self.cpu);
+        for key in 0..16 {
+            if self.taps[key] > 0 {
+                self.taps[key] -= 1;
+                if self.taps[key] == 0 {
+                    self.handle_event(CpuEvent::KeyReleased(key));
+                }
+            }
         }
     }
 
-    /// Update the `World` internal state; bounce the box around the screen.
+    /// Catch emulation up with the wall clock, one fixed 60 Hz frame at a time.
     pub fn update(&mut self) {
-        self.cpu.clock_cycle()
+        let now = Instant::now();
+        let elapsed = now - self.last_update;
+        self.last_update = now;
+        let speed = self.speed.clamp(MIN_SPEED, MAX_SPEED);
+        if self.rewinding && !self.movie_active() {
+            // Play back at the same speed the game runs forwards.
+            self.accumulator += elapsed.mul_f32(speed);
+            self.accumulator = self.accumulator.min(MAX_LAG.mul_f32(speed));
+            while self.accumulator >= FRAME {
+                self.accumulator -= FRAME;
+                if !self.rewind.step_back_frame(&mut self.cpu) {
+                    self.accumulator = Duration::from_secs(0);
+                }
+            }
+            return;
+        }
+        if !self.cpu.is_active() {
+            self.accumulator = Duration::from_secs(0);
+            return;
+        }
+        if self.fast_forward {
+            self.accumulator = Duration::from_secs(0);
+            while self.cpu.is_active() && now.elapsed() < FAST_FORWARD_BUDGET {
+                self.run_frame();
+            }
+            return;
+        }
+        self.accumulator += elapsed.mul_f32(speed);
+        let max_lag = MAX_LAG.mul_f32(speed);
+        if self.accumulator > max_lag {
+            log::warn!("Emulation fell behind by {:?}, skipping ahead", self.accumulator);
+            self.accumulator = max_lag;
+        }
+        while self.accumulator >= FRAME {
+            self.run_frame();
+            self.accumulator -= FRAME;
+        }
+    }
+
+    /// Run a single frame while paused.
+    pub fn frame_advance(&mut self) {
+        if self.cpu.execution_state == ExecutionState::Paused {
+            self.cpu.resume();
+            self.run_frame();
+            self.cpu.pause();
+        }
+    }
+
+    /// Execute a single instruction while paused. Not while a movie is active,
+    /// as movies only record whole frames.
+    pub fn step(&mut self) {
+        if !self.movie_active() {
+            self.cpu.step();
+        }
+    }
+
+    /// Step, running a whole subroutine if the next instruction calls one. Not while
+    /// a movie is active, like `step`.
+    pub fn step_over(&mut self) {
+        if !self.movie_active() {
+            self.cpu.step_over();
+        }
+    }
+
+    /// Run until the current subroutine returns. Not while a movie is active.
+    pub fn step_out(&mut self) {
+        if !self.movie_active() {
+            self.cpu.step_out();
+        }
+    }
+
+    /// Run until the instruction at `addr` is about to execute. Not while a movie is active.
+    pub fn run_to(&mut self, addr: u16) {
+        if !self.movie_active() {
+            self.cpu.run_to(addr);
+        }
+    }
+
+    /// Go back one frame while paused.
+    pub fn step_back_frame(&mut self) {
+        if !self.movie_active() && self.rewind.step_back_frame(&mut self.cpu) {
+            self.cpu.pause();
+        }
+    }
+
+    /// Go back one instruction while paused.
+    pub fn step_back_instruction(&mut self) {
+        if !self.movie_active() {
+            self.rewind.step_back_instruction(&mut self.cpu);
+        }
+    }
+
+    fn slot_path(&self, slot: usize) -> PathBuf {
+        self.state_dir
+            .join(format!("{:016x}.{}.state", self.cpu.rom_hash(), slot))
+    }
+
+    /// Save the machine state to the current slot.
+    pub fn save_slot(&self) -> Result<(), SaveStateError> {
+        let path = self.slot_path(self.slot);
+        std::fs::create_dir_all(&self.state_dir)?;
+        std::fs::write(&path, self.cpu.save_state().to_bytes())?;
+        log::info!("Saved state to {}", path.display());
+        Ok(())
+    }
+
+    /// Restore the machine state from the current slot.
+    pub fn load_slot(&mut self) -> Result<(), SaveStateError> {
+        if self.movie_active() {
+            log::warn!("Cannot load a state while a movie is recorded or played back");
+            return Ok(());
+        }
+        let bytes = std::fs::read(self.slot_path(self.slot))?;
+        self.cpu.load_state(&SaveState::from_bytes(&bytes)?)?;
+        self.accumulator = Duration::from_secs(0);
+        self.rewind.clear();
+        Ok(())
+    }
+
+    /// Whether the given slot holds a state for the loaded ROM.
+    pub fn slot_used(&self, slot: usize) -> bool {
+        self.slot_path(slot).is_file()
+    }
+
+    /// Write the machine state as JSON, for bug reports.
+    pub fn export_json(&self, path: &Path) -> Result<(), SaveStateError> {
+        std::fs::write(path, self.cpu.save_state().to_json())?;
+        Ok(())
+    }
+
+    /// Size of the emulated screen in pixels, which changes with the SUPER-CHIP resolution.
+    pub fn screen_size(&self) -> (u32, u32) {
+        let (width, height) = self.cpu.screen_size();
+        (width as u32, height as u32)
     }
 
     /// Draw the `World` state to the frame buffer.
     ///
     /// Assumes the default texture format: `wgpu::TextureFormat::Rgba8UnormSrgb`
+    /// and a frame buffer sized to `World::screen_size`.
     pub fn draw(&self, frame: &mut [u8]) {
-        static WHITE_PIXEL: [u8; 4] = [255; 4];
+        let (width, _) = self.cpu.screen_size();
         for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let row = i / 64;
-            let col = i % 64;
-            if self.cpu.vram[col][row] {
-                pixel.copy_from_slice(&WHITE_PIXEL)
-            } else {
-                pixel.copy_from_slice(&[0,0,0,0])
-            }
+            let row = i / width;
+            let col = i % width;
+            pixel.copy_from_slice(&PALETTE[self.cpu.vram[col][row] as usize & 3]);
         }
     }
 }